@@ -0,0 +1,94 @@
+//! Compares the `SpatialGrid` broad phase with testing every pair of boxes,
+//! as the collision systems did before the grid, at 50, 500 and 2000
+//! entities. Each iteration finds every overlapping pair once; the grid
+//! version includes rebuilding the grid, as happens every frame.
+//!
+//! The game has no library target, so `main.rs` is pulled in as a module.
+
+#[allow(dead_code)]
+#[path = "../main.rs"]
+mod game;
+
+use bevy::prelude::*;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use game::{is_colliding, SpatialGrid};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+const ENTITY_COUNTS: [usize; 3] = [50, 500, 2000];
+const LEVEL_HEIGHT: f32 = 600.0;
+// Room per entity along the level, so crowding stays about the same as the
+// count grows, the way a longer level holds more enemies.
+const WIDTH_PER_ENTITY: f32 = 60.0;
+
+/// Boxes of enemy and obstacle size scattered over a level, from a fixed
+/// seed so every run measures the same layout.
+fn scatter_boxes(count: usize) -> Vec<(Vec3, Vec2)> {
+    let mut rng = StdRng::seed_from_u64(count as u64);
+    let width = count as f32 * WIDTH_PER_ENTITY;
+    (0..count)
+        .map(|_| {
+            let center = Vec3::new(
+                rng.gen_range(0.0..width),
+                rng.gen_range(0.0..LEVEL_HEIGHT),
+                0.0,
+            );
+            let half = Vec2::new(rng.gen_range(10.0..30.0), rng.gen_range(10.0..30.0));
+            (center, half)
+        })
+        .collect()
+}
+
+fn pairwise_overlaps(boxes: &[(Vec3, Vec2)]) -> usize {
+    let mut overlaps = 0;
+    for (i, &(pos_a, half_a)) in boxes.iter().enumerate() {
+        for &(pos_b, half_b) in &boxes[i + 1..] {
+            if is_colliding(pos_a, half_a, pos_b, half_b) {
+                overlaps += 1;
+            }
+        }
+    }
+    overlaps
+}
+
+fn grid_overlaps(grid: &mut SpatialGrid, boxes: &[(Vec3, Vec2)]) -> usize {
+    grid.clear();
+    for (i, &(center, half)) in boxes.iter().enumerate() {
+        grid.insert(Entity::from_raw(i as u32), center.truncate(), half);
+    }
+    let mut overlaps = 0;
+    for (i, &(pos_a, half_a)) in boxes.iter().enumerate() {
+        for entity in grid.query_region(pos_a.truncate(), half_a) {
+            let j = entity.index() as usize;
+            let (pos_b, half_b) = boxes[j];
+            if j > i && is_colliding(pos_a, half_a, pos_b, half_b) {
+                overlaps += 1;
+            }
+        }
+    }
+    overlaps
+}
+
+fn broad_phase(c: &mut Criterion) {
+    let mut group = c.benchmark_group("broad_phase");
+    for count in ENTITY_COUNTS {
+        let boxes = scatter_boxes(count);
+        let mut grid = SpatialGrid::default();
+        assert_eq!(
+            pairwise_overlaps(&boxes),
+            grid_overlaps(&mut grid, &boxes),
+            "the grid finds different pairs for {} entities",
+            count
+        );
+        group.bench_with_input(BenchmarkId::new("pairwise", count), &boxes, |b, boxes| {
+            b.iter(|| pairwise_overlaps(black_box(boxes)))
+        });
+        group.bench_with_input(BenchmarkId::new("grid", count), &boxes, |b, boxes| {
+            b.iter(|| grid_overlaps(&mut grid, black_box(boxes)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, broad_phase);
+criterion_main!(benches);
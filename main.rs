@@ -1,512 +1,11444 @@
+// Bevy system signatures routinely trip these lints.
+#![allow(clippy::type_complexity, clippy::too_many_arguments)]
+
 use bevy::app::AppExit;
+use bevy::asset::LoadState;
+use bevy::audio::{AudioPlugin, SpatialScale, Volume};
+use bevy::diagnostic::{DiagnosticsStore, FrameTimeDiagnosticsPlugin};
+use bevy::ecs::system::{EntityCommands, SystemParam};
+use bevy::input::gamepad::{GamepadRumbleIntensity, GamepadRumbleRequest};
+use bevy::input::mouse::MouseWheel;
 use bevy::prelude::*;
-use bevy::time::Time;
+use bevy::render::mesh::{Indices, PrimitiveTopology};
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+use bevy::sprite::MaterialMesh2dBundle;
+use bevy::time::{Stopwatch, Time};
 use bevy::window::{PrimaryWindow, Window};
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::path::PathBuf;
+use std::time::Duration;
 
 // Constants for gameplay tuning.
 const PLAYER_SIZE: Vec2 = Vec2::new(30.0, 30.0);
 const PLAYER_SPEED: f32 = 200.0;
 const PLAYER_JUMP_VELOCITY: f32 = 300.0;
+const DASH_SPEED: f32 = 500.0;
+const DASH_DURATION: f32 = 0.2;
+const DASH_COOLDOWN: f32 = 0.6;
+// The player counts as running above this horizontal speed, and each
+// animation pose is held this long.
+const RUN_ANIM_THRESHOLD: f32 = 20.0;
+const ANIM_FRAME_TIME: f32 = 0.1;
+// How far the left stick must tilt before it counts as a direction.
+const GAMEPAD_STICK_THRESHOLD: f32 = 0.3;
+const GAMEPAD_RUMBLE_DURATION: f32 = 0.2;
+const GAMEPAD_RUMBLE_STRENGTH: f32 = 0.6;
+// How quickly the camera closes in on its target: the fraction of the gap
+// left after one second is e^-rate.
+const CAMERA_FOLLOW_RATE: f32 = 8.0;
+// The camera doesn't move while its target stays inside this box around
+// the centre of the view.
+const CAMERA_DEADZONE: Vec2 = Vec2::new(120.0, 80.0);
+// Adaptive difficulty looks at the kills of the last `ADAPTIVE_WINDOW`
+// seconds every `ADAPTIVE_INTERVAL`. More than `ADAPTIVE_MAX_KILLS` speeds
+// enemies up by `ADAPTIVE_STEP`, fewer than `ADAPTIVE_MIN_KILLS` slows them
+// down, within `ADAPTIVE_SPEED_RANGE`.
+const ADAPTIVE_WINDOW: f64 = 30.0;
+const ADAPTIVE_INTERVAL: f32 = 5.0;
+const ADAPTIVE_MAX_KILLS: usize = 6;
+const ADAPTIVE_MIN_KILLS: usize = 2;
+const ADAPTIVE_STEP: f32 = 0.1;
+const ADAPTIVE_SPEED_RANGE: (f32, f32) = (0.5, 2.5);
+// Width of one repeat of a parallax layer, and how many repeats either side
+// of the middle one: enough to cover the widest window fully zoomed out.
+const PARALLAX_TILE_WIDTH: f32 = 480.0;
+const PARALLAX_TILES: i32 = 6;
+// Screen shake: trauma each event adds, how much drains per second, and
+// the camera offset in pixels at full trauma.
+const SHAKE_STOMP_TRAUMA: f32 = 0.2;
+const SHAKE_HURT_TRAUMA: f32 = 0.5;
+const SHAKE_DECAY: f32 = 2.0;
+const SHAKE_MAX_OFFSET: f32 = 12.0;
+// Gameplay freezes for this long after a kill. Kills close together add
+// up, but never past `HIT_STOP_MAX`.
+const HIT_STOP_DURATION: f32 = 0.05;
+const HIT_STOP_MAX: f32 = 0.1;
+// Stomping the last enemy slows the game to this speed for this many
+// real seconds.
+const SLOW_MOTION_SCALE: f32 = 0.2;
+const SLOW_MOTION_DURATION: f32 = 0.5;
+// Camera zoom, as an orthographic scale: its limits, how far one wheel
+// notch or key press moves it, and how quickly the camera eases to it.
+const ZOOM_RANGE: (f32, f32) = (0.5, 2.0);
+const ZOOM_STEP: f32 = 0.1;
+const ZOOM_RATE: f32 = 6.0;
+// Auto zoom pulls out by `AUTO_ZOOM_OUT` with more than
+// `AUTO_ZOOM_ENEMY_COUNT` enemies around, or with nothing to land on
+// within `AUTO_ZOOM_HEIGHT` below the player.
+const AUTO_ZOOM_OUT: f32 = 0.25;
+const AUTO_ZOOM_ENEMY_COUNT: usize = 6;
+const AUTO_ZOOM_HEIGHT: f32 = 150.0;
+// A new wave pulls the camera out by this fraction of its zoom over
+// `WAVE_PULSE_OUT` seconds, then eases back over `WAVE_PULSE_BACK`.
+const WAVE_PULSE_ZOOM: f32 = 0.15;
+const WAVE_PULSE_OUT: f32 = 0.4;
+const WAVE_PULSE_BACK: f32 = 0.6;
+// The debug readout refreshes this often, in seconds, to stay readable.
+const DEBUG_HUD_REFRESH: f32 = 0.25;
+// How long banners like "Wave N" take to fade out.
+const BANNER_DURATION: f32 = 2.0;
 const ENEMY_SIZE: Vec2 = Vec2::new(30.0, 30.0);
 const ENEMY_SPEED_RANGE: (f32, f32) = (50.0, 150.0);
 const OBSTACLE_SIZE: Vec2 = Vec2::new(40.0, 40.0);
+// How far either side of its spawn point a patrolling layout enemy walks.
+const ENEMY_PATROL_RANGE: f32 = 100.0;
+// Enemies that take more than one stomp carry a bar this far above their
+// centre while they have more than one hit left.
+const ENEMY_HEALTH_BAR_SIZE: Vec2 = Vec2::new(30.0, 4.0);
+const ENEMY_HEALTH_BAR_OFFSET: f32 = ENEMY_SIZE.y / 2.0 + 8.0;
+const ENEMY_HEALTH_BAR_BACKGROUND: Color = Color::rgb(0.2, 0.2, 0.2);
+const ENEMY_HEALTH_BAR_COLOR: Color = Color::RED;
+// Walkers and patrollers start chasing the player within this distance,
+// give up beyond `AGGRO_RELEASE_FACTOR` times it, and chase at
+// `CHASE_SPEED_FACTOR` times their own speed.
+const AGGRO_RANGE: f32 = 200.0;
+const AGGRO_RELEASE_FACTOR: f32 = 1.5;
+const CHASE_SPEED_FACTOR: f32 = 1.5;
+// An enemy on a patrol route moves on to its next waypoint this close to
+// the current one horizontally.
+const PATROL_WAYPOINT_REACH: f32 = 5.0;
+// Side of a cell in the grid chasing enemies plan their routes on, and how
+// many rows of it stack above the ground.
+const NAV_CELL_SIZE: f32 = OBSTACLE_SIZE.x;
+const NAV_ROWS: i32 = 8;
+// Route costs: a hop onto a higher cell costs more than walking, and a drop
+// more the further it falls.
+const NAV_WALK_COST: u32 = 2;
+const NAV_HOP_COST: u32 = 3;
+// Seconds between a chaser's route updates.
+const PATHFIND_INTERVAL: f32 = 0.5;
+// A chaser counts a waypoint as reached this close to it horizontally.
+const PATH_WAYPOINT_REACH: f32 = 4.0;
+// Upward speed of a chaser's hop, enough to clear one nav cell.
+const CHASER_HOP_VELOCITY: f32 = 260.0;
+// Random levels send in at most this many enemies as one formation, this
+// far apart.
+const FORMATION_SIZE: usize = 5;
+const FORMATION_SPACING: f32 = 50.0;
+// Randomly placed enemies never start closer than this to the player.
+const ENEMY_SPAWN_CLEARANCE: f32 = 150.0;
+// Endless mode sends in a wave this often, or as soon as the last enemy is
+// gone.
+const WAVE_INTERVAL: f32 = 15.0;
+// The first wave's size. Each wave after it brings one more, up to the cap.
+const WAVE_BASE_SIZE: usize = 3;
+const WAVE_MAX_SIZE: usize = 12;
+// Each wave walks this much faster than the first, as a fraction of its speed.
+const WAVE_SPEED_RAMP: f32 = 0.1;
+// Wave enemies drop in from this high above the ground.
+const WAVE_DROP_HEIGHT: f32 = 200.0;
+// Extra room kept between a random enemy and the sides of any obstacle.
+const ENEMY_OBSTACLE_CLEARANCE: f32 = OBSTACLE_SIZE.x;
+// Random obstacles keep at least this far from the player's spawn point.
+const OBSTACLE_SPAWN_CLEARANCE: f32 = 100.0;
+// Attempts at finding a free spot before giving up on one spawn.
+const SPAWN_ATTEMPTS: usize = 20;
+// Side of a square cell in the collision broad phase: two of the widest
+// box, so most enemies and obstacles fall in no more than four cells.
+const SPATIAL_CELL_SIZE: f32 = ENEMY_SIZE.x.max(OBSTACLE_SIZE.x) * 2.0;
+// Breakable blocks float low enough for a jumping player's head to reach.
+const BREAKABLE_HEIGHT: f32 = 100.0;
+const BREAKABLE_SCORE: i32 = 20;
+const BREAKABLE_COIN_CHANCE: f64 = 0.3;
+// Share of random obstacles that a dash smashes through.
+const FRAGILE_CHANCE: f64 = 0.3;
+const FRAGILE_SCORE: i32 = 30;
+const DEBRIS_SIZE: f32 = 8.0;
+const DEBRIS_LIFETIME: f32 = 0.5;
+// Landing faster than `DUST_MIN_FALL_SPEED` kicks up a puff of dust.
+const DUST_MIN_FALL_SPEED: f32 = 100.0;
+const DUST_SIZE: f32 = 5.0;
+const DUST_LIFETIME: f32 = 0.35;
+const DUST_COLOR: Color = Color::rgba(0.8, 0.7, 0.5, 0.8);
+// Share of random obstacles that are conveyor belts instead.
+const CONVEYOR_CHANCE: f64 = 0.2;
+const CONVEYOR_SIZE: Vec2 = Vec2::new(80.0, 20.0);
+const CONVEYOR_PUSH_RANGE: (f32, f32) = (60.0, 100.0);
+const CONVEYOR_STRIPE_SPACING: f32 = 16.0;
+// How close an entity's feet must be to a belt's top to ride it.
+const CONVEYOR_CONTACT_TOLERANCE: f32 = 2.0;
+// One random obstacle per level is rubbery and bounces the player off it,
+// keeping this share of their speed.
+const BOUNCY_RESTITUTION: f32 = 0.8;
+// Bounces slower than this are dropped, so the player settles.
+const BOUNCE_MIN_SPEED: f32 = 80.0;
+// Rocks drop from the top of the screen every few seconds, after a warning.
+const HAZARD_INTERVAL_RANGE: (f32, f32) = (4.0, 7.0);
+const HAZARD_WARNING_DURATION: f32 = 0.8;
+const HAZARD_SIZE: Vec2 = Vec2::new(24.0, 24.0);
+const HAZARD_DAMAGE: f32 = 1.0;
+// Chance a random breakable block gets a ladder up to its top.
+const LADDER_CHANCE: f64 = 0.5;
+const LADDER_WIDTH: f32 = 16.0;
+const CLIMB_SPEED: f32 = 120.0;
+// Random pools of water, and how they change the player's movement.
+const WATER_SIZE: Vec2 = Vec2::new(200.0, 80.0);
+const WATER_SPAWN_CLEARANCE: f32 = 200.0;
+const WATER_GRAVITY_SCALE: f32 = 0.3;
+const WATER_SPEED_SCALE: f32 = 0.6;
+const WATER_JUMP_SCALE: f32 = 0.7;
+const WATER_ENEMY_SPEED_SCALE: f32 = 0.5;
+const SWIM_STROKE_VELOCITY: f32 = 120.0;
+const SWIM_STROKE_COOLDOWN: f32 = 0.4;
+const COIN_RADIUS: f32 = 8.0;
+const COIN_SCORE: i32 = 10;
+// Coins collected less than `COIN_PITCH_WINDOW` seconds apart each chime
+// `COIN_PITCH_STEP` higher, up to `COIN_PITCH_MAX` times normal speed.
+const COIN_PITCH_WINDOW: f32 = 1.0;
+const COIN_PITCH_STEP: f32 = 0.05;
+const COIN_PITCH_MAX: f32 = 2.0;
+const COIN_COUNT_RANGE: (usize, usize) = (10, 16);
+const COIN_BOB_AMPLITUDE: f32 = 3.0;
+const COIN_BOB_SPEED: f32 = 4.0;
+// A coin a full jump up is still within reach of the player's head.
+const COIN_JUMP_HEIGHT: f32 = 70.0;
 const GROUND_HEIGHT: f32 = 20.0;
+// Width of a randomly generated flat level, in window widths.
+const LEVEL_WIDTH_SCREENS: f32 = 3.0;
+const GAP_WIDTH_RANGE: (f32, f32) = (70.0, 120.0);
+// Solid ground between one gap and the next.
+const GAP_SPACING_RANGE: (f32, f32) = (350.0, 700.0);
+// How far below the ground something in a gap falls before it is gone.
+const GAP_FALL_DEPTH: f32 = 200.0;
+// The invisible fall death zone reaches this far down from its top.
+const FALL_ZONE_DEPTH: f32 = 1000.0;
 const GRAVITY_FORCE: f32 = -500.0;
+// Physics steps per second, independent of the frame rate.
+const PHYSICS_HZ: f64 = 64.0;
+// Longest step the player's movement takes before checking for contact:
+// half the thinnest collider, the vertical level's platforms.
+const SUBSTEP_LENGTH: f32 = PLATFORM_SIZE.y / 2.0;
+const MAX_LIVES: i32 = 3;
+const GAME_OVER_DELAY: f32 = 1.5;
+const FADE_IN_DURATION: f32 = 1.0;
+const STAR_REVEAL_INTERVAL: f32 = 0.3;
+const STAR_SIZE: f32 = 40.0;
+// Score needed for the second and third star when the level file has none.
+const DEFAULT_STAR_THRESHOLDS: (i32, i32) = (300, 600);
+const RESPAWN_INVULNERABILITY: f32 = 1.5;
+// A dying player spins, shrinks and drifts up for this long before the
+// life is lost.
+const DEATH_ANIM_DURATION: f32 = 0.6;
+const DEATH_SPIN_SPEED: f32 = 12.0;
+const DEATH_SHRINK_PER_STEP: f32 = 0.95;
+const DEATH_RISE_SPEED: f32 = 60.0;
+// Each life has this much health; an enemy's side hit takes one point.
+const PLAYER_MAX_HEALTH: f32 = 3.0;
+const ENEMY_CONTACT_DAMAGE: f32 = 1.0;
+const HIT_INVULNERABILITY: f32 = 1.0;
+// A hurt player flashes red for this long. Sprite colors multiply the
+// texture, so the flash pushes red over-bright and cuts the other channels.
+const HIT_FLASH_DURATION: f32 = 0.15;
+const HIT_FLASH_COLOR: Color = Color::rgb(3.0, 0.3, 0.3);
+// In co-op, player 2 starts this far right of player 1, tinted this color.
+const PLAYER2_OFFSET: f32 = 100.0;
+const PLAYER2_TINT: Color = Color::rgb(0.6, 0.8, 1.0);
+// The player's shadow on the ground shrinks and fades to `SHADOW_MIN_SCALE`
+// of its size and alpha at the top of a full jump.
+const SHADOW_SIZE: Vec2 = Vec2::new(28.0, 8.0);
+const SHADOW_ALPHA: f32 = 0.4;
+const SHADOW_MIN_SCALE: f32 = 0.3;
+const MAX_JUMP_HEIGHT: f32 = PLAYER_JUMP_VELOCITY * PLAYER_JUMP_VELOCITY / (-2.0 * GRAVITY_FORCE);
+// A hit reddens the screen's edges, this wide, starting at this alpha and
+// fading out. Stacked rings of border make the red thicken toward the edge.
+const DAMAGE_VIGNETTE_ALPHA: f32 = 0.35;
+const DAMAGE_VIGNETTE_DURATION: f32 = 0.4;
+const DAMAGE_VIGNETTE_WIDTH: f32 = 80.0;
+const DAMAGE_VIGNETTE_RINGS: usize = 4;
+// Winning whites out the screen, fading over this long to the win screen.
+const WIN_FLASH_DURATION: f32 = 0.5;
+// The win and game-over buttons appear once the jingle ends, or after
+// this many seconds if it never plays.
+const JINGLE_TIMEOUT: f32 = 5.0;
+// Enemy footsteps fade out and pan fully to one side this far from the
+// player, and play at this share of the SFX volume up close.
+const ENEMY_SOUND_RANGE: f32 = 600.0;
+const ENEMY_WALK_VOLUME: f32 = 0.4;
+const HEALTH_BAR_SIZE: Vec2 = Vec2::new(150.0, 20.0);
+// The minimap is a thin strip along the top edge of the screen, with a
+// fixed pool of dots for whatever is in the level. Anything past the pool
+// goes unshown.
+const MINIMAP_HEIGHT: f32 = 6.0;
+const MINIMAP_DOT_SIZE: f32 = 6.0;
+const MINIMAP_POOL_SIZE: usize = 96;
+const MINIMAP_BACKGROUND: Color = Color::rgba(0.0, 0.0, 0.0, 0.5);
+const MINIMAP_OBSTACLE_COLOR: Color = Color::GRAY;
+const MINIMAP_ENEMY_COLOR: Color = Color::RED;
+const MINIMAP_GOAL_COLOR: Color = Color::GREEN;
+const MINIMAP_PLAYER_COLOR: Color = Color::WHITE;
+// Debug builds outline hitboxes with the overlay on and draw velocities as
+// arrows 100 times shorter than the velocity itself.
+#[cfg(debug_assertions)]
+const DEBUG_VELOCITY_SCALE: f32 = 0.01;
+#[cfg(debug_assertions)]
+const DEBUG_ARROW_HEAD: f32 = 6.0;
+#[cfg(debug_assertions)]
+const DEBUG_LABEL_SIZE: f32 = 12.0;
+// At or below this share of health the bar turns red and pulses.
+const LOW_HEALTH_FRACTION: f32 = 1.0 / 3.0;
+const HEALTH_PULSE_SPEED: f32 = 4.0;
+// The bar's border flashes red on damage and green on healing, fading
+// back to white over this long.
+const HEALTH_FLASH_DURATION: f32 = 0.4;
+// Small player icons for the remaining lives sit in a row under the
+// health bar. A lost one shrinks away over `LIFE_ICON_SHRINK_DURATION`.
+const LIFE_ICON_SIZE: f32 = 24.0;
+const LIFE_ICON_SPACING: f32 = 30.0;
+const LIVES_TOP: f32 = 40.0;
+const LIFE_ICON_SHRINK_DURATION: f32 = 0.3;
+// Shown instead of the icon if `player.png` fails to load.
+const LIFE_ICON_FALLBACK_COLOR: Color = Color::RED;
+const LIFE_SHARD_COUNT: usize = 8;
+const LIFE_SHARD_SIZE: f32 = 6.0;
+const LIFE_SHARD_SPEED: f32 = 120.0;
+const LIFE_SHARD_LIFETIME: f32 = 0.6;
+// Stomps this close together in seconds build up a combo.
+const COMBO_WINDOW: f32 = 2.0;
+// The combo multiplier in the HUD swells by `COMBO_PULSE_SCALE` when it
+// goes up, shrinking back over `COMBO_PULSE_DURATION`, and shakes by up to
+// `COMBO_SHAKE_AMOUNT` pixels in the last `COMBO_SHAKE_FRACTION` of the
+// window.
+const COMBO_FONT_SIZE: f32 = 24.0;
+const COMBO_BAR_SIZE: Vec2 = Vec2::new(60.0, 4.0);
+const COMBO_PULSE_SCALE: f32 = 0.5;
+const COMBO_PULSE_DURATION: f32 = 0.25;
+const COMBO_SHAKE_FRACTION: f32 = 0.25;
+const COMBO_SHAKE_AMOUNT: f32 = 2.0;
+const COMBO_SHAKE_SPEED: f32 = 60.0;
+const STOMP_SCORE: i32 = 100;
+// Stomp sounds play at a random speed in this range; combos longer than
+// `STOMP_PITCH_COMBO` add `STOMP_PITCH_STEP` per stomp on top.
+const STOMP_PITCH_RANGE: (f32, f32) = (0.9, 1.1);
+const STOMP_PITCH_COMBO: u32 = 3;
+const STOMP_PITCH_STEP: f32 = 0.05;
+// Each stomp in a chain bounces this much higher than a plain jump.
+const CHAIN_BOUNCE_STEP: f32 = 0.2;
+const MAX_CHAIN_BOUNCE: u8 = 10;
+// A chain stomp earns this times the chain length squared on top.
+const CHAIN_BONUS_SCORE: i32 = 50;
+// Score popups rise this far from where points were earned as they fade
+// out. Popups earned on the same spot stack upward, and past the cap the
+// oldest makes way for the newest.
+const FLOATING_TEXT_DURATION: f32 = 0.8;
+const FLOATING_TEXT_RISE: f32 = 30.0;
+const FLOATING_TEXT_SIZE: f32 = 20.0;
+const MAX_FLOATING_TEXTS: usize = 20;
+// Hot streak: each kill raises the score multiplier, which drains while idle.
+const ACTIVITY_KILL_BOOST: f32 = 0.5;
+const ACTIVITY_DECAY_RATE: f32 = 0.5;
+const ACTIVITY_MAX: f32 = 4.0;
+const ACTIVITY_BAR_SIZE: Vec2 = Vec2::new(100.0, 10.0);
+// Seconds the win screen stays up before moving on to the next level.
+const LEVEL_ADVANCE_DELAY: f32 = 5.0;
+// Control hints shown one after another at the start of level 1.
+const TUTORIAL_HINTS: [&str; 1] = ["Jump on enemies to defeat them"];
+const TUTORIAL_HINT_DURATION: f32 = 3.0;
+const TUTORIAL_FADE_DURATION: f32 = 0.5;
+// The controls card shows for this long at the start of a run, or until
+// the player has both moved and jumped, then fades out.
+const CONTROLS_HINT_DURATION: f32 = 5.0;
+// How long "CLEAR!" shows after the last enemy falls before the level ends.
+const ALL_CLEAR_DELAY: f32 = 1.0;
+// Countdown in timed mode unless the level file sets its own `time_limit`.
+const TIME_LIMIT: f32 = 60.0;
+// Fractions of the time limit left when the HUD timer turns yellow, then red.
+const TIMER_WARNING_FRACTION: f32 = 0.5;
+const TIMER_DANGER_FRACTION: f32 = 0.25;
+// Time attack builds every level from this seed, so each run of a level
+// faces the same enemies.
+const TIME_ATTACK_SEED: u64 = 1337;
+// Seconds added to the time-attack clock for each death.
+const TIME_ATTACK_DEATH_PENALTY: f32 = 3.0;
+// Survival is won by lasting this many seconds, and pays this much for each
+// life left at the end.
+const SURVIVAL_DURATION: f32 = 90.0;
+const SURVIVAL_LIFE_BONUS: i32 = 500;
+
+// Constants for the vertical level mode.
+const LEVEL_HEIGHT: f32 = 2000.0;
+const PLATFORM_SIZE: Vec2 = Vec2::new(160.0, 16.0);
+// Feet this close to a slope's surface are snapped onto it, so walking
+// over it doesn't bounce.
+const SLOPE_SNAP: f32 = 8.0;
+// Fraction of the normal speed kept when walking uphill.
+const SLOPE_UPHILL_SPEED: f32 = 0.8;
+// The player and chasers count as grounded when a ray cast down from just above their
+// feet hits something within this distance.
+const GROUND_PROBE_LIFT: f32 = 1.0;
+const GROUND_PROBE_DISTANCE: f32 = 2.0;
+const PLATFORM_SPACING: f32 = 70.0;
+const PLATFORM_MAX_STEP_X: f32 = 220.0;
+const GOAL_SIZE: Vec2 = Vec2::new(20.0, 50.0);
+const GOAL_WAVE_SPEED: f32 = 6.0;
+const GOAL_WAVE_AMOUNT: f32 = 0.15;
+// Reaching the goal faster than this earns a time bonus.
+const GOAL_PAR_TIME: f32 = 60.0;
+const GOAL_TIME_BONUS_PER_SECOND: f32 = 10.0;
+const GOAL_LIFE_BONUS: i32 = 100;
+const CHECKPOINT_SIZE: Vec2 = Vec2::new(10.0, 40.0);
+const CHECKPOINT_EVERY: usize = 8;
+const CHECKPOINT_SCORE: i32 = 25;
+// Enemies this close to a checkpoint are cleared when the player respawns there.
+const CHECKPOINT_CLEAR_RADIUS: f32 = 150.0;
+
+// Constants for the key and door objective.
+const KEY_SIZE: Vec2 = Vec2::new(24.0, 12.0);
+const KEY_HEIGHT_RANGE: (f32, f32) = (50.0, 80.0);
+const DOOR_SIZE: Vec2 = Vec2::new(40.0, 80.0);
+const DOOR_BONUS: i32 = 500;
+
+const LEVEL_DIR: &str = "assets/levels";
+const SETTINGS_PATH: &str = "settings.ron";
+const KEY_BINDINGS_FILE: &str = "keybindings.ron";
+const RESOLUTIONS: [(u32, u32); 3] = [(1280, 720), (1600, 900), (1920, 1080)];
+const SLIDER_WIDTH: f32 = 300.0;
+const SLIDER_THUMB_SIZE: Vec2 = Vec2::new(16.0, 24.0);
+// Card colors on the level select screen, cycled by level number.
+const LEVEL_PREVIEW_COLORS: [Color; 4] = [
+    Color::rgb(0.3, 0.6, 0.9),
+    Color::rgb(0.6, 0.5, 0.4),
+    Color::rgb(0.4, 0.7, 0.4),
+    Color::rgb(0.7, 0.4, 0.6),
+];
+const LOCKED_CARD: Color = Color::rgb(0.3, 0.3, 0.3);
+const CARD_STAR_SIZE: f32 = 16.0;
+
+// Menu button colors.
+const NORMAL_BUTTON: Color = Color::rgb(0.15, 0.15, 0.15);
+const HOVERED_BUTTON: Color = Color::rgb(0.25, 0.25, 0.25);
+const PRESSED_BUTTON: Color = Color::rgb(0.35, 0.75, 0.35);
+
+#[derive(States, Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum GameState {
+    #[default]
+    MainMenu,
+    Settings,
+    Controls,
+    Stats,
+    LevelSelect,
+    Playing,
+    Paused,
+    GameOver,
+    Win,
+    Victory,
+}
 
 #[derive(Resource)]
 pub struct Gravity(pub f32);
 
+/// Speed caps in pixels per second, applied to everything with a
+/// `Velocity` before it moves, so stacked boosts can't outrun collision.
+#[derive(Resource, Clone, Debug)]
+pub struct VelocityLimits {
+    pub max_horizontal: f32,
+    pub max_fall: f32,
+    pub max_rise: f32,
+}
+
+impl Default for VelocityLimits {
+    fn default() -> Self {
+        VelocityLimits {
+            max_horizontal: 600.0,
+            max_fall: 800.0,
+            max_rise: 1000.0,
+        }
+    }
+}
+
+/// Longest frame, in seconds, the game clock advances by. A hitch such as
+/// dragging the window runs at most this much physics instead of catching
+/// up on the whole stall at once.
 #[derive(Resource)]
-pub struct Score(i32);
+pub struct MaxDelta(pub f32);
 
-#[derive(Component)]
-struct Player;
+impl Default for MaxDelta {
+    fn default() -> Self {
+        MaxDelta(1.0 / 20.0)
+    }
+}
 
-#[derive(Component)]
-struct Enemy;
+/// How a run is played, picked on the main menu. Story works through the
+/// levels; endless sends waves of enemies until the last life is lost; time
+/// attack races to clear each level's fixed set of enemies, with unlimited
+/// lives; survival sends waves too, and is won by outlasting the clock.
+#[derive(Resource, Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GameMode {
+    #[default]
+    Story,
+    Endless,
+    TimeAttack,
+    Survival,
+}
 
-#[derive(Component)]
-struct Obstacle;
+impl GameMode {
+    /// True for the modes where enemies keep coming in waves.
+    fn sends_waves(self) -> bool {
+        matches!(self, GameMode::Endless | GameMode::Survival)
+    }
+}
 
-#[derive(Component)]
-struct Ground;
+/// Picked on the main menu, and turned into `DifficultyMultipliers` when a
+/// level starts.
+#[derive(Resource, Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Difficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+}
 
-#[derive(Component)]
-struct ScoreText;
+impl Difficulty {
+    const ALL: [Difficulty; 3] = [Difficulty::Easy, Difficulty::Normal, Difficulty::Hard];
 
-#[derive(Component, Deref, DerefMut)]
-struct Velocity(Vec2);
+    fn label(self) -> &'static str {
+        match self {
+            Difficulty::Easy => "Easy",
+            Difficulty::Normal => "Normal",
+            Difficulty::Hard => "Hard",
+        }
+    }
+
+    fn multipliers(self) -> DifficultyMultipliers {
+        let (enemy_speed, enemy_count, score_multiplier, player_speed) = match self {
+            Difficulty::Easy => (0.7, 0.5, 1.5, 1.2),
+            Difficulty::Normal => (1.0, 1.0, 1.0, 1.0),
+            Difficulty::Hard => (1.5, 2.0, 0.8, 0.9),
+        };
+        DifficultyMultipliers {
+            enemy_speed,
+            enemy_count,
+            score_multiplier,
+            player_speed,
+        }
+    }
+}
+
+/// What the current difficulty scales: enemy speeds, how many random
+/// enemies spawn and how often rocks fall, the points everything is worth,
+/// and the player's speed.
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct DifficultyMultipliers {
+    pub enemy_speed: f32,
+    pub enemy_count: f32,
+    pub score_multiplier: f32,
+    pub player_speed: f32,
+}
 
+impl Default for DifficultyMultipliers {
+    fn default() -> Self {
+        Difficulty::Normal.multipliers()
+    }
+}
+
+impl DifficultyMultipliers {
+    /// `base` points scaled for the difficulty.
+    fn points(&self, base: i32) -> i32 {
+        (base as f32 * self.score_multiplier).round() as i32
+    }
+}
+
+/// Present once a difficulty has been picked on the main menu, which turns
+/// adaptive difficulty off.
 #[derive(Resource)]
-pub struct GroundData {
-    pub center_y: f32,
-    pub top_y: f32,
-    pub height: f32,
+pub struct DifficultyChosen;
+
+/// How the player has been doing lately, for adaptive difficulty. Times are
+/// seconds since startup.
+#[derive(Resource)]
+pub struct PerformanceTracker {
+    pub kills_last_30s: VecDeque<f64>,
+    pub avg_time_between_deaths: f32,
+    deaths: u32,
+    last_death: f64,
+    timer: Timer,
 }
 
-fn main() {
-    App::new()
-        .add_plugins(DefaultPlugins)
-        .insert_resource(Gravity(GRAVITY_FORCE))
-        .insert_resource(Score(0))
-        .insert_resource(GroundData {
-            center_y: 0.0,
-            top_y: GROUND_HEIGHT / 2.0,
-            height: GROUND_HEIGHT,
-        })
-        .add_systems(Startup, setup)
-        .add_systems(Startup, spawn_enemies.after(setup))
-        .add_systems(Startup, spawn_obstacles.after(setup))
-        .add_systems(Update, player_input_system)
-        .add_systems(Update, apply_gravity_system)
-        .add_systems(Update, movement_system)
-        .add_systems(Update, player_wrap_system) // wrap-around for player
-        .add_systems(Update, enemy_wrap_system)  // wrap-around for enemies
-        // NEW: Enemy-obstacle collision system
-        .add_systems(Update, enemy_obstacle_collision_system)
-        .add_systems(Update, collision_system)
-        .add_systems(Update, enemy_collision_system)
-        .add_systems(Update, obstacle_collision_system)
-        .add_systems(Update, update_score_system)
-        .add_systems(Update, check_end_game_system)
-        .run();
+impl Default for PerformanceTracker {
+    fn default() -> Self {
+        PerformanceTracker {
+            kills_last_30s: VecDeque::new(),
+            avg_time_between_deaths: 0.0,
+            deaths: 0,
+            last_death: 0.0,
+            timer: Timer::from_seconds(ADAPTIVE_INTERVAL, TimerMode::Repeating),
+        }
+    }
 }
 
+impl PerformanceTracker {
+    fn record_kill(&mut self, now: f64) {
+        self.kills_last_30s.push_back(now);
+    }
 
-//
-// SETUP SYSTEMS
-//
+    fn record_death(&mut self, now: f64) {
+        let gap = (now - self.last_death) as f32;
+        self.deaths += 1;
+        self.avg_time_between_deaths += (gap - self.avg_time_between_deaths) / self.deaths as f32;
+        self.last_death = now;
+    }
+}
 
-/// Initializes the camera, ground, UI text, and player.
-fn setup(
-    mut commands: Commands,
-    asset_server: Res<AssetServer>,
-    window_query: Query<&Window, With<PrimaryWindow>>,
-) {
-    let window = window_query.single();
+/// Camera zoom as an orthographic scale, where below 1 is closer in.
+/// `chosen` is the player's pick, kept in `Settings`; with `auto` on the
+/// camera pulls further out when the action spreads. `eased` is where the
+/// scale has got to on its way there, which a wave's `pulse` multiplies
+/// while it plays.
+#[derive(Resource)]
+pub struct CameraZoom {
+    pub chosen: f32,
+    pub auto: bool,
+    eased: f32,
+    pulse: Option<Timer>,
+}
 
-    // Calculate ground positions.
-    let ground_center_y = 0.0;
-    let ground_top_y = ground_center_y + GROUND_HEIGHT / 2.0;
+/// How the camera follows its target. It holds still while the target is
+/// inside the `deadzone` box around the centre of the view, then eases
+/// after it at `follow_rate` just far enough to keep it at the box's edge.
+#[derive(Resource)]
+pub struct CameraConfig {
+    pub deadzone: Vec2,
+    pub follow_rate: f32,
+}
 
-    // Update the GroundData resource.
-    commands.insert_resource(GroundData {
-        center_y: ground_center_y,
-        top_y: ground_top_y,
-        height: GROUND_HEIGHT,
-    });
+impl Default for CameraConfig {
+    fn default() -> Self {
+        CameraConfig {
+            deadzone: CAMERA_DEADZONE,
+            follow_rate: CAMERA_FOLLOW_RATE,
+        }
+    }
+}
 
-    // Spawn the 2D camera.
-    commands.spawn(Camera2dBundle::default());
+/// Whether the F3 debug overlay is showing.
+#[derive(Resource, Default, PartialEq, Eq)]
+pub struct DebugOverlay(pub bool);
 
-    // Spawn the ground.
-    commands.spawn((
-        SpriteBundle {
-            sprite: Sprite {
-                color: Color::rgb(0.2, 0.8, 0.2),
-                custom_size: Some(Vec2::new(window.width(), GROUND_HEIGHT)),
-                ..default()
-            },
-            transform: Transform::from_translation(Vec3::new(0.0, 0.0, 0.0)),
-            ..default()
-        },
-        Ground,
-    ));
+/// Text over a player or enemy showing its velocity while the debug overlay
+/// is on.
+#[cfg(debug_assertions)]
+#[derive(Component)]
+struct DebugLabel {
+    target: Entity,
+}
 
-    // Spawn score UI.
-    commands.spawn((
-        TextBundle {
-            text: Text::from_section(
-                "Score: 0",
-                TextStyle {
-                    font: asset_server.load("fonts/FiraSans-Bold.ttf"),
-                    font_size: 40.0,
-                    color: Color::WHITE,
-                },
-            ),
-            style: Style {
-                position_type: PositionType::Absolute,
-                top: Val::Px(10.0),
-                right: Val::Px(10.0),
-                ..default()
-            },
-            ..default()
-        },
-        ScoreText,
-    ));
+/// Whether M has hidden the minimap.
+#[derive(Resource, Default)]
+pub struct MinimapHidden(pub bool);
 
-    // Spawn the player so its bottom touches the ground.
-    // Center is ground top + half the player height.
-    let player_y = ground_top_y + PLAYER_SIZE.y / 2.0;
-    commands.spawn((
-        SpriteBundle {
-            texture: asset_server.load("player.png"),
-            sprite: Sprite {
-                custom_size: Some(PLAYER_SIZE),
-                ..default()
-            },
-            transform: Transform::from_translation(Vec3::new(0.0, player_y, 0.0)),
-            ..default()
-        },
-        Player,
-        Velocity(Vec2::ZERO),
-    ));
+/// The minimap strip across the top of the screen. It's only shown on
+/// levels wider than one screen.
+#[derive(Component)]
+struct Minimap;
+
+/// One of the minimap's pooled dots, moved onto a player, enemy, obstacle
+/// or goal each frame and hidden when there's nothing left to mark.
+#[derive(Component)]
+struct MinimapDot;
+
+/// The debug overlay's readout of frame rate and entity counts, spawned
+/// only while the overlay is on. It refreshes each time `refresh` runs out.
+#[derive(Component)]
+struct DebugHud {
+    refresh: Timer,
 }
 
-/// Spawns a random number of enemies with random horizontal velocities.
-fn spawn_enemies(
-    mut commands: Commands,
-    asset_server: Res<AssetServer>,
-    ground_data: Res<GroundData>,
-    window_query: Query<&Window, With<PrimaryWindow>>,
-) {
-    let window = window_query.single();
-    let mut rng = rand::thread_rng();
-    let enemy_count = rng.gen_range(2..5);
-    let enemy_y = ground_data.top_y + ENEMY_SIZE.y / 2.0;
+/// A moment of frozen gameplay after a kill, for impact. It runs on real
+/// time, since the virtual clock it stops can't time it.
+#[derive(Resource)]
+pub struct HitStop(Timer);
 
-    for _ in 0..enemy_count {
-        let x = rng.gen_range(-window.width() / 2.0..window.width() / 2.0);
-        let enemy_pos = Vec3::new(x, enemy_y, 0.0);
+impl Default for HitStop {
+    fn default() -> Self {
+        HitStop(Timer::from_seconds(0.0, TimerMode::Once))
+    }
+}
 
-        // Random horizontal speed and direction.
-        let speed = rng.gen_range(ENEMY_SPEED_RANGE.0..ENEMY_SPEED_RANGE.1);
-        let direction = if rng.gen_bool(0.5) { 1.0 } else { -1.0 };
+impl HitStop {
+    /// Adds `seconds` to what's left of the freeze, up to `HIT_STOP_MAX`.
+    pub fn add(&mut self, seconds: f32) {
+        let remaining = self.0.remaining_secs();
+        self.0 = Timer::from_seconds((remaining + seconds).min(HIT_STOP_MAX), TimerMode::Once);
+    }
+}
 
-        commands.spawn((
-            SpriteBundle {
-                texture: asset_server.load("enemy.png"),
-                sprite: Sprite {
-                    custom_size: Some(ENEMY_SIZE),
-                    ..default()
-                },
-                transform: Transform::from_translation(enemy_pos),
-                ..default()
-            },
-            Enemy,
-            Velocity(Vec2::new(direction * speed, 0.0)),
-        ));
+/// Bullet time after the last enemy goes down: the game runs at `scale`
+/// speed until `timer`, on real time like `HitStop`, runs out.
+#[derive(Resource)]
+pub struct SlowMotion {
+    timer: Timer,
+    scale: f32,
+}
+
+impl Default for SlowMotion {
+    fn default() -> Self {
+        SlowMotion {
+            timer: Timer::from_seconds(0.0, TimerMode::Once),
+            scale: 1.0,
+        }
     }
 }
 
-/// Spawns a random number of obstacles at ground level.
-fn spawn_obstacles(
-    mut commands: Commands,
-    ground_data: Res<GroundData>,
-    window_query: Query<&Window, With<PrimaryWindow>>,
-) {
-    let window = window_query.single();
-    let mut rng = rand::thread_rng();
-    let obstacle_count = rng.gen_range(3..7);
-    let obstacle_y = ground_data.top_y + OBSTACLE_SIZE.y / 2.0;
+impl SlowMotion {
+    fn start(&mut self) {
+        self.timer = Timer::from_seconds(SLOW_MOTION_DURATION, TimerMode::Once);
+        self.scale = SLOW_MOTION_SCALE;
+    }
+}
 
-    for _ in 0..obstacle_count {
-        let x = rng.gen_range(-window.width() / 2.0..window.width() / 2.0);
-        let obstacle_pos = Vec3::new(x, obstacle_y, 0.0);
+/// How shaken up the camera is, from 0 to 1. Events add to `trauma` and
+/// it drains over time; the shake grows with its square. `offset` is what
+/// was last added to the camera, taken off again before it follows.
+#[derive(Resource, Default)]
+pub struct ScreenShake {
+    pub trauma: f32,
+    offset: Vec2,
+}
 
-        commands.spawn((
-            SpriteBundle {
-                sprite: Sprite {
-                    color: Color::DARK_GRAY,
-                    custom_size: Some(OBSTACLE_SIZE),
-                    ..default()
-                },
-                transform: Transform::from_translation(obstacle_pos),
-                ..default()
-            },
-            Obstacle,
-        ));
+impl ScreenShake {
+    pub fn add_trauma(&mut self, amount: f32) {
+        self.trauma = (self.trauma + amount).min(1.0);
     }
 }
 
-//
-// GAMEPLAY SYSTEMS
-//
+#[derive(Resource)]
+pub struct Score(i32);
 
-/// Processes player input for movement and jumping.
-fn player_input_system(
-    keyboard_input: Res<Input<KeyCode>>,
-    mut query: Query<(&mut Velocity, &mut Transform), With<Player>>,
-    ground_data: Res<GroundData>,
-) {
-    for (mut velocity, mut transform) in query.iter_mut() {
-        // Horizontal movement.
-        let mut direction = 0.0;
-        if keyboard_input.pressed(KeyCode::Left) || keyboard_input.pressed(KeyCode::A) {
-            direction -= 1.0;
+/// The best score to show beside the current one: the saved best for the
+/// level, or the current score once it's higher. Saving happens through
+/// `HighScores` when the run ends.
+#[derive(Resource, Default)]
+pub struct HighScore(i32);
+
+#[derive(Resource)]
+pub struct Lives(i32);
+
+/// Best score on each level, kept in the user's data directory between sessions.
+#[derive(Resource, Serialize, Deserialize, Default)]
+pub struct HighScores {
+    pub per_level: HashMap<u32, i32>,
+    /// Best score in endless mode, which isn't tied to a level.
+    #[serde(default)]
+    pub endless: Option<i32>,
+    /// Fastest time-attack clear of each level, in seconds.
+    #[serde(default)]
+    pub best_time: HashMap<u32, f32>,
+    #[serde(skip)]
+    pub save_path: PathBuf,
+}
+
+impl HighScores {
+    fn best(&self, level: u32) -> Option<i32> {
+        self.per_level.get(&level).copied()
+    }
+
+    /// Stores `score` if it beats the best for `level` and saves to disk.
+    /// Returns the previous best.
+    fn record(&mut self, level: u32, score: i32) -> Option<i32> {
+        let previous = self.best(level);
+        if previous.is_none_or(|best| score > best) {
+            self.per_level.insert(level, score);
+            self.save();
         }
-        if keyboard_input.pressed(KeyCode::Right) || keyboard_input.pressed(KeyCode::D) {
-            direction += 1.0;
+        previous
+    }
+
+    /// The best for `level`, or the endless best in endless mode.
+    fn best_in(&self, mode: GameMode, level: u32) -> Option<i32> {
+        match mode {
+            GameMode::Endless => self.endless,
+            _ => self.best(level),
         }
-        velocity.x = direction * PLAYER_SPEED;
+    }
 
-        // Flip sprite based on direction.
-        if direction != 0.0 {
-            transform.scale.x = transform.scale.x.abs() * direction.signum();
+    /// Like `record`, but endless runs go on their own table.
+    fn record_in(&mut self, mode: GameMode, level: u32, score: i32) -> Option<i32> {
+        match mode {
+            GameMode::Story | GameMode::TimeAttack | GameMode::Survival => {
+                self.record(level, score)
+            }
+            GameMode::Endless => {
+                let previous = self.endless;
+                if previous.is_none_or(|best| score > best) {
+                    self.endless = Some(score);
+                    self.save();
+                }
+                previous
+            }
         }
+    }
 
-        // Jump if on the ground.
-        if (keyboard_input.just_pressed(KeyCode::Space)
-            || keyboard_input.just_pressed(KeyCode::Key2))
-            && transform.translation.y <= ground_data.top_y + PLAYER_SIZE.y / 2.0
-        {
-            velocity.y = PLAYER_JUMP_VELOCITY;
+    /// Stores `seconds` if it beats the fastest time-attack clear of
+    /// `level` and saves to disk. Returns the previous best.
+    fn record_time(&mut self, level: u32, seconds: f32) -> Option<f32> {
+        let previous = self.best_time.get(&level).copied();
+        if previous.is_none_or(|best| seconds < best) {
+            self.best_time.insert(level, seconds);
+            self.save();
         }
+        previous
     }
-}
 
-/// Applies gravity to the player.
-fn apply_gravity_system(
-    time: Res<Time>,
-    gravity: Res<Gravity>,
-    mut query: Query<&mut Velocity, With<Player>>,
-) {
-    for mut velocity in query.iter_mut() {
-        velocity.y += gravity.0 * time.delta_seconds();
+    fn save(&self) {
+        write_save_file(&self.save_path, self);
     }
 }
 
-/// Moves all entities based on their velocity.
-fn movement_system(time: Res<Time>, mut query: Query<(&mut Transform, &Velocity)>) {
-    for (mut transform, velocity) in query.iter_mut() {
-        transform.translation += (velocity.0 * time.delta_seconds()).extend(0.0);
-    }
+/// Lifetime totals across every session, saved next to the high scores.
+#[derive(Resource, Default, Serialize, Deserialize)]
+pub struct PlayerStats {
+    pub enemies_killed: u32,
+    pub jumps_made: u32,
+    pub deaths: u32,
+    /// Deaths from falling out of the level, also counted in `deaths`.
+    #[serde(default)]
+    pub falls: u32,
+    pub total_play_seconds: f64,
+    /// Quickest time to clear a story level, in seconds.
+    #[serde(default)]
+    pub fastest_clear: Option<f32>,
+    pub coins_collected: u32,
 }
 
-/// Wraps the player around the screen horizontally.
-fn player_wrap_system(
-    window_query: Query<&Window, With<PrimaryWindow>>,
-    mut query: Query<&mut Transform, With<Player>>,
-) {
-    let window = window_query.single();
-    let half_width = window.width() / 2.0;
-    for mut transform in query.iter_mut() {
-        if transform.translation.x > half_width {
-            transform.translation.x = -half_width;
-        } else if transform.translation.x < -half_width {
-            transform.translation.x = half_width;
+/// Random source for level generation and falling rocks. It is reseeded
+/// from `seed` and the level number every time a level spawns, so the same
+/// seed always builds the same layouts.
+#[derive(Resource)]
+pub struct GameRng {
+    pub seed: u64,
+    pub rng: StdRng,
+}
+
+impl GameRng {
+    fn new(seed: u64) -> Self {
+        GameRng {
+            seed,
+            rng: StdRng::seed_from_u64(seed),
         }
     }
+
+    fn reseed(&mut self, level: u32) {
+        self.rng = StdRng::seed_from_u64(self.seed.wrapping_add(level as u64));
+    }
 }
 
-/// Wraps enemies around the screen horizontally.
-fn enemy_wrap_system(
-    window_query: Query<&Window, With<PrimaryWindow>>,
-    mut query: Query<&mut Transform, With<Enemy>>,
-) {
-    let window = window_query.single();
-    let half_width = window.width() / 2.0;
-    for mut transform in query.iter_mut() {
-        if transform.translation.x > half_width {
-            transform.translation.x = -half_width;
-        } else if transform.translation.x < -half_width {
-            transform.translation.x = half_width;
+/// Player options, loaded from `settings.ron` at startup and saved when
+/// the settings menu closes.
+#[derive(Resource, Serialize, Deserialize, Clone, Debug)]
+pub struct Settings {
+    #[serde(default)]
+    pub audio: AudioSettings,
+    pub resolution: (u32, u32),
+    #[serde(default)]
+    pub movement: MovementTuning,
+    /// Turns off screen shake.
+    #[serde(default)]
+    pub reduced_motion: bool,
+    /// Last camera zoom picked in game.
+    #[serde(default = "default_zoom")]
+    pub zoom: f32,
+    #[serde(default)]
+    pub auto_zoom: bool,
+    /// Tunes enemy speed to how well the player is doing, unless a
+    /// difficulty is picked on the main menu.
+    #[serde(default)]
+    pub adaptive_difficulty: bool,
+    /// Freezes the action for a moment on each kill.
+    #[serde(default = "default_hit_stop")]
+    pub hit_stop: bool,
+    /// Shows the controls card at the start of every run, not just the
+    /// first one since the game was launched.
+    #[serde(default)]
+    pub always_show_controls: bool,
+}
+
+fn default_zoom() -> f32 {
+    1.0
+}
+
+fn default_hit_stop() -> bool {
+    true
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            audio: AudioSettings::default(),
+            resolution: RESOLUTIONS[0],
+            movement: MovementTuning::default(),
+            reduced_motion: false,
+            zoom: default_zoom(),
+            auto_zoom: false,
+            adaptive_difficulty: false,
+            hit_stop: default_hit_stop(),
+            always_show_controls: false,
         }
     }
 }
 
-/// Helper function for AABB collision detection.
-fn is_colliding(pos_a: Vec3, half_a: Vec2, pos_b: Vec3, half_b: Vec2) -> bool {
-    (pos_a.x - half_a.x < pos_b.x + half_b.x)
-        && (pos_a.x + half_a.x > pos_b.x - half_b.x)
-        && (pos_a.y - half_a.y < pos_b.y + half_b.y)
-        && (pos_a.y + half_a.y > pos_b.y - half_b.y)
+/// Volumes from 0 to 1. Music and sound effects play at their own volume
+/// times `master`. Copied from `Settings` whenever they change.
+#[derive(Resource, Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct AudioSettings {
+    pub master: f32,
+    pub music: f32,
+    pub sfx: f32,
 }
 
-/// Keeps the player on the ground if falling below it.
-fn collision_system(
-    mut query: Query<(&mut Transform, &mut Velocity), With<Player>>,
-    ground_data: Res<GroundData>,
-) {
-    for (mut transform, mut velocity) in query.iter_mut() {
-        let player_half = PLAYER_SIZE.y / 2.0;
-        if transform.translation.y - player_half < ground_data.top_y {
-            transform.translation.y = ground_data.top_y + player_half;
-            if velocity.y < 0.0 {
-                velocity.y = 0.0;
-            }
+impl Default for AudioSettings {
+    fn default() -> Self {
+        AudioSettings {
+            master: 1.0,
+            music: 0.8,
+            sfx: 1.0,
         }
     }
 }
 
-/// Handles collisions between the player and enemies.
-fn enemy_collision_system(
+impl AudioSettings {
+    fn music_volume(&self) -> f32 {
+        self.music * self.master
+    }
+
+    fn sfx_volume(&self) -> f32 {
+        self.sfx * self.master
+    }
+}
+
+/// The volume new sound effects play at, kept up to date by
+/// `apply_audio_settings_system`.
+#[derive(Resource)]
+pub struct SfxVolume(f32);
+
+/// How quickly the player's walking speed changes, in pixels per second
+/// squared. Acceleration applies while steering toward a speed, and
+/// friction or drag while slowing down or going faster than walking pace.
+/// Control in the air is weaker, so jumps are committed. Copied from
+/// `Settings` whenever they change.
+#[derive(Resource, Serialize, Deserialize, Clone, Debug)]
+pub struct MovementTuning {
+    pub ground_accel: f32,
+    pub ground_friction: f32,
+    pub air_accel: f32,
+    pub air_drag: f32,
+}
+
+impl Default for MovementTuning {
+    fn default() -> Self {
+        MovementTuning {
+            ground_accel: 3000.0,
+            ground_friction: 6000.0,
+            air_accel: 1500.0,
+            air_drag: 600.0,
+        }
+    }
+}
+
+/// Keys for the remappable player actions, loaded from `keybindings.ron`
+/// at startup and saved whenever one is changed on the controls screen.
+#[derive(Resource, Serialize, Deserialize, Clone, Debug)]
+pub struct KeyBindings {
+    pub left: KeyPair,
+    pub right: KeyPair,
+    pub jump: KeyPair,
+    pub dash: KeyPair,
+    /// Not used by any ability yet.
+    pub shoot: KeyPair,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        KeyBindings {
+            left: KeyPair::new(KeyCode::Left, Some(KeyCode::A)),
+            right: KeyPair::new(KeyCode::Right, Some(KeyCode::D)),
+            jump: KeyPair::new(KeyCode::Space, Some(KeyCode::Key2)),
+            dash: KeyPair::new(KeyCode::ShiftLeft, Some(KeyCode::ShiftRight)),
+            shoot: KeyPair::new(KeyCode::F, None),
+        }
+    }
+}
+
+impl KeyBindings {
+    fn keys(&self, action: BoundAction) -> KeyPair {
+        match action {
+            BoundAction::Left => self.left,
+            BoundAction::Right => self.right,
+            BoundAction::Jump => self.jump,
+            BoundAction::Dash => self.dash,
+            BoundAction::Shoot => self.shoot,
+        }
+    }
+
+    fn keys_mut(&mut self, action: BoundAction) -> &mut KeyPair {
+        match action {
+            BoundAction::Left => &mut self.left,
+            BoundAction::Right => &mut self.right,
+            BoundAction::Jump => &mut self.jump,
+            BoundAction::Dash => &mut self.dash,
+            BoundAction::Shoot => &mut self.shoot,
+        }
+    }
+}
+
+/// The keys bound to one action; either of them triggers it. The secondary
+/// key is optional.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct KeyPair {
+    pub primary: KeyCode,
+    #[serde(default)]
+    pub secondary: Option<KeyCode>,
+}
+
+impl KeyPair {
+    fn new(primary: KeyCode, secondary: Option<KeyCode>) -> Self {
+        KeyPair { primary, secondary }
+    }
+
+    fn keys(self) -> impl Iterator<Item = KeyCode> {
+        std::iter::once(self.primary).chain(self.secondary)
+    }
+
+    fn pressed(self, input: &Input<KeyCode>) -> bool {
+        input.any_pressed(self.keys())
+    }
+
+    fn just_pressed(self, input: &Input<KeyCode>) -> bool {
+        input.any_just_pressed(self.keys())
+    }
+
+    fn slot(self, slot: KeySlot) -> Option<KeyCode> {
+        match slot {
+            KeySlot::Primary => Some(self.primary),
+            KeySlot::Secondary => self.secondary,
+        }
+    }
+
+    /// The text on a slot's button on the controls screen.
+    fn slot_label(self, slot: KeySlot) -> String {
+        self.slot(slot).map_or_else(|| "-".to_string(), key_name)
+    }
+}
+
+/// How a key is written on screen. Falls back to its `KeyCode` name, which
+/// already reads well for letters and most named keys.
+fn key_name(key: KeyCode) -> String {
+    let name = match key {
+        KeyCode::Left => "←",
+        KeyCode::Right => "→",
+        KeyCode::Up => "↑",
+        KeyCode::Down => "↓",
+        KeyCode::Key1 => "1",
+        KeyCode::Key2 => "2",
+        KeyCode::Key3 => "3",
+        KeyCode::Key4 => "4",
+        KeyCode::Key5 => "5",
+        KeyCode::Key6 => "6",
+        KeyCode::Key7 => "7",
+        KeyCode::Key8 => "8",
+        KeyCode::Key9 => "9",
+        KeyCode::Key0 => "0",
+        KeyCode::ShiftLeft => "Left Shift",
+        KeyCode::ShiftRight => "Right Shift",
+        KeyCode::ControlLeft => "Left Ctrl",
+        KeyCode::ControlRight => "Right Ctrl",
+        KeyCode::AltLeft => "Left Alt",
+        KeyCode::AltRight => "Right Alt",
+        KeyCode::Return => "Enter",
+        KeyCode::Back => "Backspace",
+        _ => return format!("{:?}", key),
+    };
+    name.to_string()
+}
+
+/// Which of an action's two keys a controls screen button rebinds.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum KeySlot {
+    Primary,
+    Secondary,
+}
+
+/// An action that can be given a different key on the controls screen.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum BoundAction {
+    Left,
+    Right,
+    Jump,
+    Dash,
+    Shoot,
+}
+
+impl BoundAction {
+    const ALL: [BoundAction; 5] = [
+        BoundAction::Left,
+        BoundAction::Right,
+        BoundAction::Jump,
+        BoundAction::Dash,
+        BoundAction::Shoot,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            BoundAction::Left => "Left",
+            BoundAction::Right => "Right",
+            BoundAction::Jump => "Jump",
+            BoundAction::Dash => "Dash",
+            BoundAction::Shoot => "Shoot",
+        }
+    }
+}
+
+/// The level being played, numbered from 1.
+#[derive(Resource, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CurrentLevel(pub u32);
+
+/// Number of level files found under `assets/levels` at startup.
+#[derive(Resource)]
+pub struct LevelCount(pub u32);
+
+/// Score banked from every level cleared since the campaign started.
+#[derive(Resource, Default)]
+pub struct TotalScore(pub i32);
+
+/// Present when `--hardcore` is passed: retrying after a game over starts
+/// again from level 1.
+#[derive(Resource)]
+pub struct Hardcore;
+
+/// Counts down on the win screen before the next level starts.
+#[derive(Resource)]
+pub struct LevelAdvanceTimer(Timer);
+
+/// Levels completed so far and the best score on each.
+#[derive(Resource, Default)]
+pub struct ProgressResource {
+    pub highest_completed: u32,
+    pub best_scores: HashMap<u32, i32>,
+}
+
+/// Consecutive stomps; `count` falls back to zero once `timer` runs out
+/// without another stomp.
+#[derive(Resource)]
+pub struct ComboState {
+    pub count: u32,
+    pub timer: Timer,
+}
+
+impl Default for ComboState {
+    fn default() -> Self {
+        ComboState {
+            count: 0,
+            timer: Timer::from_seconds(COMBO_WINDOW, TimerMode::Once),
+        }
+    }
+}
+
+/// Coins collected in a row; `count` falls back to zero once `reset_timer`
+/// runs out without another coin.
+#[derive(Resource)]
+pub struct CoinPitchTracker {
+    pub count: u32,
+    pub reset_timer: Timer,
+}
+
+impl Default for CoinPitchTracker {
+    fn default() -> Self {
+        CoinPitchTracker {
+            count: 0,
+            reset_timer: Timer::from_seconds(COIN_PITCH_WINDOW, TimerMode::Once),
+        }
+    }
+}
+
+/// Hot-streak multiplier applied to every score gain. Kills raise `value`
+/// and it drains by `decay_rate` per second, never below 1.
+#[derive(Resource)]
+pub struct ActivityMultiplier {
+    pub value: f32,
+    pub decay_rate: f32,
+}
+
+impl Default for ActivityMultiplier {
+    fn default() -> Self {
+        ActivityMultiplier {
+            value: 1.0,
+            decay_rate: ACTIVITY_DECAY_RATE,
+        }
+    }
+}
+
+impl ActivityMultiplier {
+    /// Whole multiplier applied to score gains.
+    fn factor(&self) -> i32 {
+        self.value.floor() as i32
+    }
+}
+
+/// Time spent playing the current level; stops while paused.
+#[derive(Resource, Default)]
+pub struct LevelTimer {
+    pub elapsed: Stopwatch,
+    /// The countdown in timed mode. Running out of time ends the game.
+    pub remaining: Option<Timer>,
+}
+
+impl LevelTimer {
+    fn timed_out(&self) -> bool {
+        self.remaining
+            .as_ref()
+            .is_some_and(|timer| timer.finished())
+    }
+}
+
+/// The time-attack clock. It counts up while playing, and each death adds
+/// `TIME_ATTACK_DEATH_PENALTY`.
+#[derive(Resource, Default)]
+pub struct TimeAttackTimer(pub Stopwatch);
+
+/// Pause between losing the last life and showing the game-over screen.
+#[derive(Resource)]
+pub struct GameOverDelay(Timer);
+
+/// Runs while no enemies are left; clearing them wins once it finishes.
+#[derive(Resource)]
+pub struct AllClearTimer(Timer);
+
+impl Default for AllClearTimer {
+    fn default() -> Self {
+        AllClearTimer(Timer::from_seconds(ALL_CLEAR_DELAY, TimerMode::Once))
+    }
+}
+
+/// Counts down to the next falling rock. Restarted with every level.
+#[derive(Resource)]
+pub struct HazardSpawner {
+    pub timer: Timer,
+}
+
+impl HazardSpawner {
+    /// A random wait from `HAZARD_INTERVAL_RANGE`, shorter on difficulties
+    /// with more enemies.
+    fn random_interval(rng: &mut StdRng, difficulty: &DifficultyMultipliers) -> Timer {
+        let seconds = rng.gen_range(HAZARD_INTERVAL_RANGE.0..HAZARD_INTERVAL_RANGE.1);
+        Timer::from_seconds(seconds / difficulty.enemy_count, TimerMode::Once)
+    }
+}
+
+/// Counts down to the next wave of enemies in endless mode.
+#[derive(Resource)]
+pub struct WaveSpawner {
+    /// The last wave sent in; 0 before the first.
+    pub wave: u32,
+    pub timer: Timer,
+}
+
+impl Default for WaveSpawner {
+    fn default() -> Self {
+        WaveSpawner {
+            wave: 0,
+            timer: Timer::from_seconds(WAVE_INTERVAL, TimerMode::Repeating),
+        }
+    }
+}
+
+/// Counters for the current run, shown when it ends.
+#[derive(Resource, Default)]
+pub struct RunStats {
+    pub blocks_broken: u32,
+    pub coins_collected: u32,
+    pub enemies_stomped: u32,
+}
+
+/// Shared mesh and material for coin pickups.
+#[derive(Resource)]
+pub struct CoinAssets {
+    mesh: Handle<Mesh>,
+    material: Handle<ColorMaterial>,
+}
+
+/// Sound effects, loaded once the level is set up.
+#[derive(Resource)]
+pub struct AudioHandles {
+    jump: Handle<AudioSource>,
+    stomp: Handle<AudioSource>,
+    hurt: Handle<AudioSource>,
+    coin: Handle<AudioSource>,
+    walk: Handle<AudioSource>,
+}
+
+/// A one-shot sound, despawned by `audio_cleanup_system` once it has played.
+#[derive(Component)]
+struct SoundEffect;
+
+/// An enemy's looping footsteps, started by `start_walk_sound_system`.
+#[derive(Component)]
+struct WalkSound(Handle<AudioSource>);
+
+/// Where an enemy is relative to the player, from -1 (`ENEMY_SOUND_RANGE`
+/// or more to the left) to 1 (as far to the right). Quietens its footsteps.
+#[derive(Component, Default)]
+struct StereoPan(f32);
+
+/// A music track or jingle, played at the music volume.
+#[derive(Component)]
+struct BgmSink;
+
+/// Marks the main menu's track, so returning to the menu doesn't restart it.
+#[derive(Component)]
+struct MenuMusic;
+
+/// The music track currently playing, if any.
+#[derive(Resource)]
+pub struct BgmEntity(Entity);
+
+/// A win or game-over jingle.
+#[derive(Component)]
+struct Jingle;
+
+/// Holds back the buttons that leave the win or game-over screen until
+/// the jingle has finished.
+#[derive(Component)]
+struct AfterJingle;
+
+/// Whether the current win or game-over jingle has finished.
+#[derive(Resource)]
+pub struct JingleState {
+    pub jingle_timer: Timer,
+    pub finished: bool,
+}
+
+impl Default for JingleState {
+    fn default() -> Self {
+        JingleState {
+            jingle_timer: Timer::from_seconds(JINGLE_TIMEOUT, TimerMode::Once),
+            finished: false,
+        }
+    }
+}
+
+/// Where the player reappears after losing a life.
+#[derive(Resource)]
+pub struct RespawnPoint(Vec3);
+
+/// How the current level is won.
+#[derive(Resource, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WinCondition {
+    /// Defeat every enemy.
+    ClearEnemies,
+    /// Touch the goal flag.
+    ReachGoal,
+    /// Pick up the key and walk through the door.
+    UnlockDoor,
+}
+
+/// The win condition picked on the command line, used by levels that
+/// don't declare their own.
+#[derive(Resource)]
+pub struct DefaultWinCondition(pub WinCondition);
+
+/// Progress towards the key and door objective.
+#[derive(Resource, Default)]
+pub struct DoorObjective {
+    pub has_key: bool,
+    pub opened: bool,
+}
+
+/// Present while a level is spawned, so resuming from pause doesn't
+/// spawn it a second time.
+#[derive(Resource)]
+pub struct LevelLoaded;
+
+/// Horizontal extent of the current level. The player and enemies are
+/// kept inside it and the camera scrolls across it.
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct LevelBounds {
+    pub min_x: f32,
+    pub max_x: f32,
+}
+
+impl LevelBounds {
+    fn width(&self) -> f32 {
+        self.max_x - self.min_x
+    }
+
+    /// How many windows wide the level is, for scaling spawn counts.
+    fn screen_count(&self, window: &Window) -> usize {
+        (self.width() / window.width()).round().max(1.0) as usize
+    }
+}
+
+/// Present only in vertical mode: the height the player has to climb.
+#[derive(Resource)]
+pub struct LevelHeight(pub f32);
+
+/// A level file as stored under `assets/levels`.
+#[derive(Resource, Deserialize)]
+pub struct LevelConfig {
+    pub tile_size: (f32, f32),
+    /// Background tiles, one string per row from top to bottom.
+    /// `.` is empty, `~` sky, `D` dirt and `S` stone.
+    pub tiles: Vec<String>,
+    /// Score needed for the second and third star on the win screen.
+    #[serde(default = "default_star_thresholds")]
+    pub star_thresholds: (i32, i32),
+    /// Overrides the command-line win condition for this level.
+    #[serde(default)]
+    pub win_condition: Option<WinCondition>,
+    /// Overrides `GRAVITY_FORCE` for this level.
+    #[serde(default)]
+    pub gravity: Option<f32>,
+    /// Overrides `ENEMY_SPEED_RANGE` for randomly spawned enemies.
+    #[serde(default)]
+    pub enemy_speed_range: Option<(f32, f32)>,
+    /// Ground color as `(r, g, b)` for the flat level.
+    #[serde(default)]
+    pub ground_color: Option<(f32, f32, f32)>,
+    /// Seconds on the countdown; setting it makes the level timed.
+    #[serde(default)]
+    pub time_limit: Option<f32>,
+    /// Routes for layout enemies to walk instead of heading straight on.
+    #[serde(default)]
+    pub enemy_patrols: Vec<PatrolDef>,
+    /// Hand-placed layout. Without one the level is generated randomly.
+    #[serde(default)]
+    pub layout: Option<LevelDef>,
+}
+
+/// Hand-placed objects of a flat level. Positions are `(x, y)` where `x`
+/// is the world x of the object's centre and `y` is the height of its
+/// bottom edge above the ground.
+#[derive(Deserialize)]
+pub struct LevelDef {
+    pub player_spawn: (f32, f32),
+    #[serde(default)]
+    pub obstacles: Vec<ObstacleDef>,
+    #[serde(default)]
+    pub enemies: Vec<EnemyDef>,
+    /// Where the goal flag stands in goal mode; defaults to the right edge.
+    #[serde(default)]
+    pub goal: Option<(f32, f32)>,
+    /// Exact coin positions; without them coins are scattered randomly.
+    #[serde(default)]
+    pub coins: Option<Vec<(f32, f32)>>,
+    #[serde(default)]
+    pub ladders: Vec<LadderDef>,
+    #[serde(default)]
+    pub water: Vec<WaterDef>,
+    #[serde(default)]
+    pub platforms: Vec<PlatformDef>,
+    #[serde(default)]
+    pub slopes: Vec<SlopeDef>,
+}
+
+/// A sloped piece of ground; `heights` are how high its surface rises at
+/// the left and right edges.
+#[derive(Deserialize)]
+pub struct SlopeDef {
+    pub position: (f32, f32),
+    pub width: f32,
+    pub heights: (f32, f32),
+}
+
+/// A one-way platform; `position.1` is the height of its top above the ground.
+#[derive(Deserialize)]
+pub struct PlatformDef {
+    pub position: (f32, f32),
+    #[serde(default = "default_platform_width")]
+    pub width: f32,
+}
+
+/// A route for the walker or patroller at index `enemy` of the layout's
+/// enemies. Waypoints are positions like the layout's, visited in order and
+/// then from the first again.
+#[derive(Deserialize)]
+pub struct PatrolDef {
+    pub enemy: usize,
+    pub waypoints: Vec<(f32, f32)>,
+}
+
+fn default_platform_width() -> f32 {
+    PLATFORM_SIZE.x
+}
+
+#[derive(Deserialize)]
+pub struct WaterDef {
+    pub position: (f32, f32),
+    pub size: (f32, f32),
+}
+
+#[derive(Deserialize)]
+pub struct LadderDef {
+    pub position: (f32, f32),
+    pub height: f32,
+}
+
+#[derive(Deserialize)]
+pub struct ObstacleDef {
+    pub position: (f32, f32),
+    #[serde(default = "default_obstacle_size")]
+    pub size: (f32, f32),
+    #[serde(default)]
+    pub breakable: bool,
+    /// Makes the obstacle a conveyor belt moving its top at this speed;
+    /// negative runs left.
+    #[serde(default)]
+    pub conveyor: Option<f32>,
+    /// Makes the obstacle bouncy, giving back this share of the player's
+    /// speed when they land on or run into it.
+    #[serde(default)]
+    pub restitution: Option<f32>,
+}
+
+fn default_obstacle_size() -> (f32, f32) {
+    (OBSTACLE_SIZE.x, OBSTACLE_SIZE.y)
+}
+
+#[derive(Deserialize)]
+pub struct EnemyDef {
+    pub kind: EnemyKind,
+    pub position: (f32, f32),
+    /// Starting horizontal speed; negative walks left.
+    pub speed: f32,
+    /// How many stomps it takes to defeat.
+    #[serde(default = "default_enemy_health")]
+    pub health: u32,
+}
+
+fn default_enemy_health() -> u32 {
+    1
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EnemyKind {
+    /// Walks until it bumps into an obstacle or the edge of the level.
+    Walker,
+    /// Walks back and forth around its spawn point.
+    Patroller,
+    /// Heads for the player at `speed`, hopping onto obstacles in its way.
+    Chaser,
+}
+
+/// How a random level's enemies are laid out. Everything but `Random` drops
+/// a formation of enemies in together, all heading the same way.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum SpawnPattern {
+    /// Each enemy somewhere on its own.
+    Random,
+    /// Side by side along the ground.
+    Line,
+    /// Rising either side of a point on the ground.
+    VShape,
+    /// Along a semicircle standing on the ground.
+    Arc,
+}
+
+impl SpawnPattern {
+    /// Cycles through the patterns level by level, starting with `Random`.
+    fn for_level(level: u32) -> Self {
+        const PATTERNS: [SpawnPattern; 4] = [
+            SpawnPattern::Random,
+            SpawnPattern::Line,
+            SpawnPattern::VShape,
+            SpawnPattern::Arc,
+        ];
+        PATTERNS[level.saturating_sub(1) as usize % PATTERNS.len()]
+    }
+}
+
+/// Where `count` enemies in `pattern` go, `spacing` apart and centred on
+/// `center` at the bottom. `Random` has no shape, so it places none.
+fn pattern_spawn_helper(
+    pattern: SpawnPattern,
+    count: usize,
+    center: Vec2,
+    spacing: f32,
+) -> Vec<Vec2> {
+    let middle = (count as f32 - 1.0) / 2.0;
+    (0..count)
+        .filter_map(|index| {
+            let offset = index as f32 - middle;
+            match pattern {
+                SpawnPattern::Random => None,
+                SpawnPattern::Line => Some(Vec2::new(offset * spacing, 0.0)),
+                SpawnPattern::VShape => {
+                    Some(Vec2::new(offset * spacing, offset.abs() * spacing / 2.0))
+                }
+                SpawnPattern::Arc => {
+                    let radius = middle * spacing;
+                    let angle = if count > 1 {
+                        std::f32::consts::PI * index as f32 / (count as f32 - 1.0)
+                    } else {
+                        std::f32::consts::FRAC_PI_2
+                    };
+                    Some(Vec2::new(-angle.cos(), angle.sin()) * radius)
+                }
+            }
+        })
+        .map(|offset| center + offset)
+        .collect()
+}
+
+/// Present when `--endless` is passed: layouts are ignored and every level
+/// is generated randomly.
+#[derive(Resource)]
+pub struct Endless;
+
+/// Present when `--timed` is passed: every level is played against a
+/// countdown, and time left over multiplies the score.
+#[derive(Resource)]
+pub struct Timed;
+
+/// Present when `--coop` is passed: a second player joins on the same
+/// keyboard.
+#[derive(Resource)]
+pub struct Coop;
+
+fn default_star_thresholds() -> (i32, i32) {
+    DEFAULT_STAR_THRESHOLDS
+}
+
+/// Purely visual background scenery; tiles have no collision.
+#[derive(Resource)]
+pub struct TileMap {
+    pub tiles: Vec<Vec<TileKind>>,
+    pub tile_size: Vec2,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TileKind {
+    Empty,
+    Dirt,
+    Stone,
+    Sky,
+}
+
+impl TileKind {
+    fn from_char(c: char) -> Self {
+        match c {
+            'D' => TileKind::Dirt,
+            'S' => TileKind::Stone,
+            '~' => TileKind::Sky,
+            _ => TileKind::Empty,
+        }
+    }
+
+    fn texture(self) -> Option<&'static str> {
+        match self {
+            TileKind::Empty => None,
+            TileKind::Dirt => Some("tiles/dirt.png"),
+            TileKind::Stone => Some("tiles/stone.png"),
+            TileKind::Sky => Some("tiles/sky.png"),
+        }
+    }
+}
+
+impl From<&LevelConfig> for TileMap {
+    fn from(config: &LevelConfig) -> Self {
+        TileMap {
+            tiles: config
+                .tiles
+                .iter()
+                .map(|row| row.chars().map(TileKind::from_char).collect())
+                .collect(),
+            tile_size: Vec2::new(config.tile_size.0, config.tile_size.1),
+        }
+    }
+}
+
+#[derive(Component)]
+struct Player;
+
+/// The second player in co-op, who also has `Player`. They have no lives
+/// of their own: running out of health takes them out of the level, and
+/// player 1 carries on alone.
+#[derive(Component)]
+struct Player2;
+
+/// The oval on the ground under player 1 that shows how high they are.
+/// It's a separate entity rather than a child, so it stays on the ground.
+#[derive(Component)]
+struct PlayerShadow;
+
+/// A background layer that scrolls at `factor` of the camera's speed: 0
+/// stays put on screen, 1 moves with the world. Its children repeat every
+/// `PARALLAX_TILE_WIDTH`, so it is kept within one repeat of the camera.
+#[derive(Component)]
+struct Parallax {
+    factor: f32,
+    base_y: f32,
+}
+
+/// What the camera follows. Only the player has one for now.
+#[derive(Component)]
+struct CameraTarget;
+
+#[derive(Component)]
+struct Enemy;
+
+#[derive(Component)]
+struct Obstacle;
+
+/// An obstacle the player can destroy by jumping into it from below.
+#[derive(Component)]
+struct Breakable;
+
+/// A cracked obstacle the player can smash by dashing into it.
+#[derive(Component)]
+struct Fragile;
+
+/// How much of the player's speed an obstacle gives back when they land on
+/// or run into it, from 0 (none) to 1 (all of it).
+#[derive(Component)]
+struct Restitution(f32);
+
+/// An obstacle whose top carries whatever stands on it sideways at `push`
+/// pixels per second.
+#[derive(Component)]
+struct Conveyor {
+    push: f32,
+}
+
+/// One of the stripes scrolling along a conveyor to show which way it runs.
+#[derive(Component)]
+struct ConveyorStripe {
+    half_width: f32,
+}
+
+/// Marks where a rock is about to drop; the rock falls when the timer ends.
+#[derive(Component)]
+struct HazardWarning(Timer);
+
+/// A rock falling from the sky. It hurts the player, squashes enemies and
+/// breaks apart on the ground or an obstacle.
+#[derive(Component)]
+struct FallingHazard;
+
+/// One ring of the red border shown around the screen after a hit, fading
+/// out as its timer runs.
+#[derive(Component)]
+struct DamageVignette(Timer);
+
+/// A full-screen white flash over the win screen, fading out as its timer
+/// runs.
+#[derive(Component)]
+struct ScreenFlash {
+    timer: Timer,
+}
+
+/// A chunk of a smashed obstacle, or a speck of landing dust, flying off
+/// until its timer runs out.
+#[derive(Component)]
+struct Debris(Timer);
+
+/// Exempts an entity from `VelocityLimits`.
+#[derive(Component)]
+struct Unclamped;
+
+/// Climbable by the player; enemies walk straight past.
+#[derive(Component)]
+struct Ladder;
+
+/// Set on the player while touching a ladder.
+#[derive(Component)]
+struct OnLadder;
+
+/// The player is on a ladder and ignores gravity.
+#[derive(Component)]
+struct Climbing;
+
+/// A pool the player swims through and enemies wade through.
+#[derive(Component)]
+struct Water;
+
+/// Set on the player or an enemy while its centre is inside water.
+#[derive(Component)]
+struct InWater;
+
+/// Set after a swim stroke; the next one waits until it runs out.
+#[derive(Component)]
+struct SwimCooldown(Timer);
+
+/// The player is dashing in the direction they face.
+#[derive(Component)]
+struct Dashing(Timer);
+
+/// Set after a dash ends; the player cannot dash again until it runs out.
+#[derive(Component)]
+struct DashCooldown(Timer);
+
+/// A pickup bobbing around the height it was spawned at.
+#[derive(Component)]
+struct Key;
+
+/// Solid until the player holds the key.
+#[derive(Component)]
+struct Door;
+
+/// HUD icon shown once the key is collected.
+#[derive(Component)]
+struct KeyIcon;
+
+#[derive(Component)]
+struct Coin {
+    base_y: f32,
+}
+
+#[derive(Component)]
+struct Ground;
+
+/// An invisible area below the level that costs a life to fall into. Its
+/// top is the screen bottom in vertical mode, or a way down the gaps in
+/// the ground otherwise.
+#[derive(Component)]
+struct FallDeathZone;
+
+#[derive(Component)]
+struct Platform;
+
+/// The box an entity collides with, as half its width and height. Set at
+/// spawn time so collision code never has to guess a size.
+#[derive(Component, Clone, Copy)]
+struct Collider {
+    half_extents: Vec2,
+}
+
+impl Collider {
+    fn from_size(size: Vec2) -> Self {
+        Collider {
+            half_extents: size / 2.0,
+        }
+    }
+}
+
+/// A thin platform that can be jumped up through from below and landed on
+/// from above.
+#[derive(Component)]
+struct OneWayPlatform;
+
+/// Ground whose surface runs in a straight line from `left_y` at its left
+/// edge to `right_y` at its right, both in world space. Its horizontal
+/// extent comes from its `Collider`.
+#[derive(Component)]
+struct Slope {
+    left_y: f32,
+    right_y: f32,
+}
+
+impl Slope {
+    /// Surface height `t` of the way across, from 0 at the left edge to 1
+    /// at the right.
+    fn height_at(&self, t: f32) -> f32 {
+        self.left_y + (self.right_y - self.left_y) * t.clamp(0.0, 1.0)
+    }
+}
+
+/// Where an entity was at the start of the frame, before it moved. Anything
+/// with one can land on a `OneWayPlatform`.
+#[derive(Component)]
+struct PrevTranslation(Vec3);
+
+#[derive(Component)]
+struct GoalFlag;
+
+/// A flag that becomes the respawn point once the player touches it.
+#[derive(Component)]
+struct Checkpoint {
+    respawn: Vec3,
+    activated: bool,
+}
+
+/// Horizontal range an enemy walks back and forth on its platform.
+#[derive(Component)]
+struct PlatformPatrol {
+    min_x: f32,
+    max_x: f32,
+}
+
+/// How close the player has to come before this enemy gives chase.
+#[derive(Component)]
+struct AggroRange(f32);
+
+impl Default for AggroRange {
+    fn default() -> Self {
+        AggroRange(AGGRO_RANGE)
+    }
+}
+
+#[derive(Component, Default, Clone, Copy, PartialEq, Eq, Debug)]
+enum EnemyState {
+    /// Walking its own route, as spawned.
+    #[default]
+    Patrol,
+    /// Running straight at the player.
+    Chase,
+}
+
+/// A route an enemy walks, heading for `waypoints[current]` and on to the
+/// next once it gets there. Enemies only walk, so just the x of each
+/// waypoint is steered for.
+#[derive(Component)]
+struct PatrolPath {
+    waypoints: Vec<Vec2>,
+    current: usize,
+}
+
+impl PatrolPath {
+    /// Index of the waypoint closest to `position`.
+    fn nearest(&self, position: Vec2) -> usize {
+        self.waypoints
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| a.distance(position).total_cmp(&b.distance(position)))
+            .map_or(0, |(index, _)| index)
+    }
+}
+
+/// Horizontal velocity an enemy spawned with, restored when it stops
+/// chasing.
+#[derive(Component)]
+struct SpawnVelocity(f32);
+
+/// Stomps left before a layout enemy is defeated. Enemies without one go
+/// down in a single stomp.
+#[derive(Component)]
+struct EnemyHealth {
+    current: u32,
+    max: u32,
+}
+
+/// A piece of the bar above an enemy with `EnemyHealth`: its background,
+/// or the `EnemyHealthBarFill` on top of it.
+#[derive(Component)]
+struct EnemyHealthBar;
+
+/// The part of an enemy's health bar that shrinks with each stomp.
+#[derive(Component)]
+struct EnemyHealthBarFill;
+
+/// An enemy that follows a route to the player instead of walking straight
+/// ahead. `path` holds the feet positions still to visit, re-planned every
+/// `PATHFIND_INTERVAL`.
+#[derive(Component)]
+struct Pathfinder {
+    path: VecDeque<Vec2>,
+    speed: f32,
+    timer: Timer,
+}
+
+impl Pathfinder {
+    fn new(speed: f32) -> Self {
+        let mut timer = Timer::from_seconds(PATHFIND_INTERVAL, TimerMode::Repeating);
+        // Plan on the first step rather than half a second in.
+        timer.set_elapsed(timer.duration());
+        Pathfinder {
+            path: VecDeque::new(),
+            speed,
+            timer,
+        }
+    }
+}
+
+#[derive(Component)]
+struct Grounded(bool);
+
+/// What a player's animation is showing.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+enum PlayerAnim {
+    #[default]
+    Idle,
+    Run,
+    Jump,
+    Fall,
+    Climb,
+}
+
+impl PlayerAnim {
+    /// The squash-and-stretch poses the animation cycles through, as
+    /// multiples of the sprite's width and height.
+    fn frames(self) -> &'static [Vec2] {
+        const IDLE: [Vec2; 2] = [Vec2::new(1.0, 1.0), Vec2::new(1.02, 0.98)];
+        const RUN: [Vec2; 4] = [
+            Vec2::new(1.0, 1.0),
+            Vec2::new(0.95, 1.05),
+            Vec2::new(1.0, 1.0),
+            Vec2::new(1.05, 0.95),
+        ];
+        const JUMP: [Vec2; 1] = [Vec2::new(0.85, 1.15)];
+        const FALL: [Vec2; 1] = [Vec2::new(0.92, 1.08)];
+        const CLIMB: [Vec2; 2] = [Vec2::new(1.0, 1.0), Vec2::new(1.04, 0.96)];
+        match self {
+            PlayerAnim::Idle => &IDLE,
+            PlayerAnim::Run => &RUN,
+            PlayerAnim::Jump => &JUMP,
+            PlayerAnim::Fall => &FALL,
+            PlayerAnim::Climb => &CLIMB,
+        }
+    }
+}
+
+/// The animation a player is in and the one before it. Only
+/// `process_player_anim_transitions_system` changes it, sending an
+/// `AnimTransition` each time.
+#[derive(Component, Default)]
+struct PlayerAnimStateMachine {
+    current: PlayerAnim,
+    previous: PlayerAnim,
+}
+
+/// Which pose of the current animation is showing, and when to move on.
+#[derive(Component)]
+struct AnimFrame {
+    index: usize,
+    timer: Timer,
+}
+
+impl Default for AnimFrame {
+    fn default() -> Self {
+        AnimFrame {
+            index: 0,
+            timer: Timer::from_seconds(ANIM_FRAME_TIME, TimerMode::Repeating),
+        }
+    }
+}
+
+/// Stomps since the player last touched the ground.
+#[derive(Component, Default)]
+struct ChainBounce {
+    count: u8,
+}
+
+#[derive(Component)]
+struct Invulnerable(Timer);
+
+/// A killed player playing their death animation. They're frozen out of
+/// control, gravity and collisions until it ends and the life is lost.
+#[derive(Component)]
+struct DyingAnimation {
+    timer: Timer,
+    spin_speed: f32,
+}
+
+/// Flashes a sprite red for `timer` after a hit, then puts back
+/// `original_color`.
+#[derive(Component)]
+struct HitFlash {
+    timer: Timer,
+    original_color: Color,
+}
+
+impl HitFlash {
+    fn new(original_color: Color) -> Self {
+        HitFlash {
+            timer: Timer::from_seconds(HIT_FLASH_DURATION, TimerMode::Once),
+            original_color,
+        }
+    }
+}
+
+/// The player's health for the current life. Running out costs a life.
+#[derive(Component)]
+struct Health {
+    current: f32,
+    max: f32,
+}
+
+impl Health {
+    fn full(max: f32) -> Self {
+        Health { current: max, max }
+    }
+
+    fn fraction(&self) -> f32 {
+        (self.current / self.max).clamp(0.0, 1.0)
+    }
+}
+
+/// Root of a health bar: player 1's in the top-left corner of the HUD,
+/// and in co-op player 2's on the right. It remembers the health it last
+/// showed, to tell damage from healing and to notice a new maximum, and
+/// flashes its border in `flash_color` after a change.
+#[derive(Component)]
+struct HealthBarUI {
+    second_player: bool,
+    current: f32,
+    max: f32,
+    flash: Timer,
+    flash_color: Color,
+}
+
+impl HealthBarUI {
+    fn new(max: f32, second_player: bool) -> Self {
+        let mut flash = Timer::from_seconds(HEALTH_FLASH_DURATION, TimerMode::Once);
+        flash.tick(flash.duration());
+        HealthBarUI {
+            second_player,
+            current: max,
+            max,
+            flash,
+            flash_color: Color::WHITE,
+        }
+    }
+}
+
+/// A line between two points of health on the health bar.
+#[derive(Component)]
+struct HealthBarDivider;
+
+/// The part of the health bar that shrinks as the player takes damage.
+#[derive(Component)]
+struct HealthBarFill;
+
+/// The HUD row holding one `LifeIcon` per remaining life.
+#[derive(Component)]
+struct LivesUI;
+
+/// A small player icon in the lives row.
+#[derive(Component)]
+struct LifeIcon;
+
+/// A life icon shrinking away after its life was lost.
+#[derive(Component)]
+struct LostLifeIcon(Timer);
+
+/// A piece of the last life icon, flying apart when the final life is lost.
+#[derive(Component)]
+struct LifeIconShard {
+    velocity: Vec2,
+    timer: Timer,
+}
+
+#[derive(Component)]
+struct ScoreText;
+
+#[derive(Component)]
+struct CoinText;
+
+#[derive(Component)]
+struct SeedText;
+
+#[derive(Component)]
+struct GamepadConnectedText;
+
+/// The HUD block showing the combo multiplier over a bar that drains as
+/// the combo window runs out. Hidden until a combo starts. `shown` is the
+/// multiplier it last showed, and `pulse` times the swell when it goes up.
+#[derive(Component)]
+struct ComboUI {
+    shown: u32,
+    pulse: Timer,
+}
+
+/// The combo multiplier text in the `ComboUI` block.
+#[derive(Component)]
+struct ComboHudText;
+
+/// Fill of the combo bar in the `ComboUI` block.
+#[derive(Component)]
+struct ComboBarFill;
+
+/// HUD label showing the current hot-streak multiplier.
+#[derive(Component)]
+struct ActivityText;
+
+/// Fill of the HUD bar that drains along with the hot streak.
+#[derive(Component)]
+struct ActivityBarFill;
+
+/// Whether the player has moved and jumped since the controls card last
+/// appeared, and whether it has appeared at all this session.
+#[derive(Resource, Default)]
+pub struct TutorialProgress {
+    pub moved: bool,
+    pub jumped: bool,
+    pub card_shown: bool,
+}
+
+/// The panel of the controls card. It fades out over `fade` once `shown`
+/// runs out or the player has both moved and jumped.
+#[derive(Component)]
+struct ControlsHint {
+    shown: Timer,
+    fade: Timer,
+}
+
+/// The current control hint in the level 1 tutorial. `index` points into
+/// `TUTORIAL_HINTS`; the timer covers showing the hint and fading it out.
+#[derive(Component)]
+struct TutorialText {
+    index: usize,
+    display_timer: Timer,
+}
+
+/// A message such as "Wave N" across the top of the screen, fading out as
+/// its timer runs.
+#[derive(Component)]
+struct Banner(Timer);
+
+/// HUD line at the top of the screen counting the enemies left.
+#[derive(Component)]
+struct EnemyCountText;
+
+/// Countdown shown at the top of the screen in timed mode, or the time
+/// survived so far in endless mode.
+#[derive(Component)]
+struct TimerText;
+
+/// The run clock under the countdown, with the tenth of a second it last
+/// showed so the text is only rebuilt when that changes.
+#[derive(Component, Default)]
+struct RunTimerText {
+    shown: Option<u32>,
+}
+
+/// Score popup such as "+100" or "x2!" that rises and fades out until
+/// its timer runs out.
+#[derive(Component)]
+struct FloatingText {
+    timer: Timer,
+    rise_speed: f32,
+}
+
+#[derive(Component, Deref, DerefMut)]
+struct Velocity(Vec2);
+
+/// Smooths the drawn position of something moved in `FixedUpdate`. Each
+/// frame its `Transform` is blended between the last two physics steps, and
+/// put back before the next step runs.
+#[derive(Component, Default)]
+struct Interpolated {
+    previous: Vec3,
+    current: Vec3,
+    /// The position last drawn. Finding anything else there means the
+    /// entity was moved outside physics, e.g. just spawned, so it snaps.
+    rendered: Vec3,
+}
+
+/// What the player asked for, sampled every frame and used by the next
+/// physics step.
+#[derive(Resource, Default, Clone, Copy)]
+struct PlayerIntent {
+    /// Held direction; each axis is -1, 0 or 1.
+    direction: Vec2,
+    /// Jump or dash pressed since the last step; kept until a step uses it
+    /// so presses between steps aren't lost.
+    jump: bool,
+    dash: bool,
+    /// Jump currently held, for swimming.
+    jump_held: bool,
+}
+
+impl PlayerIntent {
+    /// A copy for this step, using up the jump and dash presses.
+    fn take_presses(&mut self) -> PlayerIntent {
+        let taken = *self;
+        self.jump = false;
+        self.dash = false;
+        taken
+    }
+}
+
+/// What player 2 asked for in co-op, kept apart from player 1's.
+#[derive(Resource, Default)]
+struct Player2Intent(PlayerIntent);
+
+/// The stages of a physics step in `FixedUpdate`, which run in this order.
+#[derive(SystemSet, Clone, PartialEq, Eq, Hash, Debug)]
+enum PhysicsSet {
+    /// Undo the last frame's interpolation.
+    Restore,
+    /// Player control, gravity, movement, collisions and their responses.
+    Step,
+    /// Save where the step left everything for the next interpolation.
+    Record,
+}
+
+/// How strongly the global `Gravity` pulls on an entity: 1.0 is normal,
+/// 0.0 floats. Only entities with one fall.
+#[derive(Component)]
+struct GravityScale(f32);
+
+/// Marks everything spawned for a run so it can be torn down on restart.
+#[derive(Component)]
+struct GameEntity;
+
+/// Marks everything spawned for the main menu so it can be cleaned up.
+#[derive(Component)]
+struct MainMenuEntity;
+
+/// The main menu's "Difficulty: ..." label.
+#[derive(Component)]
+struct DifficultyText;
+
+/// Marks everything spawned for the level select screen so it can be cleaned up.
+#[derive(Component)]
+struct LevelSelectEntity;
+
+/// A clickable level card, which keeps its preview color instead of the
+/// usual button feedback.
+#[derive(Component)]
+struct LevelCard;
+
+/// Marks everything spawned for the stats screen so it can be cleaned up.
+#[derive(Component)]
+struct StatsEntity;
+
+/// Marks everything spawned for the settings menu so it can be cleaned up.
+#[derive(Component)]
+struct SettingsEntity;
+
+/// Marks everything spawned for the controls screen so it can be cleaned up.
+#[derive(Component)]
+struct ControlsEntity;
+
+/// A horizontal slider track; pressing or dragging on it sets `value`.
+#[derive(Component)]
+struct Slider {
+    value: f32,
+    min: f32,
+    max: f32,
+}
+
+impl Slider {
+    fn fraction(&self) -> f32 {
+        (self.value - self.min) / (self.max - self.min)
+    }
+}
+
+/// The draggable handle inside a `Slider`.
+#[derive(Component)]
+struct SliderThumb;
+
+/// Which volume setting a slider controls.
+#[derive(Component, Clone, Copy)]
+enum VolumeSlider {
+    Master,
+    Music,
+    Sfx,
+}
+
+/// Marks everything spawned for the pause menu so it can be cleaned up.
+#[derive(Component)]
+struct PauseMenuEntity;
+
+/// Which pause menu button the keyboard has picked, counting from the top.
+#[derive(Resource, Default)]
+struct PauseSelection(usize);
+
+/// Marks everything spawned for the game-over screen so it can be cleaned up.
+#[derive(Component)]
+struct GameOverEntity;
+
+/// Marks everything spawned for the win screen so it can be cleaned up.
+#[derive(Component)]
+struct WinScreenEntity;
+
+/// Marks everything spawned for the final victory screen so it can be cleaned up.
+#[derive(Component)]
+struct VictoryEntity;
+
+/// Fades text or a node background in from transparent to `target_alpha`
+/// over the timer's duration.
+#[derive(Component)]
+struct FadeIn {
+    timer: Timer,
+    target_alpha: f32,
+}
+
+impl FadeIn {
+    fn new(target_alpha: f32) -> Self {
+        FadeIn {
+            timer: Timer::from_seconds(FADE_IN_DURATION, TimerMode::Once),
+            target_alpha,
+        }
+    }
+}
+
+/// A star on the win screen that lights up once its timer runs out.
+#[derive(Component)]
+struct StarReveal(Timer);
+
+/// What a menu button does when pressed.
+#[derive(Component, Clone, Copy, PartialEq, Eq)]
+enum MenuButton {
+    Play,
+    Settings,
+    Stats,
+    Quit,
+    Resume,
+    Restart,
+    QuitToMenu,
+    SelectLevel(u32),
+    NextLevel,
+    Back,
+    Resolution(u32, u32),
+    Controls,
+    Rebind(BoundAction, KeySlot),
+    Difficulty(Difficulty),
+    Endless,
+    TimeAttack,
+    Survival,
+}
+
+#[derive(Resource)]
+pub struct GroundData {
+    pub center_y: f32,
+    pub top_y: f32,
+    pub height: f32,
+    /// Solid stretches of ground as `(left, right)` x coordinates; the
+    /// spaces between them are gaps.
+    pub segments: Vec<(f32, f32)>,
+}
+
+impl GroundData {
+    /// True if solid ground runs under the whole span `x ± half_width`.
+    fn supports(&self, x: f32, half_width: f32) -> bool {
+        self.segments
+            .iter()
+            .any(|&(left, right)| x - half_width >= left && x + half_width <= right)
+    }
+}
+
+/// Collision broad phase: every enemy and obstacle, filed under each grid
+/// cell its box overlaps. Rebuilt every frame so collision systems only test
+/// what is nearby instead of every pair.
+#[derive(Resource)]
+pub(crate) struct SpatialGrid {
+    cell_size: f32,
+    cells: HashMap<(i32, i32), Vec<Entity>>,
+}
+
+impl Default for SpatialGrid {
+    fn default() -> Self {
+        SpatialGrid {
+            cell_size: SPATIAL_CELL_SIZE,
+            cells: HashMap::new(),
+        }
+    }
+}
+
+impl SpatialGrid {
+    /// Empties every cell but keeps its allocation for the next rebuild.
+    pub(crate) fn clear(&mut self) {
+        for cell in self.cells.values_mut() {
+            cell.clear();
+        }
+    }
+
+    /// Inclusive cell coordinates covered by the box `center ± half`.
+    fn cell_range(&self, center: Vec2, half: Vec2) -> (IVec2, IVec2) {
+        let min = ((center - half) / self.cell_size).floor().as_ivec2();
+        let max = ((center + half) / self.cell_size).floor().as_ivec2();
+        (min, max)
+    }
+
+    pub(crate) fn insert(&mut self, entity: Entity, center: Vec2, half: Vec2) {
+        let (min, max) = self.cell_range(center, half);
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                self.cells.entry((x, y)).or_default().push(entity);
+            }
+        }
+    }
+
+    /// Every entity sharing a cell with the box `center ± half`, each once.
+    /// This is a superset of what actually overlaps the box.
+    pub(crate) fn query_region(&self, center: Vec2, half: Vec2) -> impl Iterator<Item = Entity> {
+        let (min, max) = self.cell_range(center, half);
+        let mut found = Vec::new();
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                if let Some(cell) = self.cells.get(&(x, y)) {
+                    found.extend_from_slice(cell);
+                }
+            }
+        }
+        found.sort_unstable();
+        found.dedup();
+        found.into_iter()
+    }
+}
+
+/// A solid box a ray can hit; `entity` is `None` for the ground.
+#[derive(Clone, Copy, Debug)]
+struct RayTarget {
+    entity: Option<Entity>,
+    center: Vec2,
+    half: Vec2,
+}
+
+/// Where a ray struck a `RayTarget`.
+#[derive(Clone, Copy, Debug)]
+struct RayHit {
+    // Nothing needs to know what was hit yet, only where.
+    #[allow(dead_code)]
+    entity: Option<Entity>,
+    point: Vec2,
+    distance: f32,
+}
+
+/// Casts a ray from `origin` along `direction` and returns the nearest
+/// target it hits within `max_dist`. Grazing a box's edge counts as a hit;
+/// a box the ray starts inside is ignored.
+fn raycast(origin: Vec2, direction: Vec2, max_dist: f32, targets: &[RayTarget]) -> Option<RayHit> {
+    let direction = direction.normalize_or_zero();
+    let mut nearest: Option<RayHit> = None;
+    for target in targets {
+        let min = target.center - target.half;
+        let max = target.center + target.half;
+        // Slab test: where the ray enters and leaves the box on each axis.
+        let mut enter = f32::NEG_INFINITY;
+        let mut exit = f32::INFINITY;
+        let mut missed = false;
+        for axis in 0..2 {
+            if direction[axis] == 0.0 {
+                missed |= origin[axis] < min[axis] || origin[axis] > max[axis];
+            } else {
+                let t1 = (min[axis] - origin[axis]) / direction[axis];
+                let t2 = (max[axis] - origin[axis]) / direction[axis];
+                enter = enter.max(t1.min(t2));
+                exit = exit.min(t1.max(t2));
+            }
+        }
+        if missed || enter > exit || enter < 0.0 || enter > max_dist {
+            continue;
+        }
+        if nearest.is_none_or(|hit| enter < hit.distance) {
+            nearest = Some(RayHit {
+                entity: target.entity,
+                point: origin + direction * enter,
+                distance: enter,
+            });
+        }
+    }
+    nearest
+}
+
+/// Everything that keeps count of enemies defeated: the run's tally, the
+/// lifetime stats and the recent kills adaptive difficulty looks at.
+#[derive(SystemParam)]
+struct KillTally<'w> {
+    run_stats: ResMut<'w, RunStats>,
+    stats: ResMut<'w, PlayerStats>,
+    tracker: ResMut<'w, PerformanceTracker>,
+    time: Res<'w, Time>,
+}
+
+impl KillTally<'_> {
+    fn record(&mut self) {
+        self.run_stats.enemies_stomped += 1;
+        self.stats.enemies_killed += 1;
+        self.tracker.record_kill(self.time.elapsed_seconds_f64());
+    }
+}
+
+/// Casts rays against what the player and chasers can stand on: the ground,
+/// obstacles (found through the `SpatialGrid`) and one-way platforms.
+#[derive(SystemParam)]
+struct Raycaster<'w, 's> {
+    grid: Res<'w, SpatialGrid>,
+    ground_data: Res<'w, GroundData>,
+    level_height: Option<Res<'w, LevelHeight>>,
+    obstacles:
+        Query<'w, 's, (&'static Transform, &'static Collider), (With<Obstacle>, Without<Grounded>)>,
+    platforms: Query<
+        'w,
+        's,
+        (Entity, &'static Transform, &'static Sprite),
+        (With<OneWayPlatform>, Without<Grounded>),
+    >,
+}
+
+impl Raycaster<'_, '_> {
+    fn cast(&self, origin: Vec2, direction: Vec2, max_dist: f32) -> Option<RayHit> {
+        let reach = direction.normalize_or_zero() * max_dist / 2.0;
+        let mut targets = Vec::new();
+        // The ground is a death zone in vertical mode, not something to stand on.
+        if self.level_height.is_none() {
+            for &(left, right) in &self.ground_data.segments {
+                targets.push(RayTarget {
+                    entity: None,
+                    center: Vec2::new(
+                        (left + right) / 2.0,
+                        self.ground_data.top_y - GAP_FALL_DEPTH / 2.0,
+                    ),
+                    half: Vec2::new((right - left) / 2.0, GAP_FALL_DEPTH / 2.0),
+                });
+            }
+        }
+        for entity in self.grid.query_region(origin + reach, reach.abs()) {
+            if let Ok((transform, collider)) = self.obstacles.get(entity) {
+                targets.push(RayTarget {
+                    entity: Some(entity),
+                    center: transform.translation.truncate(),
+                    half: collider.half_extents,
+                });
+            }
+        }
+        for (entity, transform, sprite) in self.platforms.iter() {
+            targets.push(RayTarget {
+                entity: Some(entity),
+                center: transform.translation.truncate(),
+                half: sprite.custom_size.unwrap_or(PLATFORM_SIZE) / 2.0,
+            });
+        }
+        raycast(origin, direction, max_dist, &targets)
+    }
+}
+
+/// Sent when a player runs out of health or falls out of the level.
+#[derive(Event)]
+struct PlayerDied(Entity);
+
+/// Sent when a player's death animation ends, for the death to be counted.
+#[derive(Event)]
+struct DeathAnimationFinished(Entity);
+
+/// Asks for a score popup such as "+100" at `position` in world space.
+#[derive(Event)]
+struct ScorePopup {
+    text: String,
+    position: Vec3,
+    color: Color,
+}
+
+/// Sent whenever the player takes damage, including the hit that costs a life.
+#[derive(Event)]
+struct PlayerHurt;
+
+/// Sent when endless mode sends in a new wave of enemies.
+#[derive(Event)]
+struct WaveAnnouncement {
+    wave: u32,
+}
+
+/// Sent when the camera's target jumps somewhere, such as on a respawn, so
+/// the camera cuts straight there instead of sweeping across the level.
+#[derive(Event)]
+struct CameraCut;
+
+/// Sent when a player's animation changes from one state to another.
+#[derive(Event)]
+struct AnimTransition {
+    entity: Entity,
+    from: PlayerAnim,
+    to: PlayerAnim,
+}
+
+/// How the player touched something this frame.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum CollisionKind {
+    /// Landed on an enemy from above.
+    Stomp,
+    /// Ran into an enemy from the side or below.
+    SideHit,
+    /// Overlapped an obstacle.
+    Obstacle,
+}
+
+/// Sent by `collision_detection_system` for every overlap between the
+/// player `a` and an enemy or obstacle `b`.
+#[derive(Event)]
+struct CollisionEvent {
+    a: Entity,
+    b: Entity,
+    kind: CollisionKind,
+}
+
+fn main() {
+    let mut app = App::new();
+    // `--vertical` starts the climbing level instead of the flat one, while
+    // `--goal` and `--door` swap clearing enemies for another objective.
+    let args: Vec<String> = std::env::args().collect();
+    let win_condition = if args.iter().any(|arg| arg == "--vertical") {
+        app.insert_resource(LevelHeight(LEVEL_HEIGHT));
+        WinCondition::ReachGoal
+    } else if args.iter().any(|arg| arg == "--goal") {
+        WinCondition::ReachGoal
+    } else if args.iter().any(|arg| arg == "--door") {
+        WinCondition::UnlockDoor
+    } else {
+        WinCondition::ClearEnemies
+    };
+    if args.iter().any(|arg| arg == "--endless") {
+        app.insert_resource(Endless);
+    }
+    if args.iter().any(|arg| arg == "--hardcore") {
+        app.insert_resource(Hardcore);
+    }
+    if args.iter().any(|arg| arg == "--timed") {
+        app.insert_resource(Timed);
+    }
+    if args.iter().any(|arg| arg == "--coop") {
+        app.insert_resource(Coop);
+    }
+    // `--seed N` replays the layouts of an earlier run.
+    let seed = args
+        .iter()
+        .position(|arg| arg == "--seed")
+        .and_then(|index| args.get(index + 1))
+        .and_then(|value| value.parse().ok())
+        .unwrap_or_else(|| rand::thread_rng().gen());
+    app.insert_resource(GameRng::new(seed));
+    // Settings are loaded first so the window opens at the saved resolution.
+    let settings: Settings = load_config_file(SETTINGS_PATH);
+    let window_plugin = WindowPlugin {
+        primary_window: Some(Window {
+            resolution: (settings.resolution.0 as f32, settings.resolution.1 as f32).into(),
+            ..default()
+        }),
+        ..default()
+    };
+    // Spatial sounds are placed in units of `ENEMY_SOUND_RANGE`, so only
+    // our own falloff applies within that range.
+    let audio_plugin = AudioPlugin {
+        spatial_scale: SpatialScale::new_2d(1.0 / ENEMY_SOUND_RANGE),
+        ..default()
+    };
+    app.add_plugins(DefaultPlugins.set(window_plugin).set(audio_plugin))
+        .add_plugins(FrameTimeDiagnosticsPlugin)
+        .insert_resource(SfxVolume(settings.audio.sfx_volume()))
+        .insert_resource(settings.audio.clone())
+        .insert_resource(settings.movement.clone())
+        .insert_resource(CameraZoom {
+            chosen: settings.zoom,
+            auto: settings.auto_zoom,
+            eased: settings.zoom,
+            pulse: None,
+        })
+        .insert_resource(settings)
+        .insert_resource(load_save_file::<KeyBindings>(&data_file(KEY_BINDINGS_FILE)))
+        .insert_resource(win_condition)
+        .insert_resource(DefaultWinCondition(win_condition))
+        .init_resource::<DoorObjective>()
+        .add_event::<PlayerDied>()
+        .add_event::<DeathAnimationFinished>()
+        .add_event::<PlayerHurt>()
+        .add_event::<ScorePopup>()
+        .add_event::<CameraCut>()
+        .add_event::<AnimTransition>()
+        .add_event::<WaveAnnouncement>()
+        .add_event::<CollisionEvent>()
+        .insert_resource(Gravity(GRAVITY_FORCE))
+        .insert_resource(Score(0))
+        .init_resource::<HighScore>()
+        .insert_resource(Lives(MAX_LIVES))
+        .init_resource::<RunStats>()
+        .init_resource::<ComboState>()
+        .init_resource::<CoinPitchTracker>()
+        .init_resource::<AllClearTimer>()
+        .init_resource::<SpatialGrid>()
+        .init_resource::<PlayerIntent>()
+        .init_resource::<Player2Intent>()
+        .init_resource::<VelocityLimits>()
+        .init_resource::<MaxDelta>()
+        .init_resource::<ScreenShake>()
+        .init_resource::<HitStop>()
+        .init_resource::<SlowMotion>()
+        .init_resource::<CameraConfig>()
+        .init_resource::<DebugOverlay>()
+        .init_resource::<MinimapHidden>()
+        .init_resource::<TutorialProgress>()
+        .init_resource::<Difficulty>()
+        .init_resource::<GameMode>()
+        .init_resource::<WaveSpawner>()
+        .init_resource::<DifficultyMultipliers>()
+        .init_resource::<PerformanceTracker>()
+        .insert_resource(Time::<Fixed>::from_hz(PHYSICS_HZ))
+        .configure_sets(
+            FixedUpdate,
+            (PhysicsSet::Restore, PhysicsSet::Step, PhysicsSet::Record).chain(),
+        )
+        .init_resource::<ActivityMultiplier>()
+        .init_resource::<LevelTimer>()
+        .init_resource::<TimeAttackTimer>()
+        .insert_resource(CurrentLevel(1))
+        .insert_resource(LevelCount(level_count()))
+        .init_resource::<TotalScore>()
+        .init_resource::<ProgressResource>()
+        .insert_resource(GroundData {
+            center_y: 0.0,
+            top_y: GROUND_HEIGHT / 2.0,
+            height: GROUND_HEIGHT,
+            segments: Vec::new(),
+        })
+        .add_state::<GameState>()
+        .add_systems(
+            Startup,
+            (spawn_camera, load_high_scores_system, load_stats_system),
+        )
+        .add_systems(
+            OnEnter(GameState::MainMenu),
+            (setup_main_menu, start_menu_music_system),
+        )
+        .add_systems(OnExit(GameState::MainMenu), cleanup_menu_system)
+        .add_systems(
+            Update,
+            main_menu_system.run_if(in_state(GameState::MainMenu)),
+        )
+        .add_systems(OnEnter(GameState::Settings), setup_settings_menu)
+        .add_systems(
+            OnExit(GameState::Settings),
+            (save_settings_system, cleanup_settings_menu),
+        )
+        .add_systems(
+            Update,
+            (
+                settings_menu_system,
+                slider_drag_system,
+                slider_thumb_system,
+            )
+                .run_if(in_state(GameState::Settings)),
+        )
+        .add_systems(OnEnter(GameState::Controls), setup_controls_menu)
+        .add_systems(OnExit(GameState::Controls), cleanup_controls_menu)
+        .add_systems(
+            Update,
+            controls_menu_system.run_if(in_state(GameState::Controls)),
+        )
+        .add_systems(
+            Update,
+            (
+                apply_settings_system.run_if(resource_changed::<Settings>()),
+                apply_audio_settings_system
+                    .after(apply_settings_system)
+                    .run_if(resource_changed::<AudioSettings>()),
+                apply_max_delta_system.run_if(resource_changed::<MaxDelta>()),
+                hit_stop_system,
+            ),
+        )
+        .add_systems(OnEnter(GameState::Stats), setup_stats_screen)
+        .add_systems(OnExit(GameState::Stats), cleanup_stats_screen)
+        .add_systems(
+            Update,
+            stats_screen_system.run_if(in_state(GameState::Stats)),
+        )
+        // Pausing, dying or winning all leave `Playing`, so stats are saved
+        // whenever a stretch of play ends.
+        .add_systems(
+            OnExit(GameState::Playing),
+            (save_stats_system, save_zoom_system, clear_hazard_warnings),
+        )
+        .add_systems(OnEnter(GameState::LevelSelect), setup_level_select)
+        .add_systems(OnExit(GameState::LevelSelect), cleanup_level_select)
+        .add_systems(
+            Update,
+            level_select_system.run_if(in_state(GameState::LevelSelect)),
+        )
+        .add_systems(
+            OnEnter(GameState::Paused),
+            (setup_pause_menu, pause_bgm_system),
+        )
+        .add_systems(
+            OnExit(GameState::Paused),
+            (cleanup_pause_menu, clear_paused_input),
+        )
+        .add_systems(
+            Update,
+            (
+                resume_input_system,
+                pause_menu_navigation_system.before(run_menu_system),
+            )
+                .run_if(in_state(GameState::Paused)),
+        )
+        .add_systems(OnEnter(GameState::GameOver), enter_game_over)
+        .add_systems(OnExit(GameState::GameOver), cleanup_game_over_screen)
+        .add_systems(
+            Update,
+            (
+                game_over_screen_system,
+                game_over_keys_system.before(run_menu_system),
+            )
+                .run_if(in_state(GameState::GameOver)),
+        )
+        .add_systems(OnEnter(GameState::Win), (setup_win_screen, spawn_win_flash))
+        .add_systems(OnExit(GameState::Win), cleanup_win_screen)
+        .add_systems(
+            Update,
+            (
+                star_reveal_system,
+                level_advance_system,
+                screen_flash_system,
+            )
+                .run_if(in_state(GameState::Win)),
+        )
+        .add_systems(OnEnter(GameState::Victory), setup_victory_screen)
+        .add_systems(OnExit(GameState::Victory), cleanup_victory_screen)
+        .add_systems(
+            Update,
+            run_menu_system.run_if(
+                in_state(GameState::Paused)
+                    .or_else(in_state(GameState::GameOver))
+                    .or_else(in_state(GameState::Win))
+                    .or_else(in_state(GameState::Victory)),
+            ),
+        )
+        .add_systems(Update, fade_in_system)
+        // Life icon shards keep flying, and the lost icon keeps shrinking,
+        // once the game-over screen is up.
+        .add_systems(Update, (life_icon_shard_system, lost_life_icon_system))
+        // A fatal hit still flashes and sounds over the game-over screen.
+        .add_systems(
+            Update,
+            (
+                hurt_feedback_system.run_if(on_event::<PlayerHurt>()),
+                damage_vignette_system,
+            ),
+        )
+        .add_systems(Update, button_color_system)
+        .add_systems(
+            Update,
+            (
+                toggle_debug_overlay_system,
+                spawn_debug_hud_system.run_if(resource_changed::<DebugOverlay>()),
+                update_debug_hud_system,
+            )
+                .chain(),
+        )
+        .add_systems(
+            Update,
+            (toggle_minimap_system, minimap_system)
+                .chain()
+                .run_if(in_state(GameState::Playing)),
+        )
+        .add_systems(Update, audio_cleanup_system)
+        .add_systems(
+            Update,
+            (start_walk_sound_system, enemy_walk_sound_system).chain(),
+        )
+        .add_systems(OnEnter(GameState::Playing), start_bgm_system)
+        .init_resource::<JingleState>()
+        .add_systems(OnEnter(GameState::Win), play_jingle_system)
+        .add_systems(OnEnter(GameState::GameOver), play_jingle_system)
+        .add_systems(
+            Update,
+            jingle_system.run_if(in_state(GameState::GameOver).or_else(in_state(GameState::Win))),
+        )
+        .add_systems(
+            OnEnter(GameState::Playing),
+            (
+                apply_difficulty_system,
+                reseed_rng_system,
+                load_level_config_system,
+                // Setup sizes the level from the file loaded above.
+                apply_deferred,
+                setup,
+                (
+                    spawn_tutorial.run_if(
+                        resource_equals(CurrentLevel(1)).and_then(resource_equals(GameMode::Story)),
+                    ),
+                    spawn_controls_hint,
+                ),
+                spawn_platforms.run_if(resource_exists::<LevelHeight>()),
+                // Enemies look up the platforms spawned above.
+                apply_deferred,
+                apply_level_tuning,
+                spawn_tilemap_system.run_if(resource_exists::<TileMap>()),
+                spawn_parallax_layers.run_if(not(resource_exists::<TileMap>())),
+                load_level.run_if(uses_level_layout),
+                spawn_obstacles
+                    .run_if(not(resource_exists::<LevelHeight>()).and_then(not(uses_level_layout))),
+                spawn_breakable_blocks
+                    .run_if(not(resource_exists::<LevelHeight>()).and_then(not(uses_level_layout))),
+                spawn_random_water
+                    .run_if(not(resource_exists::<LevelHeight>()).and_then(not(uses_level_layout))),
+                spawn_key_and_door.run_if(resource_equals(WinCondition::UnlockDoor)),
+                spawn_goal_flag.run_if(
+                    resource_equals(WinCondition::ReachGoal)
+                        .and_then(not(resource_exists::<LevelHeight>())),
+                ),
+                // Enemies and coins keep clear of the obstacles spawned above.
+                apply_deferred,
+                spawn_enemies.run_if(not(uses_level_layout)),
+                (spawn_coins, reset_hazard_spawner_system).chain(),
+            )
+                .chain()
+                .run_if(not(resource_exists::<LevelLoaded>())),
+        )
+        // Physics runs at a fixed rate so jumps and collisions don't depend
+        // on the frame rate.
+        .add_systems(
+            FixedUpdate,
+            (
+                restore_interpolated_system.in_set(PhysicsSet::Restore),
+                record_interpolated_system.in_set(PhysicsSet::Record),
+                falling_hazard_system.in_set(PhysicsSet::Step),
+            )
+                .run_if(in_state(GameState::Playing)),
+        )
+        .add_systems(
+            FixedUpdate,
+            (
+                player_control_system,
+                apply_gravity_system,
+                water_physics_system
+                    .after(player_control_system)
+                    .before(movement_system),
+                (
+                    clamp_velocity_system
+                        .after(apply_gravity_system)
+                        .after(water_physics_system),
+                    record_prev_translation_system,
+                    movement_system,
+                )
+                    .chain(),
+                ladder_overlap_system.after(movement_system),
+                player_bounds_system,
+                enemy_bounds_system,
+                enemy_obstacle_collision_system.after(rebuild_spatial_grid_system),
+                (
+                    (enemy_ai_system, patrol_path_system)
+                        .chain()
+                        .before(enemy_patrol_system),
+                    enemy_patrol_system,
+                    (pathfind_system, pathfinder_steering_system)
+                        .chain()
+                        .before(apply_gravity_system),
+                ),
+                collision_system,
+                // Slopes aren't boxes, so they set `Grounded` after the probe.
+                (
+                    one_way_platform_system,
+                    slope_system.after(ground_probe_system),
+                )
+                    .after(collision_system),
+                rebuild_spatial_grid_system
+                    .after(collision_system)
+                    .after(enemy_bounds_system)
+                    .after(enemy_patrol_system),
+                (
+                    collision_detection_system,
+                    stomp_system,
+                    last_enemy_slow_motion_system,
+                    player_damage_system,
+                    obstacle_block_system,
+                    ground_probe_system
+                        .after(one_way_platform_system)
+                        .after(door_collision_system),
+                )
+                    .chain()
+                    .after(rebuild_spatial_grid_system),
+                door_collision_system.after(collision_system),
+                conveyor_system.after(obstacle_block_system),
+                fall_death_system,
+                enemy_gap_system.run_if(not(resource_exists::<LevelHeight>())),
+                (
+                    start_dying_system,
+                    dying_animation_system,
+                    player_death_system,
+                )
+                    .chain(),
+                invulnerability_system,
+            )
+                .in_set(PhysicsSet::Step)
+                .run_if(in_state(GameState::Playing)),
+        )
+        .add_systems(
+            Update,
+            (
+                (
+                    player_input_system,
+                    player2_input_system.run_if(resource_exists::<Coop>()),
+                ),
+                coin_collection_system,
+                coin_bob_system,
+                goal_flag_wave_system,
+                key_collection_system,
+                checkpoint_system,
+                level_timer_system,
+                play_time_system,
+                combo_decay_system,
+                (spawn_score_popup_system, floating_text_system).chain(),
+                activity_multiplier_system,
+                dash_system,
+                debris_system,
+                conveyor_stripe_system,
+                tutorial_system.run_if(
+                    resource_equals(CurrentLevel(1)).and_then(resource_equals(GameMode::Story)),
+                ),
+                controls_hint_system,
+                hazard_spawner_system,
+                hazard_warning_system,
+                adaptive_difficulty_system.run_if(adaptive_difficulty_enabled),
+                wave_spawner_system.run_if(sends_waves),
+            )
+                .run_if(in_state(GameState::Playing)),
+        )
+        .add_systems(
+            Update,
+            (
+                interpolate_transform_system.before(camera_follow_system),
+                (
+                    remove_screen_shake_system,
+                    zoom_input_system,
+                    camera_zoom_system,
+                    camera_follow_system,
+                    draw_deadzone_system.run_if(resource_equals(DebugOverlay(true))),
+                    screen_shake_system,
+                    parallax_system,
+                )
+                    .chain(),
+                player_shadow_system.after(interpolate_transform_system),
+                update_score_system,
+                update_coin_text_system,
+                combo_ui_system,
+                update_activity_hud_system,
+                update_health_bar_system,
+                update_lives_ui_system,
+                update_timer_text_system,
+                update_run_timer_text_system,
+                update_enemy_count_system.before(check_end_game_system),
+                update_key_icon_system,
+                banner_system,
+                check_end_game_system,
+                pause_input_system,
+                update_gamepad_text_system,
+                gamepad_rumble_system,
+            )
+                .run_if(in_state(GameState::Playing)),
+        )
+        .add_systems(
+            Update,
+            (
+                (
+                    process_player_anim_transitions_system,
+                    on_anim_transition_system,
+                    animate_player_system,
+                )
+                    .chain(),
+                hit_flash_system,
+                update_enemy_health_bar_system,
+            )
+                .run_if(in_state(GameState::Playing)),
+        );
+    // Hitboxes and velocities are for development and left out of release
+    // builds.
+    #[cfg(debug_assertions)]
+    app.add_systems(
+        Update,
+        (
+            draw_hitboxes_system.run_if(resource_equals(DebugOverlay(true))),
+            debug_label_system,
+        ),
+    );
+    app.run();
+}
+
+//
+// SETUP SYSTEMS
+//
+
+/// Spawns the 2D camera shared by the menus and the game.
+fn spawn_camera(mut commands: Commands) {
+    commands.spawn(Camera2dBundle::default());
+}
+
+fn level_config_path(level: u32) -> String {
+    format!("{}/level{}.ron", LEVEL_DIR, level)
+}
+
+/// Number of consecutive level files starting from `level1.ron`.
+fn level_count() -> u32 {
+    let mut count = 0;
+    while std::path::Path::new(&level_config_path(count + 1)).exists() {
+        count += 1;
+    }
+    count
+}
+
+fn read_level_config(level: u32) -> Result<LevelConfig, String> {
+    let path = level_config_path(level);
+    let contents = std::fs::read_to_string(&path)
+        .map_err(|err| format!("Could not read {}: {}", path, err))?;
+    ron::from_str(&contents).map_err(|err| format!("Invalid level file {}: {}", path, err))
+}
+
+/// Restarts the level generator from the run's seed.
+fn reseed_rng_system(
+    mut game_rng: ResMut<GameRng>,
+    current_level: Res<CurrentLevel>,
+    game_mode: Res<GameMode>,
+) {
+    if *game_mode == GameMode::TimeAttack {
+        game_rng.rng = StdRng::seed_from_u64(TIME_ATTACK_SEED.wrapping_add(current_level.0 as u64));
+    } else {
+        game_rng.reseed(current_level.0);
+        info!("Level {} from seed {}", current_level.0, game_rng.seed);
+    }
+}
+
+/// Reads the current level's file and inserts its `LevelConfig` and `TileMap`.
+/// A missing or invalid file leaves the level without background tiles.
+fn load_level_config_system(mut commands: Commands, current_level: Res<CurrentLevel>) {
+    match read_level_config(current_level.0) {
+        Ok(config) => {
+            commands.insert_resource(TileMap::from(&config));
+            commands.insert_resource(config);
+        }
+        Err(err) => error!("{}", err),
+    }
+}
+
+/// Initializes the ground, UI text, and player.
+fn setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    level_height: Option<Res<LevelHeight>>,
+    high_scores: Res<HighScores>,
+    current_level: Res<CurrentLevel>,
+    difficulty: Res<Difficulty>,
+    mut game_rng: ResMut<GameRng>,
+    level_config: Option<Res<LevelConfig>>,
+    endless: Option<Res<Endless>>,
+    game_mode: Res<GameMode>,
+    coop: Option<Res<Coop>>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    let window = window_query.single();
+
+    // Random flat levels span several screens. Hand-placed layouts and the
+    // vertical level are one window wide.
+    let layout = active_layout(
+        level_config.as_deref(),
+        level_height.is_some(),
+        endless.is_some() || *game_mode == GameMode::Endless,
+    );
+    let screens = if level_height.is_none() && layout.is_none() {
+        LEVEL_WIDTH_SCREENS
+    } else {
+        1.0
+    };
+    let min_x = -window.width() / 2.0;
+    let bounds = LevelBounds {
+        min_x,
+        max_x: min_x + window.width() * screens,
+    };
+    commands.insert_resource(bounds);
+
+    commands.insert_resource(LevelLoaded);
+    commands.insert_resource(CoinAssets {
+        mesh: meshes.add(shape::Circle::new(COIN_RADIUS).into()),
+        material: materials.add(ColorMaterial::from(Color::YELLOW)),
+    });
+    commands.insert_resource(AudioHandles {
+        jump: asset_server.load("jump.ogg"),
+        stomp: asset_server.load("stomp.ogg"),
+        hurt: asset_server.load("hurt.ogg"),
+        coin: asset_server.load("coin.ogg"),
+        walk: asset_server.load("walk.ogg"),
+    });
+
+    // Calculate ground positions. In vertical mode the ground sits at the
+    // bottom of the screen as a death zone and the player starts on a platform.
+    let ground_center_y = if level_height.is_some() {
+        -window.height() / 2.0 + GROUND_HEIGHT / 2.0
+    } else {
+        0.0
+    };
+    let ground_top_y = ground_center_y + GROUND_HEIGHT / 2.0;
+    let spawn_top_y = GROUND_HEIGHT / 2.0;
+
+    // Random multi-screen levels get gaps in the ground, keeping the first
+    // screen and the right edge solid for the spawn point and the goal.
+    let segments = if screens > 1.0 {
+        generate_ground_segments(
+            &mut game_rng.rng,
+            &bounds,
+            bounds.min_x + window.width(),
+            bounds.max_x - window.width() / 2.0,
+        )
+    } else {
+        vec![(bounds.min_x, bounds.max_x)]
+    };
+
+    // Spawn one ground sprite per solid stretch.
+    for &(left, right) in &segments {
+        commands.spawn((
+            SpriteBundle {
+                sprite: Sprite {
+                    color: if level_height.is_some() {
+                        Color::rgb(0.8, 0.2, 0.2)
+                    } else {
+                        Color::rgb(0.2, 0.8, 0.2)
+                    },
+                    custom_size: Some(Vec2::new(right - left, GROUND_HEIGHT)),
+                    ..default()
+                },
+                transform: Transform::from_xyz((left + right) / 2.0, ground_center_y, 0.0),
+                ..default()
+            },
+            Ground,
+            GameEntity,
+        ));
+    }
+
+    // Spawn the fall death zone below the ground, wider than the level so
+    // there's no way around it.
+    let fall_top = if level_height.is_some() {
+        -window.height() / 2.0
+    } else {
+        ground_top_y - GAP_FALL_DEPTH
+    };
+    commands.spawn((
+        SpriteBundle {
+            sprite: Sprite {
+                custom_size: Some(Vec2::new(
+                    bounds.width() + 2.0 * window.width(),
+                    FALL_ZONE_DEPTH,
+                )),
+                ..default()
+            },
+            transform: Transform::from_xyz(
+                (bounds.min_x + bounds.max_x) / 2.0,
+                fall_top - FALL_ZONE_DEPTH / 2.0,
+                0.0,
+            ),
+            visibility: Visibility::Hidden,
+            ..default()
+        },
+        FallDeathZone,
+        GameEntity,
+    ));
+
+    // Update the GroundData resource.
+    commands.insert_resource(GroundData {
+        center_y: ground_center_y,
+        top_y: ground_top_y,
+        height: GROUND_HEIGHT,
+        segments,
+    });
+
+    // Spawn score UI, with the level's saved best beside it.
+    let best = high_scores
+        .best_in(*game_mode, current_level.0)
+        .unwrap_or(0);
+    commands.insert_resource(HighScore(best));
+    commands.spawn((
+        TextBundle {
+            text: Text::from_sections([
+                TextSection::new(
+                    "Score: 0",
+                    TextStyle {
+                        font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+                        font_size: 40.0,
+                        color: Color::WHITE,
+                    },
+                ),
+                TextSection::new(
+                    format!("  Best: {}", best),
+                    TextStyle {
+                        font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+                        font_size: 30.0,
+                        color: Color::WHITE,
+                    },
+                ),
+            ]),
+            style: Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(10.0),
+                right: Val::Px(10.0),
+                ..default()
+            },
+            ..default()
+        },
+        ScoreText,
+        GameEntity,
+    ));
+
+    // Spawn coin counter UI below the score.
+    commands.spawn((
+        TextBundle {
+            text: Text::from_section(
+                "Coins: 0",
+                TextStyle {
+                    font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+                    font_size: 30.0,
+                    color: Color::YELLOW,
+                },
+            ),
+            style: Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(55.0),
+                right: Val::Px(10.0),
+                ..default()
+            },
+            ..default()
+        },
+        CoinText,
+        GameEntity,
+    ));
+
+    // Spawn the combo multiplier and its bar below the coins, hidden until
+    // a combo starts.
+    let mut pulse = Timer::from_seconds(COMBO_PULSE_DURATION, TimerMode::Once);
+    pulse.tick(pulse.duration());
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    top: Val::Px(90.0),
+                    right: Val::Px(10.0),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::FlexEnd,
+                    row_gap: Val::Px(2.0),
+                    ..default()
+                },
+                visibility: Visibility::Hidden,
+                ..default()
+            },
+            ComboUI { shown: 0, pulse },
+            GameEntity,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+                        font_size: COMBO_FONT_SIZE,
+                        color: Color::ORANGE,
+                    },
+                ),
+                ComboHudText,
+            ));
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        width: Val::Px(COMBO_BAR_SIZE.x),
+                        height: Val::Px(COMBO_BAR_SIZE.y),
+                        ..default()
+                    },
+                    background_color: Color::rgba(0.0, 0.0, 0.0, 0.5).into(),
+                    ..default()
+                })
+                .with_children(|bar| {
+                    bar.spawn((
+                        NodeBundle {
+                            style: Style {
+                                width: Val::Percent(100.0),
+                                height: Val::Percent(100.0),
+                                ..default()
+                            },
+                            background_color: Color::ORANGE.into(),
+                            ..default()
+                        },
+                        ComboBarFill,
+                    ));
+                });
+        });
+
+    // Spawn the hot-streak multiplier and its draining bar below the combo.
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    top: Val::Px(125.0),
+                    right: Val::Px(10.0),
+                    align_items: AlignItems::Center,
+                    column_gap: Val::Px(8.0),
+                    ..default()
+                },
+                ..default()
+            },
+            GameEntity,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle::from_section(
+                    "x1",
+                    TextStyle {
+                        font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+                        font_size: 24.0,
+                        color: Color::ORANGE_RED,
+                    },
+                ),
+                ActivityText,
+            ));
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        width: Val::Px(ACTIVITY_BAR_SIZE.x),
+                        height: Val::Px(ACTIVITY_BAR_SIZE.y),
+                        ..default()
+                    },
+                    background_color: Color::rgb(0.2, 0.2, 0.2).into(),
+                    ..default()
+                })
+                .with_children(|bar| {
+                    bar.spawn((
+                        NodeBundle {
+                            style: Style {
+                                width: Val::Percent(0.0),
+                                height: Val::Percent(100.0),
+                                ..default()
+                            },
+                            background_color: Color::ORANGE_RED.into(),
+                            ..default()
+                        },
+                        ActivityBarFill,
+                    ));
+                });
+        });
+
+    // Spawn the enemy counter centred at the top, above the countdown that
+    // stays empty unless the level is timed, and the run clock.
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    width: Val::Percent(100.0),
+                    top: Val::Px(10.0),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                ..default()
+            },
+            GameEntity,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+                        font_size: 30.0,
+                        color: Color::WHITE,
+                    },
+                ),
+                EnemyCountText,
+            ));
+            parent.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+                        font_size: 50.0,
+                        color: Color::WHITE,
+                    },
+                ),
+                TimerText,
+            ));
+            parent.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+                        font_size: 24.0,
+                        color: Color::WHITE,
+                    },
+                ),
+                RunTimerText::default(),
+            ));
+        });
+
+    // Spawn the seed in the bottom-left corner so a run can be replayed.
+    commands.spawn((
+        TextBundle {
+            text: Text::from_section(
+                format!("Seed: {}", game_rng.seed),
+                TextStyle {
+                    font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+                    font_size: 20.0,
+                    color: Color::GRAY,
+                },
+            ),
+            style: Style {
+                position_type: PositionType::Absolute,
+                bottom: Val::Px(10.0),
+                left: Val::Px(10.0),
+                ..default()
+            },
+            ..default()
+        },
+        SeedText,
+        GameEntity,
+    ));
+
+    // The difficulty sits just above the seed.
+    commands.spawn((
+        TextBundle {
+            text: Text::from_section(
+                difficulty.label(),
+                TextStyle {
+                    font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+                    font_size: 20.0,
+                    color: Color::GRAY,
+                },
+            ),
+            style: Style {
+                position_type: PositionType::Absolute,
+                bottom: Val::Px(35.0),
+                left: Val::Px(10.0),
+                ..default()
+            },
+            ..default()
+        },
+        GameEntity,
+    ));
+
+    // Spawn the controller status in the bottom-right corner.
+    commands.spawn((
+        TextBundle {
+            text: Text::from_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+                    font_size: 20.0,
+                    color: Color::GRAY,
+                },
+            ),
+            style: Style {
+                position_type: PositionType::Absolute,
+                bottom: Val::Px(10.0),
+                right: Val::Px(10.0),
+                ..default()
+            },
+            ..default()
+        },
+        GamepadConnectedText,
+        GameEntity,
+    ));
+
+    spawn_health_bar(&mut commands, false);
+
+    // Spawn the row of life icons under the health bar. It fills in once
+    // the lives UI system sees the `Lives` resource.
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(LIVES_TOP),
+                left: Val::Px(10.0),
+                height: Val::Px(LIFE_ICON_SIZE),
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            ..default()
+        },
+        LivesUI,
+        GameEntity,
+    ));
+
+    // Spawn the minimap along the top edge with its pool of dots, all
+    // hidden until the minimap system places them.
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    top: Val::Px(0.0),
+                    width: Val::Percent(100.0),
+                    height: Val::Px(MINIMAP_HEIGHT),
+                    ..default()
+                },
+                background_color: MINIMAP_BACKGROUND.into(),
+                visibility: Visibility::Hidden,
+                ..default()
+            },
+            Minimap,
+            GameEntity,
+        ))
+        .with_children(|parent| {
+            for _ in 0..MINIMAP_POOL_SIZE {
+                parent.spawn((
+                    NodeBundle {
+                        style: Style {
+                            position_type: PositionType::Absolute,
+                            top: Val::Px((MINIMAP_HEIGHT - MINIMAP_DOT_SIZE) / 2.0),
+                            width: Val::Px(MINIMAP_DOT_SIZE),
+                            height: Val::Px(MINIMAP_DOT_SIZE),
+                            margin: UiRect::left(Val::Px(-MINIMAP_DOT_SIZE / 2.0)),
+                            ..default()
+                        },
+                        visibility: Visibility::Hidden,
+                        ..default()
+                    },
+                    MinimapDot,
+                ));
+            }
+        });
+
+    // Spawn the key icon beside the health bar, hidden until the key is
+    // picked up.
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(14.0),
+                left: Val::Px(HEALTH_BAR_SIZE.x + 20.0),
+                width: Val::Px(KEY_SIZE.x),
+                height: Val::Px(KEY_SIZE.y),
+                ..default()
+            },
+            background_color: Color::GOLD.into(),
+            visibility: Visibility::Hidden,
+            ..default()
+        },
+        KeyIcon,
+        GameEntity,
+    ));
+
+    // Spawn the player so its bottom touches the ground.
+    // Center is ground top + half the player height.
+    let player_pos = Vec3::new(0.0, spawn_top_y + PLAYER_SIZE.y / 2.0, 0.0);
+    commands.insert_resource(RespawnPoint(player_pos));
+    commands.spawn((
+        SpriteBundle {
+            texture: asset_server.load("player.png"),
+            sprite: Sprite {
+                custom_size: Some(PLAYER_SIZE),
+                ..default()
+            },
+            transform: Transform::from_translation(player_pos),
+            ..default()
+        },
+        Player,
+        CameraTarget,
+        // Hears enemy footsteps from the side they're on.
+        SpatialListener::new(ENEMY_SOUND_RANGE),
+        Collider::from_size(PLAYER_SIZE),
+        Velocity(Vec2::ZERO),
+        GravityScale(1.0),
+        Interpolated::default(),
+        PrevTranslation(player_pos),
+        Grounded(true),
+        ChainBounce::default(),
+        Health::full(PLAYER_MAX_HEALTH),
+        PlayerAnimStateMachine::default(),
+        AnimFrame::default(),
+        GameEntity,
+    ));
+    commands.spawn((
+        SpriteBundle {
+            texture: images.add(oval_image(SHADOW_SIZE)),
+            sprite: Sprite {
+                color: Color::BLACK.with_a(SHADOW_ALPHA),
+                custom_size: Some(SHADOW_SIZE),
+                ..default()
+            },
+            transform: Transform::from_xyz(player_pos.x, spawn_top_y + 1.0, -0.1),
+            ..default()
+        },
+        PlayerShadow,
+        GameEntity,
+    ));
+
+    // Player 2 joins beside player 1 in co-op, with a health bar of their own.
+    if coop.is_some() {
+        let player2_pos = player_pos + Vec3::new(PLAYER2_OFFSET, 0.0, 0.0);
+        commands.spawn((
+            SpriteBundle {
+                texture: asset_server.load("player.png"),
+                sprite: Sprite {
+                    color: PLAYER2_TINT,
+                    custom_size: Some(PLAYER_SIZE),
+                    ..default()
+                },
+                transform: Transform::from_translation(player2_pos),
+                ..default()
+            },
+            Player,
+            Player2,
+            Collider::from_size(PLAYER_SIZE),
+            Velocity(Vec2::ZERO),
+            GravityScale(1.0),
+            Interpolated::default(),
+            PrevTranslation(player2_pos),
+            Grounded(true),
+            ChainBounce::default(),
+            Health::full(PLAYER_MAX_HEALTH),
+            PlayerAnimStateMachine::default(),
+            AnimFrame::default(),
+            GameEntity,
+        ));
+        spawn_health_bar(&mut commands, true);
+    }
+}
+
+/// A white oval filling a `size` texture, with a clear background, for
+/// tinting with a sprite's color.
+fn oval_image(size: Vec2) -> Image {
+    let (width, height) = (size.x as u32, size.y as u32);
+    let mut data = Vec::with_capacity((width * height * 4) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let dx = (x as f32 + 0.5) / width as f32 * 2.0 - 1.0;
+            let dy = (y as f32 + 0.5) / height as f32 * 2.0 - 1.0;
+            let alpha = if dx * dx + dy * dy <= 1.0 { 255 } else { 0 };
+            data.extend_from_slice(&[255, 255, 255, alpha]);
+        }
+    }
+    Image::new(
+        Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    )
+}
+
+/// Spawns the first control hint at the bottom of the screen on its
+/// semi-transparent panel.
+fn spawn_tutorial(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    width: Val::Percent(100.0),
+                    bottom: Val::Px(60.0),
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                ..default()
+            },
+            GameEntity,
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        padding: UiRect::axes(Val::Px(20.0), Val::Px(10.0)),
+                        ..default()
+                    },
+                    background_color: Color::rgba(0.0, 0.0, 0.0, 0.6).into(),
+                    ..default()
+                })
+                .with_children(|panel| {
+                    panel.spawn((
+                        TextBundle::from_section(
+                            TUTORIAL_HINTS[0],
+                            TextStyle {
+                                font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+                                font_size: 30.0,
+                                color: Color::WHITE,
+                            },
+                        ),
+                        TutorialText {
+                            index: 0,
+                            display_timer: Timer::from_seconds(
+                                TUTORIAL_HINT_DURATION + TUTORIAL_FADE_DURATION,
+                                TimerMode::Once,
+                            ),
+                        },
+                    ));
+                });
+        });
+}
+
+/// The controls card's text, such as "A/D or ←/→ to move, Space to jump".
+/// The secondary movement keys only show up when both directions have one.
+fn controls_hint_text(key_bindings: &KeyBindings) -> String {
+    let (left, right) = (key_bindings.left, key_bindings.right);
+    let mut move_keys = format!("{}/{}", key_name(left.primary), key_name(right.primary));
+    if let (Some(left), Some(right)) = (left.secondary, right.secondary) {
+        move_keys = format!("{}/{} or {}", key_name(left), key_name(right), move_keys);
+    }
+    format!(
+        "{} to move, {} to jump",
+        move_keys,
+        key_name(key_bindings.jump.primary)
+    )
+}
+
+/// Spawns a card with the move and jump keys in the middle of the screen
+/// on the first run since launch, or on every run with
+/// `always_show_controls` on.
+fn spawn_controls_hint(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    settings: Res<Settings>,
+    key_bindings: Res<KeyBindings>,
+    mut progress: ResMut<TutorialProgress>,
+) {
+    if progress.card_shown && !settings.always_show_controls {
+        return;
+    }
+    *progress = TutorialProgress {
+        card_shown: true,
+        ..default()
+    };
+    let text = controls_hint_text(&key_bindings);
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    width: Val::Percent(100.0),
+                    top: Val::Percent(30.0),
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                ..default()
+            },
+            GameEntity,
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn((
+                    NodeBundle {
+                        style: Style {
+                            padding: UiRect::axes(Val::Px(20.0), Val::Px(10.0)),
+                            ..default()
+                        },
+                        background_color: Color::rgba(0.0, 0.0, 0.0, 0.6).into(),
+                        ..default()
+                    },
+                    ControlsHint {
+                        shown: Timer::from_seconds(CONTROLS_HINT_DURATION, TimerMode::Once),
+                        fade: Timer::from_seconds(TUTORIAL_FADE_DURATION, TimerMode::Once),
+                    },
+                ))
+                .with_children(|panel| {
+                    panel.spawn(TextBundle::from_section(
+                        text,
+                        TextStyle {
+                            font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+                            font_size: 30.0,
+                            color: Color::WHITE,
+                        },
+                    ));
+                });
+        });
+}
+
+/// Applies the level's gravity, ground color, time limit and win condition
+/// overrides, or restores the defaults when the level has none. Only story
+/// and survival are played against the clock, survival always for
+/// `SURVIVAL_DURATION`.
+fn apply_level_tuning(
+    level_config: Option<Res<LevelConfig>>,
+    level_height: Option<Res<LevelHeight>>,
+    timed: Option<Res<Timed>>,
+    game_mode: Res<GameMode>,
+    default_win_condition: Res<DefaultWinCondition>,
+    mut win_condition: ResMut<WinCondition>,
+    mut gravity: ResMut<Gravity>,
+    mut level_timer: ResMut<LevelTimer>,
+    mut ground_query: Query<&mut Sprite, With<Ground>>,
+) {
+    let config = level_config.as_deref();
+    gravity.0 = config.and_then(|c| c.gravity).unwrap_or(GRAVITY_FORCE);
+    let time_limit = match *game_mode {
+        GameMode::Story => config
+            .and_then(|c| c.time_limit)
+            .or(timed.map(|_| TIME_LIMIT)),
+        GameMode::Survival => Some(SURVIVAL_DURATION),
+        GameMode::Endless | GameMode::TimeAttack => None,
+    };
+    level_timer.remaining = time_limit.map(|seconds| Timer::from_seconds(seconds, TimerMode::Once));
+
+    // The vertical level keeps its own objective and death-zone ground.
+    if level_height.is_some() {
+        return;
+    }
+    // Time attack is always a race to clear the enemies.
+    *win_condition = if *game_mode == GameMode::TimeAttack {
+        WinCondition::ClearEnemies
+    } else {
+        config
+            .and_then(|c| c.win_condition)
+            .unwrap_or(default_win_condition.0)
+    };
+    if let Some((r, g, b)) = config.and_then(|c| c.ground_color) {
+        for mut sprite in ground_query.iter_mut() {
+            sprite.color = Color::rgb(r, g, b);
+        }
+    }
+}
+
+/// Splits the ground into solid stretches with gaps between them. Gaps only
+/// open between `solid_until` and `solid_from`.
+fn generate_ground_segments(
+    rng: &mut StdRng,
+    bounds: &LevelBounds,
+    solid_until: f32,
+    solid_from: f32,
+) -> Vec<(f32, f32)> {
+    let mut segments = Vec::new();
+    let mut start = bounds.min_x;
+    let mut gap_left = solid_until + rng.gen_range(0.0..GAP_SPACING_RANGE.0);
+    loop {
+        let gap_width = rng.gen_range(GAP_WIDTH_RANGE.0..GAP_WIDTH_RANGE.1);
+        if gap_left + gap_width > solid_from {
+            break;
+        }
+        segments.push((start, gap_left));
+        start = gap_left + gap_width;
+        gap_left = start + rng.gen_range(GAP_SPACING_RANGE.0..GAP_SPACING_RANGE.1);
+    }
+    segments.push((start, bounds.max_x));
+    segments
+}
+
+/// The hand-placed layout to build the current level from, if any.
+/// Vertical mode, endless mode and `--endless` always generate the level
+/// randomly.
+fn active_layout(
+    level_config: Option<&LevelConfig>,
+    vertical: bool,
+    endless: bool,
+) -> Option<&LevelDef> {
+    if vertical || endless {
+        return None;
+    }
+    level_config.and_then(|config| config.layout.as_ref())
+}
+
+/// True when the current level file has a hand-placed layout to use.
+fn uses_level_layout(
+    level_config: Option<Res<LevelConfig>>,
+    level_height: Option<Res<LevelHeight>>,
+    endless: Option<Res<Endless>>,
+    game_mode: Res<GameMode>,
+) -> bool {
+    active_layout(
+        level_config.as_deref(),
+        level_height.is_some(),
+        endless.is_some() || *game_mode == GameMode::Endless,
+    )
+    .is_some()
+}
+
+/// True when the game mode sends enemies in waves.
+fn sends_waves(game_mode: Res<GameMode>) -> bool {
+    game_mode.sends_waves()
+}
+
+/// True when adaptive difficulty is on in the settings and no difficulty
+/// has been picked on the main menu.
+fn adaptive_difficulty_enabled(
+    settings: Res<Settings>,
+    chosen: Option<Res<DifficultyChosen>>,
+) -> bool {
+    settings.adaptive_difficulty && chosen.is_none()
+}
+
+/// Scales this level for the difficulty picked on the main menu. Adaptive
+/// difficulty keeps the enemy speed it has settled on from level to level.
+fn apply_difficulty_system(
+    difficulty: Res<Difficulty>,
+    settings: Res<Settings>,
+    chosen: Option<Res<DifficultyChosen>>,
+    mut multipliers: ResMut<DifficultyMultipliers>,
+) {
+    let adaptive_speed = multipliers.enemy_speed;
+    *multipliers = difficulty.multipliers();
+    if adaptive_difficulty_enabled(settings, chosen) {
+        multipliers.enemy_speed = adaptive_speed;
+    }
+}
+
+/// Every `ADAPTIVE_INTERVAL`, speeds enemies up when the player is killing
+/// them quickly and slows them down when they aren't, including the ones
+/// already out.
+fn adaptive_difficulty_system(
+    time: Res<Time>,
+    mut tracker: ResMut<PerformanceTracker>,
+    mut multipliers: ResMut<DifficultyMultipliers>,
+    mut enemy_query: Query<
+        (&mut Velocity, &mut SpawnVelocity, Option<&mut Pathfinder>),
+        With<Enemy>,
+    >,
+) {
+    if !tracker.timer.tick(time.delta()).just_finished() {
+        return;
+    }
+    let now = time.elapsed_seconds_f64();
+    while tracker
+        .kills_last_30s
+        .front()
+        .is_some_and(|&kill| now - kill > ADAPTIVE_WINDOW)
+    {
+        tracker.kills_last_30s.pop_front();
+    }
+    let kills = tracker.kills_last_30s.len();
+    let step = if kills > ADAPTIVE_MAX_KILLS {
+        ADAPTIVE_STEP
+    } else if kills < ADAPTIVE_MIN_KILLS {
+        -ADAPTIVE_STEP
+    } else {
+        return;
+    };
+    let old_speed = multipliers.enemy_speed;
+    let new_speed = (old_speed + step).clamp(ADAPTIVE_SPEED_RANGE.0, ADAPTIVE_SPEED_RANGE.1);
+    if new_speed == old_speed {
+        return;
+    }
+    multipliers.enemy_speed = new_speed;
+    let ratio = new_speed / old_speed;
+    for (mut velocity, mut spawn_velocity, pathfinder) in enemy_query.iter_mut() {
+        velocity.x *= ratio;
+        spawn_velocity.0 *= ratio;
+        if let Some(mut pathfinder) = pathfinder {
+            pathfinder.speed *= ratio;
+        }
+    }
+}
+
+/// Places the player, obstacles and enemies described by the level's layout.
+fn load_level(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    audio_handles: Res<AudioHandles>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    level_config: Res<LevelConfig>,
+    ground_data: Res<GroundData>,
+    difficulty: Res<DifficultyMultipliers>,
+    mut player_query: Query<(&mut Transform, Has<Player2>), With<Player>>,
+) {
+    let Some(layout) = &level_config.layout else {
+        return;
+    };
+
+    let player_pos = Vec3::new(
+        layout.player_spawn.0,
+        ground_data.top_y + layout.player_spawn.1 + PLAYER_SIZE.y / 2.0,
+        0.0,
+    );
+    commands.insert_resource(RespawnPoint(player_pos));
+    for (mut transform, second) in player_query.iter_mut() {
+        transform.translation = player_pos;
+        if second {
+            transform.translation.x += PLAYER2_OFFSET;
+        }
+    }
+
+    for obstacle in &layout.obstacles {
+        let size = Vec2::new(obstacle.size.0, obstacle.size.1);
+        let position = Vec3::new(
+            obstacle.position.0,
+            ground_data.top_y + obstacle.position.1 + size.y / 2.0,
+            0.0,
+        );
+        let mut entity = commands.spawn((
+            SpriteBundle {
+                sprite: Sprite {
+                    color: if obstacle.breakable {
+                        Color::rgb(0.75, 0.35, 0.15)
+                    } else if obstacle.restitution.is_some() {
+                        Color::rgb(0.2, 0.75, 0.3)
+                    } else {
+                        Color::DARK_GRAY
+                    },
+                    custom_size: Some(size),
+                    ..default()
+                },
+                transform: Transform::from_translation(position),
+                ..default()
+            },
+            Obstacle,
+            Collider::from_size(size),
+            GameEntity,
+        ));
+        if obstacle.breakable {
+            entity.insert(Breakable);
+        }
+        if let Some(restitution) = obstacle.restitution {
+            entity.insert(Restitution(restitution));
+        }
+        if let Some(push) = obstacle.conveyor {
+            entity
+                .insert(Conveyor { push })
+                .with_children(|parent| spawn_conveyor_stripes(parent, size));
+        }
+    }
+
+    for (index, enemy) in layout.enemies.iter().enumerate() {
+        let speed = enemy.speed * difficulty.enemy_speed;
+        let position = Vec3::new(
+            enemy.position.0,
+            ground_data.top_y + enemy.position.1 + ENEMY_SIZE.y / 2.0,
+            0.0,
+        );
+        let mut entity = commands.spawn((
+            SpriteBundle {
+                texture: asset_server.load("enemy.png"),
+                sprite: Sprite {
+                    custom_size: Some(ENEMY_SIZE),
+                    ..default()
+                },
+                transform: Transform::from_translation(position),
+                ..default()
+            },
+            Enemy,
+            Collider::from_size(ENEMY_SIZE),
+            GameEntity,
+            Velocity(Vec2::new(speed, 0.0)),
+            Interpolated::default(),
+            WalkSound(audio_handles.walk.clone()),
+            AggroRange::default(),
+            EnemyState::default(),
+            SpawnVelocity(speed),
+        ));
+        let route = level_config
+            .enemy_patrols
+            .iter()
+            .find(|patrol| patrol.enemy == index && !patrol.waypoints.is_empty())
+            .filter(|_| enemy.kind != EnemyKind::Chaser);
+        if let Some(route) = route {
+            entity.insert(PatrolPath {
+                waypoints: route
+                    .waypoints
+                    .iter()
+                    .map(|&(x, y)| Vec2::new(x, ground_data.top_y + y + ENEMY_SIZE.y / 2.0))
+                    .collect(),
+                current: 0,
+            });
+        }
+        if enemy.health > 1 {
+            entity
+                .insert(EnemyHealth {
+                    current: enemy.health,
+                    max: enemy.health,
+                })
+                .with_children(spawn_enemy_health_bar);
+        }
+        match enemy.kind {
+            EnemyKind::Walker => {}
+            EnemyKind::Patroller if route.is_some() => {}
+            EnemyKind::Patroller => {
+                entity.insert(PlatformPatrol {
+                    min_x: position.x - ENEMY_PATROL_RANGE,
+                    max_x: position.x + ENEMY_PATROL_RANGE,
+                });
+            }
+            EnemyKind::Chaser => {
+                entity.insert((
+                    Velocity(Vec2::ZERO),
+                    Pathfinder::new(speed.abs()),
+                    GravityScale(1.0),
+                    Grounded(false),
+                ));
+            }
+        }
+    }
+
+    for ladder in &layout.ladders {
+        spawn_ladder(
+            &mut commands,
+            ladder.position.0,
+            ground_data.top_y + ladder.position.1,
+            ladder.height,
+        );
+    }
+
+    for water in &layout.water {
+        let size = Vec2::new(water.size.0, water.size.1);
+        let position = Vec3::new(
+            water.position.0,
+            ground_data.top_y + water.position.1 + size.y / 2.0,
+            0.0,
+        );
+        spawn_water(&mut commands, position, size);
+    }
+
+    for platform in &layout.platforms {
+        let position = Vec3::new(
+            platform.position.0,
+            ground_data.top_y + platform.position.1 - PLATFORM_SIZE.y / 2.0,
+            0.0,
+        );
+        spawn_one_way_platform(&mut commands, position, platform.width);
+    }
+
+    let (r, g, b) = level_config.ground_color.unwrap_or((0.2, 0.8, 0.2));
+    let slope_material = materials.add(ColorMaterial::from(Color::rgb(r, g, b)));
+    for slope in &layout.slopes {
+        let (left_height, right_height) = slope.heights;
+        let size = Vec2::new(slope.width, left_height.max(right_height));
+        let bottom = ground_data.top_y + slope.position.1;
+        let half = size / 2.0;
+        // A quad with its top edge tilted, centred on its bounding box.
+        let mesh = Mesh::new(PrimitiveTopology::TriangleList)
+            .with_inserted_attribute(
+                Mesh::ATTRIBUTE_POSITION,
+                vec![
+                    [-half.x, -half.y, 0.0],
+                    [half.x, -half.y, 0.0],
+                    [half.x, right_height - half.y, 0.0],
+                    [-half.x, left_height - half.y, 0.0],
+                ],
+            )
+            .with_indices(Some(Indices::U32(vec![0, 1, 2, 0, 2, 3])));
+        commands.spawn((
+            MaterialMesh2dBundle {
+                mesh: meshes.add(mesh).into(),
+                material: slope_material.clone(),
+                transform: Transform::from_xyz(slope.position.0, bottom + half.y, 0.0),
+                ..default()
+            },
+            Slope {
+                left_y: bottom + left_height,
+                right_y: bottom + right_height,
+            },
+            Collider::from_size(size),
+            GameEntity,
+        ));
+    }
+}
+
+/// Spawns a one-way platform of `width` centred on `position`.
+fn spawn_one_way_platform(commands: &mut Commands, position: Vec3, width: f32) {
+    commands.spawn((
+        SpriteBundle {
+            sprite: Sprite {
+                color: Color::rgb(0.6, 0.4, 0.2),
+                custom_size: Some(Vec2::new(width, PLATFORM_SIZE.y)),
+                ..default()
+            },
+            transform: Transform::from_translation(position),
+            ..default()
+        },
+        OneWayPlatform,
+        GameEntity,
+    ));
+}
+
+/// Covers a conveyor of `size` with evenly spaced stripes for
+/// `conveyor_stripe_system` to scroll.
+fn spawn_conveyor_stripes(parent: &mut ChildBuilder, size: Vec2) {
+    let half_width = size.x / 2.0;
+    let mut x = -half_width;
+    while x < half_width {
+        parent.spawn((
+            SpriteBundle {
+                sprite: Sprite {
+                    color: Color::rgb(0.85, 0.7, 0.1),
+                    custom_size: Some(Vec2::new(4.0, size.y)),
+                    ..default()
+                },
+                transform: Transform::from_xyz(x, 0.0, 0.1),
+                ..default()
+            },
+            ConveyorStripe { half_width },
+        ));
+        x += CONVEYOR_STRIPE_SPACING;
+    }
+}
+
+/// Spawns a pool of water centred on `position`. It is drawn in front of
+/// the player so they look submerged.
+fn spawn_water(commands: &mut Commands, position: Vec3, size: Vec2) {
+    commands.spawn((
+        SpriteBundle {
+            sprite: Sprite {
+                color: Color::rgba(0.2, 0.4, 0.9, 0.4),
+                custom_size: Some(size),
+                ..default()
+            },
+            transform: Transform::from_translation(position.truncate().extend(0.5)),
+            ..default()
+        },
+        Water,
+        GameEntity,
+    ));
+}
+
+/// Spawns a ladder standing on `bottom_y`.
+fn spawn_ladder(commands: &mut Commands, x: f32, bottom_y: f32, height: f32) {
+    commands.spawn((
+        SpriteBundle {
+            sprite: Sprite {
+                color: Color::rgb(0.55, 0.35, 0.15),
+                custom_size: Some(Vec2::new(LADDER_WIDTH, height)),
+                ..default()
+            },
+            transform: Transform::from_xyz(x, bottom_y + height / 2.0, -0.5),
+            ..default()
+        },
+        Ladder,
+        GameEntity,
+    ));
+}
+
+/// Spawns the climbable platforms of the vertical level and the goal flag on top.
+fn spawn_platforms(
+    mut commands: Commands,
+    level_height: Res<LevelHeight>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    mut game_rng: ResMut<GameRng>,
+) {
+    let window = window_query.single();
+    let rng = &mut game_rng.rng;
+    let max_x = window.width() / 2.0 - PLATFORM_SIZE.x / 2.0;
+
+    // The first platform sits under the player's spawn point. Each following
+    // one is a jump higher and close enough sideways to be reachable.
+    let mut platform_pos = Vec3::new(0.0, GROUND_HEIGHT / 2.0 - PLATFORM_SIZE.y / 2.0, 0.0);
+    let mut top_platform = platform_pos;
+    let mut platform_index = 0;
+    while platform_pos.y <= level_height.0 {
+        commands.spawn((
+            SpriteBundle {
+                sprite: Sprite {
+                    color: Color::rgb(0.6, 0.4, 0.2),
+                    custom_size: Some(PLATFORM_SIZE),
+                    ..default()
+                },
+                transform: Transform::from_translation(platform_pos),
+                ..default()
+            },
+            Platform,
+            OneWayPlatform,
+            GameEntity,
+        ));
+
+        // Every few platforms, put a checkpoint on the left end.
+        if platform_index > 0 && platform_index % CHECKPOINT_EVERY == 0 {
+            let platform_top = platform_pos.y + PLATFORM_SIZE.y / 2.0;
+            let flag_x = platform_pos.x - PLATFORM_SIZE.x / 2.0 + CHECKPOINT_SIZE.x;
+            commands.spawn((
+                SpriteBundle {
+                    sprite: Sprite {
+                        color: Color::GRAY,
+                        custom_size: Some(CHECKPOINT_SIZE),
+                        ..default()
+                    },
+                    transform: Transform::from_xyz(
+                        flag_x,
+                        platform_top + CHECKPOINT_SIZE.y / 2.0,
+                        0.0,
+                    ),
+                    ..default()
+                },
+                Checkpoint {
+                    respawn: Vec3::new(flag_x, platform_top + PLAYER_SIZE.y / 2.0, 0.0),
+                    activated: false,
+                },
+                GameEntity,
+            ));
+        }
+
+        platform_index += 1;
+        top_platform = platform_pos;
+        platform_pos.y += PLATFORM_SPACING;
+        platform_pos.x = (platform_pos.x
+            + rng.gen_range(-PLATFORM_MAX_STEP_X..PLATFORM_MAX_STEP_X))
+        .clamp(-max_x, max_x);
+    }
+
+    // Plant the goal flag on the topmost platform.
+    commands.spawn((
+        SpriteBundle {
+            sprite: Sprite {
+                color: Color::GOLD,
+                custom_size: Some(GOAL_SIZE),
+                ..default()
+            },
+            transform: Transform::from_xyz(
+                top_platform.x,
+                top_platform.y + PLATFORM_SIZE.y / 2.0 + GOAL_SIZE.y / 2.0,
+                0.0,
+            ),
+            ..default()
+        },
+        GoalFlag,
+        GameEntity,
+    ));
+}
+
+/// Spawns one background sprite per non-empty tile, centered on the origin.
+fn spawn_tilemap_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    tile_map: Res<TileMap>,
+    bounds: Res<LevelBounds>,
+) {
+    let rows = tile_map.tiles.len() as f32;
+    let columns = tile_map.tiles.iter().map(Vec::len).max().unwrap_or(0) as f32;
+    let map_width = columns * tile_map.tile_size.x;
+    if map_width <= 0.0 {
+        return;
+    }
+    // Repeat the map side by side until it covers the whole level.
+    let copies = (bounds.width() / map_width).ceil() as usize;
+
+    for copy in 0..copies {
+        let top_left = Vec2::new(
+            bounds.min_x + copy as f32 * map_width,
+            rows * tile_map.tile_size.y / 2.0,
+        );
+        for (row, tiles) in tile_map.tiles.iter().enumerate() {
+            for (column, tile) in tiles.iter().enumerate() {
+                let Some(texture) = tile.texture() else {
+                    continue;
+                };
+                let x = top_left.x + (column as f32 + 0.5) * tile_map.tile_size.x;
+                let y = top_left.y - (row as f32 + 0.5) * tile_map.tile_size.y;
+                commands.spawn((
+                    SpriteBundle {
+                        texture: asset_server.load(texture),
+                        sprite: Sprite {
+                            custom_size: Some(tile_map.tile_size),
+                            ..default()
+                        },
+                        // Behind every gameplay sprite.
+                        transform: Transform::from_xyz(x, y, -10.0),
+                        ..default()
+                    },
+                    GameEntity,
+                ));
+            }
+        }
+    }
+}
+
+/// Spawns the distant clouds and two rows of hills behind levels without
+/// tile backgrounds, as plain shapes.
+fn spawn_parallax_layers(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    ground_data: Res<GroundData>,
+) {
+    let circle = meshes.add(shape::Circle::new(1.0).into());
+    // (factor, z, color, shapes as (x, height above the ground, size)).
+    let layers = [
+        (
+            0.1,
+            -30.0,
+            Color::rgba(1.0, 1.0, 1.0, 0.8),
+            vec![
+                (-120.0, 420.0, Vec2::new(90.0, 30.0)),
+                (130.0, 360.0, Vec2::new(70.0, 25.0)),
+            ],
+        ),
+        (
+            0.3,
+            -25.0,
+            Color::rgb(0.55, 0.7, 0.85),
+            vec![(0.0, -80.0, Vec2::splat(220.0))],
+        ),
+        (
+            0.6,
+            -20.0,
+            Color::rgb(0.35, 0.6, 0.35),
+            vec![
+                (-120.0, -60.0, Vec2::splat(130.0)),
+                (130.0, -80.0, Vec2::splat(150.0)),
+            ],
+        ),
+    ];
+    for (factor, z, color, shapes) in layers {
+        let material = materials.add(ColorMaterial::from(color));
+        commands
+            .spawn((
+                SpatialBundle::from_transform(Transform::from_xyz(0.0, ground_data.top_y, z)),
+                Parallax {
+                    factor,
+                    base_y: ground_data.top_y,
+                },
+                GameEntity,
+            ))
+            .with_children(|parent| {
+                for tile in -PARALLAX_TILES..=PARALLAX_TILES {
+                    for &(x, y, size) in &shapes {
+                        parent.spawn(MaterialMesh2dBundle {
+                            mesh: circle.clone().into(),
+                            material: material.clone(),
+                            transform: Transform::from_xyz(
+                                tile as f32 * PARALLAX_TILE_WIDTH + x,
+                                y,
+                                0.0,
+                            )
+                            .with_scale(size.extend(1.0)),
+                            ..default()
+                        });
+                    }
+                }
+            });
+    }
+}
+
+/// Spawns a random number of enemies with random horizontal velocities.
+/// In vertical mode they are placed on platforms above the start instead.
+fn spawn_enemies(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    audio_handles: Res<AudioHandles>,
+    ground_data: Res<GroundData>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    platform_query: Query<&Transform, With<Platform>>,
+    obstacle_query: Query<(&Transform, &Collider), With<Obstacle>>,
+    level_config: Option<Res<LevelConfig>>,
+    current_level: Res<CurrentLevel>,
+    difficulty: Res<DifficultyMultipliers>,
+    respawn_point: Res<RespawnPoint>,
+    bounds: Res<LevelBounds>,
+    mut game_rng: ResMut<GameRng>,
+) {
+    let window = window_query.single();
+    let rng = &mut game_rng.rng;
+    let base_count = rng.gen_range(2..5) * bounds.screen_count(window);
+    let mut enemy_count = ((base_count as f32 * difficulty.enemy_count).round() as usize).max(1);
+    let (min_speed, max_speed) = level_config
+        .and_then(|config| config.enemy_speed_range)
+        .unwrap_or(ENEMY_SPEED_RANGE);
+    let (min_speed, max_speed) = (
+        min_speed * difficulty.enemy_speed,
+        max_speed * difficulty.enemy_speed,
+    );
+    let enemy_y = ground_data.top_y + ENEMY_SIZE.y / 2.0;
+    let platforms: Vec<Vec3> = platform_query
+        .iter()
+        .map(|t| t.translation)
+        .filter(|pos| pos.y > PLATFORM_SPACING)
+        .collect();
+    let obstacles: Vec<(Vec3, Vec2)> = obstacle_query
+        .iter()
+        .map(|(transform, collider)| (transform.translation, collider.half_extents))
+        .collect();
+    let enemy_reach = ENEMY_SIZE / 2.0 + Vec2::new(ENEMY_OBSTACLE_CLEARANCE, 0.0);
+
+    // A formation drops in from where its pattern puts each enemy, so the
+    // whole column below each one has to be clear.
+    let pattern = SpawnPattern::for_level(current_level.0);
+    if pattern != SpawnPattern::Random && platforms.is_empty() {
+        let size = enemy_count.min(FORMATION_SIZE);
+        let formation = (0..SPAWN_ATTEMPTS)
+            .map(|_| {
+                let x = rng.gen_range(bounds.min_x..bounds.max_x);
+                let center = Vec2::new(x, enemy_y);
+                let positions = pattern_spawn_helper(pattern, size, center, FORMATION_SPACING);
+                (x, positions)
+            })
+            .find(|(_, positions)| {
+                positions.iter().all(|pos| {
+                    let column = Vec3::new(pos.x, (pos.y + enemy_y) / 2.0, 0.0);
+                    let column_reach = enemy_reach + Vec2::new(0.0, (pos.y - enemy_y) / 2.0);
+                    pos.extend(0.0).distance(respawn_point.0) >= ENEMY_SPAWN_CLEARANCE
+                        && pos.x - ENEMY_SIZE.x / 2.0 >= bounds.min_x
+                        && pos.x + ENEMY_SIZE.x / 2.0 <= bounds.max_x
+                        && ground_data.supports(pos.x, ENEMY_SIZE.x / 2.0)
+                        && obstacles.iter().all(|&(obstacle_pos, obstacle_half)| {
+                            !is_colliding(column, column_reach, obstacle_pos, obstacle_half)
+                        })
+                })
+            });
+        if let Some((x, positions)) = formation {
+            // Everyone heads for the player together.
+            let speed = rng.gen_range(min_speed..max_speed);
+            let velocity_x = (respawn_point.0.x - x).signum() * speed;
+            for pos in positions {
+                spawn_random_enemy(
+                    &mut commands,
+                    &asset_server,
+                    &audio_handles,
+                    pos.extend(0.0),
+                    velocity_x,
+                )
+                .insert(GravityScale(1.0));
+            }
+            enemy_count -= size;
+        }
+    }
+
+    for _ in 0..enemy_count {
+        let spot = pick_enemy_spot(
+            rng,
+            &bounds,
+            &ground_data,
+            &platforms,
+            &obstacles,
+            respawn_point.0,
+        );
+        let Some((enemy_pos, patrol)) = spot else {
+            continue;
+        };
+
+        // Random horizontal speed and direction.
+        let speed = rng.gen_range(min_speed..max_speed);
+        let direction = if rng.gen_bool(0.5) { 1.0 } else { -1.0 };
+
+        let mut enemy = spawn_random_enemy(
+            &mut commands,
+            &asset_server,
+            &audio_handles,
+            enemy_pos,
+            direction * speed,
+        );
+        if let Some(patrol) = patrol {
+            enemy.insert(patrol);
+        }
+    }
+}
+
+/// Picks where one enemy starts: on solid ground, or on one of `platforms`
+/// with the patrol range that keeps it there. Retries until the spot is far
+/// enough from `spawn` and clear of every obstacle, and gives up after
+/// `SPAWN_ATTEMPTS`.
+fn pick_enemy_spot(
+    rng: &mut StdRng,
+    bounds: &LevelBounds,
+    ground_data: &GroundData,
+    platforms: &[Vec3],
+    obstacles: &[(Vec3, Vec2)],
+    spawn: Vec3,
+) -> Option<(Vec3, Option<PlatformPatrol>)> {
+    let enemy_y = ground_data.top_y + ENEMY_SIZE.y / 2.0;
+    let enemy_reach = ENEMY_SIZE / 2.0 + Vec2::new(ENEMY_OBSTACLE_CLEARANCE, 0.0);
+    (0..SPAWN_ATTEMPTS)
+        .map(|_| {
+            if platforms.is_empty() {
+                let x = rng.gen_range(bounds.min_x..bounds.max_x);
+                (Vec3::new(x, enemy_y, 0.0), None)
+            } else {
+                let platform = platforms[rng.gen_range(0..platforms.len())];
+                let reach = PLATFORM_SIZE.x / 2.0 - ENEMY_SIZE.x / 2.0;
+                let x = rng.gen_range(platform.x - reach..platform.x + reach);
+                let y = platform.y + PLATFORM_SIZE.y / 2.0 + ENEMY_SIZE.y / 2.0;
+                let patrol = PlatformPatrol {
+                    min_x: platform.x - reach,
+                    max_x: platform.x + reach,
+                };
+                (Vec3::new(x, y, 0.0), Some(patrol))
+            }
+        })
+        .find(|(pos, patrol)| {
+            pos.distance(spawn) >= ENEMY_SPAWN_CLEARANCE
+                && (patrol.is_some() || ground_data.supports(pos.x, ENEMY_SIZE.x / 2.0))
+                && obstacles.iter().all(|&(obstacle_pos, obstacle_half)| {
+                    !is_colliding(*pos, enemy_reach, obstacle_pos, obstacle_half)
+                })
+        })
+}
+
+/// Spawns a walking enemy of a random level at `position`.
+fn spawn_random_enemy<'w, 's, 'a>(
+    commands: &'a mut Commands<'w, 's>,
+    asset_server: &AssetServer,
+    audio_handles: &AudioHandles,
+    position: Vec3,
+    velocity_x: f32,
+) -> EntityCommands<'w, 's, 'a> {
+    commands.spawn((
+        SpriteBundle {
+            texture: asset_server.load("enemy.png"),
+            sprite: Sprite {
+                custom_size: Some(ENEMY_SIZE),
+                ..default()
+            },
+            transform: Transform::from_translation(position),
+            ..default()
+        },
+        Enemy,
+        Collider::from_size(ENEMY_SIZE),
+        GameEntity,
+        Velocity(Vec2::new(velocity_x, 0.0)),
+        Interpolated::default(),
+        WalkSound(audio_handles.walk.clone()),
+        AggroRange::default(),
+        EnemyState::default(),
+        SpawnVelocity(velocity_x),
+    ))
+}
+
+/// Spawns a random number of obstacles at ground level.
+fn spawn_obstacles(
+    mut commands: Commands,
+    ground_data: Res<GroundData>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    respawn_point: Res<RespawnPoint>,
+    bounds: Res<LevelBounds>,
+    mut game_rng: ResMut<GameRng>,
+) {
+    let window = window_query.single();
+    let rng = &mut game_rng.rng;
+    let obstacle_count = rng.gen_range(3..7) * bounds.screen_count(window);
+    let obstacle_y = ground_data.top_y + OBSTACLE_SIZE.y / 2.0;
+    let xs = generate_obstacle_xs(
+        rng,
+        obstacle_count,
+        &bounds,
+        &ground_data,
+        respawn_point.0.x,
+    );
+
+    let mut bouncy_placed = false;
+    for x in xs {
+        let obstacle_pos = Vec3::new(x, obstacle_y, 0.0);
+        let variant = rng.gen::<f64>();
+        let fragile = variant < FRAGILE_CHANCE;
+        if !fragile
+            && variant < FRAGILE_CHANCE + CONVEYOR_CHANCE
+            && ground_data.supports(x, CONVEYOR_SIZE.x / 2.0)
+        {
+            let speed = rng.gen_range(CONVEYOR_PUSH_RANGE.0..CONVEYOR_PUSH_RANGE.1);
+            let push = if rng.gen_bool(0.5) { speed } else { -speed };
+            let conveyor_y = ground_data.top_y + CONVEYOR_SIZE.y / 2.0;
+            commands
+                .spawn((
+                    SpriteBundle {
+                        sprite: Sprite {
+                            color: Color::rgb(0.3, 0.3, 0.35),
+                            custom_size: Some(CONVEYOR_SIZE),
+                            ..default()
+                        },
+                        transform: Transform::from_xyz(x, conveyor_y, 0.0),
+                        ..default()
+                    },
+                    Obstacle,
+                    Collider::from_size(CONVEYOR_SIZE),
+                    Conveyor { push },
+                    GameEntity,
+                ))
+                .with_children(|parent| spawn_conveyor_stripes(parent, CONVEYOR_SIZE));
+            continue;
+        }
+
+        // The first plain obstacle is the level's rubbery one.
+        let bouncy = !fragile && !bouncy_placed;
+        bouncy_placed |= bouncy;
+        let mut obstacle = commands.spawn((
+            SpriteBundle {
+                sprite: Sprite {
+                    color: if fragile {
+                        Color::rgb(0.55, 0.5, 0.45)
+                    } else if bouncy {
+                        Color::rgb(0.2, 0.75, 0.3)
+                    } else {
+                        Color::DARK_GRAY
+                    },
+                    custom_size: Some(OBSTACLE_SIZE),
+                    ..default()
+                },
+                transform: Transform::from_translation(obstacle_pos),
+                ..default()
+            },
+            Obstacle,
+            Collider::from_size(OBSTACLE_SIZE),
+            GameEntity,
+        ));
+        if bouncy {
+            obstacle.insert(Restitution(BOUNCY_RESTITUTION));
+        }
+        if fragile {
+            // A dark diagonal line marks the obstacle as cracked.
+            obstacle.insert(Fragile).with_children(|parent| {
+                parent.spawn(SpriteBundle {
+                    sprite: Sprite {
+                        color: Color::rgb(0.2, 0.2, 0.2),
+                        custom_size: Some(Vec2::new(OBSTACLE_SIZE.x, 3.0)),
+                        ..default()
+                    },
+                    transform: Transform::from_xyz(0.0, 0.0, 0.1)
+                        .with_rotation(Quat::from_rotation_z(std::f32::consts::FRAC_PI_4)),
+                    ..default()
+                });
+            });
+        }
+    }
+}
+
+/// Picks obstacle x positions on solid ground that leave at least two
+/// player widths between neighbours and keep `OBSTACLE_SPAWN_CLEARANCE`
+/// from the player's spawn point. Fewer than `count` are returned if the
+/// level gets too crowded.
+fn generate_obstacle_xs(
+    rng: &mut StdRng,
+    count: usize,
+    bounds: &LevelBounds,
+    ground_data: &GroundData,
+    spawn_x: f32,
+) -> Vec<f32> {
+    let min_spacing = OBSTACLE_SIZE.x + PLAYER_SIZE.x * 2.0;
+    let mut xs: Vec<f32> = Vec::with_capacity(count);
+    for _ in 0..count {
+        let spot = (0..SPAWN_ATTEMPTS)
+            .map(|_| rng.gen_range(bounds.min_x..bounds.max_x))
+            .find(|&x| {
+                (x - spawn_x).abs() >= OBSTACLE_SPAWN_CLEARANCE
+                    && ground_data.supports(x, OBSTACLE_SIZE.x / 2.0)
+                    && xs.iter().all(|&other| (x - other).abs() >= min_spacing)
+            });
+        xs.extend(spot);
+    }
+    xs
+}
+
+/// Spawns about one pool of water per screen on solid ground away from the
+/// player's spawn point.
+fn spawn_random_water(
+    mut commands: Commands,
+    ground_data: Res<GroundData>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    respawn_point: Res<RespawnPoint>,
+    bounds: Res<LevelBounds>,
+    mut game_rng: ResMut<GameRng>,
+) {
+    let window = window_query.single();
+    let rng = &mut game_rng.rng;
+    let water_y = ground_data.top_y + WATER_SIZE.y / 2.0;
+    for _ in 0..bounds.screen_count(window) {
+        let spot = (0..SPAWN_ATTEMPTS)
+            .map(|_| rng.gen_range(bounds.min_x..bounds.max_x))
+            .find(|&x| {
+                (x - respawn_point.0.x).abs() >= WATER_SPAWN_CLEARANCE
+                    && ground_data.supports(x, WATER_SIZE.x / 2.0)
+            });
+        if let Some(x) = spot {
+            spawn_water(&mut commands, Vec3::new(x, water_y, 0.0), WATER_SIZE);
+        }
+    }
+}
+
+/// Spawns a few breakable blocks floating one jump above the ground.
+fn spawn_breakable_blocks(
+    mut commands: Commands,
+    ground_data: Res<GroundData>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    bounds: Res<LevelBounds>,
+    mut game_rng: ResMut<GameRng>,
+) {
+    let window = window_query.single();
+    let rng = &mut game_rng.rng;
+    let block_count = rng.gen_range(2..5) * bounds.screen_count(window);
+    let block_y = ground_data.top_y + BREAKABLE_HEIGHT;
+
+    for _ in 0..block_count {
+        let x = rng.gen_range(bounds.min_x..bounds.max_x);
+
+        commands.spawn((
+            SpriteBundle {
+                sprite: Sprite {
+                    color: Color::rgb(0.75, 0.35, 0.15),
+                    custom_size: Some(OBSTACLE_SIZE),
+                    ..default()
+                },
+                transform: Transform::from_xyz(x, block_y, 0.0),
+                ..default()
+            },
+            Obstacle,
+            Collider::from_size(OBSTACLE_SIZE),
+            Breakable,
+            GameEntity,
+        ));
+
+        // Some blocks get a ladder against their left side, reaching their top.
+        let ladder_x = x - OBSTACLE_SIZE.x / 2.0 - PLAYER_SIZE.x / 2.0;
+        if rng.gen_bool(LADDER_CHANCE) && ground_data.supports(ladder_x, LADDER_WIDTH / 2.0) {
+            let ladder_height = BREAKABLE_HEIGHT + OBSTACLE_SIZE.y / 2.0;
+            spawn_ladder(&mut commands, ladder_x, ground_data.top_y, ladder_height);
+        }
+    }
+}
+
+/// Spawns the key at a random elevated spot and the locked door at the
+/// right edge of the level.
+fn spawn_key_and_door(
+    mut commands: Commands,
+    ground_data: Res<GroundData>,
+    bounds: Res<LevelBounds>,
+    mut game_rng: ResMut<GameRng>,
+) {
+    let rng = &mut game_rng.rng;
+    let key_x = rng.gen_range(bounds.min_x + KEY_SIZE.x..bounds.max_x - DOOR_SIZE.x * 2.0);
+    let key_y = ground_data.top_y + rng.gen_range(KEY_HEIGHT_RANGE.0..KEY_HEIGHT_RANGE.1);
+    commands.spawn((
+        SpriteBundle {
+            sprite: Sprite {
+                color: Color::GOLD,
+                custom_size: Some(KEY_SIZE),
+                ..default()
+            },
+            transform: Transform::from_xyz(key_x, key_y, 0.0),
+            ..default()
+        },
+        Key,
+        GameEntity,
+    ));
+
+    commands.spawn((
+        SpriteBundle {
+            sprite: Sprite {
+                color: Color::rgb(0.45, 0.25, 0.1),
+                custom_size: Some(DOOR_SIZE),
+                ..default()
+            },
+            transform: Transform::from_xyz(
+                bounds.max_x - DOOR_SIZE.x / 2.0,
+                ground_data.top_y + DOOR_SIZE.y / 2.0,
+                0.0,
+            ),
+            ..default()
+        },
+        Door,
+        GameEntity,
+    ));
+}
+
+/// Spawns the goal flag where the level layout puts it, or at the right
+/// edge of the flat level.
+fn spawn_goal_flag(
+    mut commands: Commands,
+    ground_data: Res<GroundData>,
+    bounds: Res<LevelBounds>,
+    level_config: Option<Res<LevelConfig>>,
+    endless: Option<Res<Endless>>,
+    game_mode: Res<GameMode>,
+) {
+    let random_level = endless.is_some() || *game_mode == GameMode::Endless;
+    let layout_goal =
+        active_layout(level_config.as_deref(), false, random_level).and_then(|layout| layout.goal);
+    let (x, lift) = layout_goal.unwrap_or((bounds.max_x - GOAL_SIZE.x * 1.5, 0.0));
+    commands.spawn((
+        SpriteBundle {
+            sprite: Sprite {
+                color: Color::GOLD,
+                custom_size: Some(GOAL_SIZE),
+                ..default()
+            },
+            transform: Transform::from_xyz(x, ground_data.top_y + lift + GOAL_SIZE.y / 2.0, 0.0),
+            ..default()
+        },
+        GoalFlag,
+        GameEntity,
+    ));
+}
+
+/// Spawns a coin pickup at the given position.
+fn spawn_coin(commands: &mut Commands, coin_assets: &CoinAssets, position: Vec3) {
+    commands.spawn((
+        MaterialMesh2dBundle {
+            mesh: coin_assets.mesh.clone().into(),
+            material: coin_assets.material.clone(),
+            transform: Transform::from_translation(position),
+            ..default()
+        },
+        Coin { base_y: position.y },
+        GameEntity,
+    ));
+}
+
+/// Scatters coins at ground level, one jump up, and above obstacles.
+/// In vertical mode they sit on and above the platforms instead, and a
+/// level layout that lists its coins places them exactly.
+fn spawn_coins(
+    mut commands: Commands,
+    coin_assets: Res<CoinAssets>,
+    ground_data: Res<GroundData>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    obstacle_query: Query<(&Transform, &Collider), With<Obstacle>>,
+    platform_query: Query<&Transform, With<Platform>>,
+    level_config: Option<Res<LevelConfig>>,
+    level_height: Option<Res<LevelHeight>>,
+    endless: Option<Res<Endless>>,
+    game_mode: Res<GameMode>,
+    bounds: Res<LevelBounds>,
+    mut game_rng: ResMut<GameRng>,
+) {
+    let layout_coins = active_layout(
+        level_config.as_deref(),
+        level_height.is_some(),
+        endless.is_some() || *game_mode == GameMode::Endless,
+    )
+    .and_then(|layout| layout.coins.as_ref());
+    if let Some(coins) = layout_coins {
+        for &(x, lift) in coins {
+            let position = Vec3::new(x, ground_data.top_y + lift + COIN_RADIUS, 0.0);
+            spawn_coin(&mut commands, &coin_assets, position);
+        }
+        return;
+    }
+
+    let window = window_query.single();
+    let rng = &mut game_rng.rng;
+    let coin_count =
+        rng.gen_range(COIN_COUNT_RANGE.0..COIN_COUNT_RANGE.1) * bounds.screen_count(window);
+    let obstacles: Vec<(Vec3, Vec2)> = obstacle_query
+        .iter()
+        .map(|(t, collider)| (t.translation, collider.half_extents))
+        .collect();
+    let platforms: Vec<Vec3> = platform_query.iter().map(|t| t.translation).collect();
+    let coin_half = Vec2::splat(COIN_RADIUS);
+
+    for _ in 0..coin_count {
+        // Retry a few times if the spot lands inside an obstacle.
+        for _ in 0..10 {
+            let lift = if rng.gen_bool(0.5) {
+                COIN_RADIUS * 2.0
+            } else {
+                COIN_JUMP_HEIGHT
+            };
+            let position = if !platforms.is_empty() {
+                let platform = platforms[rng.gen_range(0..platforms.len())];
+                let reach = PLATFORM_SIZE.x / 2.0 - COIN_RADIUS;
+                let x = rng.gen_range(platform.x - reach..platform.x + reach);
+                Vec3::new(x, platform.y + PLATFORM_SIZE.y / 2.0 + lift, 0.0)
+            } else if !obstacles.is_empty() && rng.gen_bool(0.3) {
+                let (obstacle, obstacle_half) = obstacles[rng.gen_range(0..obstacles.len())];
+                Vec3::new(obstacle.x, obstacle.y + obstacle_half.y + lift, 0.0)
+            } else {
+                let x = rng.gen_range(bounds.min_x + COIN_RADIUS..bounds.max_x - COIN_RADIUS);
+                Vec3::new(x, ground_data.top_y + lift, 0.0)
+            };
+
+            let blocked = obstacles.iter().any(|&(obstacle, obstacle_half)| {
+                is_colliding(position, coin_half, obstacle, obstacle_half)
+            });
+            if !blocked {
+                spawn_coin(&mut commands, &coin_assets, position);
+                break;
+            }
+        }
+    }
+}
+
+//
+// MENU SYSTEMS
+//
+
+/// Spawns the main menu: a title, a button for each game mode, the
+/// difficulty picker and the other screens.
+fn setup_main_menu(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    difficulty: Res<Difficulty>,
+) {
+    let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    row_gap: Val::Px(20.0),
+                    ..default()
+                },
+                background_color: Color::rgb(0.1, 0.1, 0.2).into(),
+                ..default()
+            },
+            MainMenuEntity,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                "Rust Platformer",
+                TextStyle {
+                    font: font.clone(),
+                    font_size: 80.0,
+                    color: Color::WHITE,
+                },
+            ));
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        column_gap: Val::Px(10.0),
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|row| {
+                    spawn_menu_button(row, &font, "Play", MenuButton::Play);
+                    spawn_menu_button(row, &font, "Endless", MenuButton::Endless);
+                    spawn_menu_button(row, &font, "Time Attack", MenuButton::TimeAttack);
+                    spawn_menu_button(row, &font, "Survival", MenuButton::Survival);
+                });
+            parent.spawn((
+                TextBundle::from_section(
+                    format!("Difficulty: {}", difficulty.label()),
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: 30.0,
+                        color: Color::WHITE,
+                    },
+                ),
+                DifficultyText,
+            ));
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        column_gap: Val::Px(10.0),
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|row| {
+                    for difficulty in Difficulty::ALL {
+                        spawn_menu_button(
+                            row,
+                            &font,
+                            difficulty.label(),
+                            MenuButton::Difficulty(difficulty),
+                        );
+                    }
+                });
+            spawn_menu_button(parent, &font, "Settings", MenuButton::Settings);
+            spawn_menu_button(parent, &font, "Stats", MenuButton::Stats);
+            spawn_menu_button(parent, &font, "Quit", MenuButton::Quit);
+        });
+}
+
+/// Spawns a labelled menu button as a child of `parent`.
+fn spawn_menu_button(
+    parent: &mut ChildBuilder,
+    font: &Handle<Font>,
+    label: &str,
+    action: MenuButton,
+) {
+    parent
+        .spawn((
+            ButtonBundle {
+                style: Style {
+                    width: Val::Px(250.0),
+                    height: Val::Px(65.0),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                background_color: NORMAL_BUTTON.into(),
+                ..default()
+            },
+            action,
+        ))
+        .with_children(|button| {
+            button.spawn(TextBundle::from_section(
+                label,
+                TextStyle {
+                    font: font.clone(),
+                    font_size: 40.0,
+                    color: Color::WHITE,
+                },
+            ));
+        });
+}
+
+/// Gives every button hover and press feedback.
+fn button_color_system(
+    mut query: Query<
+        (&Interaction, &mut BackgroundColor),
+        (
+            Changed<Interaction>,
+            With<Button>,
+            Without<LevelCard>,
+            Without<Slider>,
+        ),
+    >,
+) {
+    for (interaction, mut color) in query.iter_mut() {
+        *color = match *interaction {
+            Interaction::Pressed => PRESSED_BUTTON.into(),
+            Interaction::Hovered => HOVERED_BUTTON.into(),
+            Interaction::None => NORMAL_BUTTON.into(),
+        };
+    }
+}
+
+/// Starts the game, picks the difficulty or quits when a main menu button
+/// is pressed. Endless mode skips the level select and starts straight away.
+fn main_menu_system(
+    mut commands: Commands,
+    query: Query<(&Interaction, &MenuButton), Changed<Interaction>>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut game_mode: ResMut<GameMode>,
+    mut current_level: ResMut<CurrentLevel>,
+    mut total_score: ResMut<TotalScore>,
+    mut difficulty: ResMut<Difficulty>,
+    mut text_query: Query<&mut Text, With<DifficultyText>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    for (interaction, button) in query.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        match button {
+            MenuButton::Play => {
+                *game_mode = GameMode::Story;
+                next_state.set(GameState::LevelSelect);
+            }
+            MenuButton::Endless => {
+                *game_mode = GameMode::Endless;
+                current_level.0 = 1;
+                total_score.0 = 0;
+                next_state.set(GameState::Playing);
+            }
+            MenuButton::TimeAttack => {
+                *game_mode = GameMode::TimeAttack;
+                next_state.set(GameState::LevelSelect);
+            }
+            MenuButton::Survival => {
+                *game_mode = GameMode::Survival;
+                next_state.set(GameState::LevelSelect);
+            }
+            MenuButton::Settings => next_state.set(GameState::Settings),
+            MenuButton::Stats => next_state.set(GameState::Stats),
+            MenuButton::Quit => exit.send(AppExit),
+            MenuButton::Difficulty(choice) => {
+                *difficulty = *choice;
+                commands.insert_resource(DifficultyChosen);
+                for mut text in text_query.iter_mut() {
+                    text.sections[0].value = format!("Difficulty: {}", choice.label());
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Despawns the main menu when leaving it.
+fn cleanup_menu_system(mut commands: Commands, query: Query<Entity, With<MainMenuEntity>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+/// Path of a save file in the user's data directory.
+fn data_file(name: &str) -> PathBuf {
+    dirs::data_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("rust_game")
+        .join(name)
+}
+
+/// Reads a RON save file, falling back to defaults if it is missing or invalid.
+fn load_save_file<T: serde::de::DeserializeOwned + Default>(path: &std::path::Path) -> T {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => ron::from_str(&contents).unwrap_or_else(|err| {
+            warn!("Invalid save file {}: {}", path.display(), err);
+            T::default()
+        }),
+        Err(_) => T::default(),
+    }
+}
+
+/// Reads the saved high scores, starting fresh if there are none yet.
+fn load_high_scores_system(mut commands: Commands) {
+    let save_path = data_file("highscores.ron");
+    let mut high_scores: HighScores = load_save_file(&save_path);
+    high_scores.save_path = save_path;
+    commands.insert_resource(high_scores);
+}
+
+/// Reads the lifetime stats, starting from zero if there are none yet.
+fn load_stats_system(mut commands: Commands) {
+    let stats: PlayerStats = load_save_file(&data_file("stats.ron"));
+    commands.insert_resource(stats);
+}
+
+/// Writes a value to a RON save file, creating its directory if needed.
+fn write_save_file<T: Serialize>(path: &std::path::Path, value: &T) {
+    let result = ron::to_string(value)
+        .map_err(|err| err.to_string())
+        .and_then(|contents| {
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+            }
+            std::fs::write(path, contents).map_err(|err| err.to_string())
+        });
+    if let Err(err) = result {
+        error!("Could not save {}: {}", path.display(), err);
+    }
+}
+
+/// Writes the lifetime stats to disk.
+fn save_stats_system(stats: Res<PlayerStats>) {
+    write_save_file(&data_file("stats.ron"), &*stats);
+}
+
+/// Reads a config file such as `settings.ron`, falling back to defaults if
+/// it is missing or invalid.
+fn load_config_file<T: serde::de::DeserializeOwned + Default>(path: &str) -> T {
+    let Ok(contents) = std::fs::read_to_string(path) else {
+        return T::default();
+    };
+    ron::from_str(&contents).unwrap_or_else(|err| {
+        warn!("Invalid config file {}: {}", path, err);
+        T::default()
+    })
+}
+
+/// Writes a config file in readable, pretty-printed RON.
+fn write_config_file<T: Serialize>(path: &str, value: &T) {
+    let result = ron::ser::to_string_pretty(value, ron::ser::PrettyConfig::default())
+        .map_err(|err| err.to_string())
+        .and_then(|contents| std::fs::write(path, contents).map_err(|err| err.to_string()));
+    if let Err(err) = result {
+        error!("Could not save {}: {}", path, err);
+    }
+}
+
+/// Writes the current settings to `settings.ron`.
+fn save_settings_system(settings: Res<Settings>) {
+    write_config_file(SETTINGS_PATH, &*settings);
+}
+
+/// Saves the zoom picked during play, if it changed.
+fn save_zoom_system(zoom: Res<CameraZoom>, mut settings: ResMut<Settings>) {
+    if settings.zoom != zoom.chosen {
+        settings.zoom = zoom.chosen;
+        write_config_file(SETTINGS_PATH, &*settings);
+    }
+}
+
+/// Plays a sound effect once at the current SFX volume. A `speed` above 1
+/// raises its pitch.
+fn play_sound(
+    commands: &mut Commands,
+    source: &Handle<AudioSource>,
+    sfx_volume: &SfxVolume,
+    speed: f32,
+) {
+    commands.spawn((
+        AudioBundle {
+            source: source.clone(),
+            settings: PlaybackSettings::ONCE
+                .with_volume(Volume::new_absolute(sfx_volume.0))
+                .with_speed(speed),
+        },
+        SoundEffect,
+    ));
+}
+
+/// Replaces the current music with `source`, looping at the music volume.
+fn play_music(
+    commands: &mut Commands,
+    current: Option<&BgmEntity>,
+    source: Handle<AudioSource>,
+    audio: &AudioSettings,
+    menu: bool,
+) {
+    if let Some(current) = current {
+        commands.entity(current.0).despawn();
+    }
+    let mut entity = commands.spawn((
+        AudioBundle {
+            source,
+            settings: PlaybackSettings::LOOP
+                .with_volume(Volume::new_absolute(audio.music_volume())),
+        },
+        BgmSink,
+    ));
+    if menu {
+        entity.insert(MenuMusic);
+    }
+    let entity = entity.id();
+    commands.insert_resource(BgmEntity(entity));
+}
+
+/// Starts the menu music, unless it is already playing.
+fn start_menu_music_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    audio: Res<AudioSettings>,
+    bgm: Option<Res<BgmEntity>>,
+    menu_music: Query<(), With<MenuMusic>>,
+) {
+    let bgm = bgm.as_deref();
+    if bgm.is_some_and(|bgm| menu_music.contains(bgm.0)) {
+        return;
+    }
+    play_music(
+        &mut commands,
+        bgm,
+        asset_server.load("menu.ogg"),
+        &audio,
+        true,
+    );
+}
+
+/// Starts the level music, or picks it back up when resuming from pause.
+fn start_bgm_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    audio: Res<AudioSettings>,
+    bgm: Option<Res<BgmEntity>>,
+    sinks: Query<&AudioSink, Without<MenuMusic>>,
+) {
+    let bgm = bgm.as_deref();
+    if let Some(sink) = bgm.and_then(|bgm| sinks.get(bgm.0).ok()) {
+        if sink.is_paused() {
+            sink.play();
+            return;
+        }
+    }
+    play_music(
+        &mut commands,
+        bgm,
+        asset_server.load("bgm.ogg"),
+        &audio,
+        false,
+    );
+}
+
+/// Pauses the music along with the game.
+fn pause_bgm_system(bgm: Option<Res<BgmEntity>>, sinks: Query<&AudioSink>) {
+    if let Some(sink) = bgm.and_then(|bgm| sinks.get(bgm.0).ok()) {
+        sink.pause();
+    }
+}
+
+/// Stops the level music and plays the win or game-over jingle in its place.
+fn play_jingle_system(
+    mut commands: Commands,
+    state: Res<State<GameState>>,
+    asset_server: Res<AssetServer>,
+    audio: Res<AudioSettings>,
+    bgm: Option<Res<BgmEntity>>,
+    sinks: Query<&AudioSink>,
+) {
+    if let Some(bgm) = bgm {
+        if let Ok(sink) = sinks.get(bgm.0) {
+            sink.stop();
+        }
+        commands.entity(bgm.0).despawn();
+        commands.remove_resource::<BgmEntity>();
+    }
+    let path = if *state.get() == GameState::Win {
+        "win.ogg"
+    } else {
+        "gameover.ogg"
+    };
+    commands.spawn((
+        AudioBundle {
+            source: asset_server.load(path),
+            settings: PlaybackSettings::ONCE
+                .with_volume(Volume::new_absolute(audio.music_volume())),
+        },
+        BgmSink,
+        Jingle,
+        SoundEffect,
+    ));
+    commands.insert_resource(JingleState::default());
+}
+
+/// Waits for the jingle to end, then reveals the buttons held back until
+/// then. The timer covers a jingle that never loads.
+fn jingle_system(
+    time: Res<Time>,
+    mut jingle: ResMut<JingleState>,
+    jingle_query: Query<&AudioSink, With<Jingle>>,
+    mut held_back: Query<&mut Visibility, With<AfterJingle>>,
+) {
+    if jingle_query.iter().any(|sink| sink.empty()) {
+        jingle.finished = true;
+    }
+    if jingle.jingle_timer.tick(time.delta()).finished() {
+        jingle.finished = true;
+    }
+    if jingle.finished {
+        for mut visibility in held_back.iter_mut() {
+            if *visibility == Visibility::Hidden {
+                *visibility = Visibility::Inherited;
+            }
+        }
+    }
+}
+
+/// Starts each new enemy's footsteps looping as a spatial sound, silent
+/// until `enemy_walk_sound_system` sets its volume.
+fn start_walk_sound_system(
+    mut commands: Commands,
+    query: Query<(Entity, &WalkSound), Added<WalkSound>>,
+) {
+    for (entity, walk_sound) in query.iter() {
+        commands.entity(entity).insert((
+            AudioBundle {
+                source: walk_sound.0.clone(),
+                settings: PlaybackSettings::LOOP
+                    .with_spatial(true)
+                    .with_volume(Volume::new_absolute(0.0)),
+            },
+            StereoPan::default(),
+        ));
+    }
+}
+
+/// Pans each enemy's footsteps by how far left or right of the player it
+/// is, and fades them with distance. Enemies standing still, or any while
+/// the game isn't being played, are silent.
+fn enemy_walk_sound_system(
+    state: Res<State<GameState>>,
+    sfx_volume: Res<SfxVolume>,
+    player_query: Query<&Transform, (With<Player>, Without<Player2>)>,
+    mut enemy_query: Query<
+        (&Transform, &Velocity, &mut StereoPan, &SpatialAudioSink),
+        (With<Enemy>, Without<Player>),
+    >,
+) {
+    let player_x = player_query
+        .get_single()
+        .map_or(0.0, |transform| transform.translation.x);
+    let playing = *state.get() == GameState::Playing;
+    for (transform, velocity, mut pan, sink) in enemy_query.iter_mut() {
+        pan.0 = ((transform.translation.x - player_x) / ENEMY_SOUND_RANGE).clamp(-1.0, 1.0);
+        let volume = if playing && velocity.x != 0.0 {
+            sfx_volume.0 * ENEMY_WALK_VOLUME * (1.0 - pan.0.abs())
+        } else {
+            0.0
+        };
+        sink.set_volume(volume);
+    }
+}
+
+/// Despawns sound effects that have finished playing.
+fn audio_cleanup_system(
+    mut commands: Commands,
+    query: Query<(Entity, &AudioSink), With<SoundEffect>>,
+) {
+    for (entity, sink) in query.iter() {
+        if sink.empty() {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+/// Pushes changed settings to the window, the audio and the movement tuning.
+fn apply_settings_system(
+    settings: Res<Settings>,
+    mut audio: ResMut<AudioSettings>,
+    mut tuning: ResMut<MovementTuning>,
+    mut zoom: ResMut<CameraZoom>,
+    mut window_query: Query<&mut Window, With<PrimaryWindow>>,
+) {
+    audio.set_if_neq(settings.audio.clone());
+    *tuning = settings.movement.clone();
+    zoom.chosen = settings.zoom;
+    zoom.auto = settings.auto_zoom;
+
+    let (width, height) = settings.resolution;
+    if let Ok(mut window) = window_query.get_single_mut() {
+        window.resolution.set(width as f32, height as f32);
+    }
+}
+
+/// Sets every playing sound to its new volume, and the volume new sound
+/// effects will play at.
+fn apply_audio_settings_system(
+    audio: Res<AudioSettings>,
+    mut sfx_volume: ResMut<SfxVolume>,
+    bgm_sinks: Query<&AudioSink, With<BgmSink>>,
+    sfx_sinks: Query<&AudioSink, (With<SoundEffect>, Without<BgmSink>)>,
+) {
+    sfx_volume.0 = audio.sfx_volume();
+    for sink in bgm_sinks.iter() {
+        sink.set_volume(audio.music_volume());
+    }
+    for sink in sfx_sinks.iter() {
+        sink.set_volume(audio.sfx_volume());
+    }
+}
+
+/// Spawns the settings panel: three volume sliders and a row of resolution buttons.
+fn setup_settings_menu(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    settings: Res<Settings>,
+) {
+    let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    row_gap: Val::Px(20.0),
+                    ..default()
+                },
+                background_color: Color::rgb(0.1, 0.1, 0.2).into(),
+                ..default()
+            },
+            SettingsEntity,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                "Settings",
+                TextStyle {
+                    font: font.clone(),
+                    font_size: 60.0,
+                    color: Color::WHITE,
+                },
+            ));
+            spawn_slider(
+                parent,
+                &font,
+                "Master",
+                VolumeSlider::Master,
+                settings.audio.master,
+            );
+            spawn_slider(
+                parent,
+                &font,
+                "Music",
+                VolumeSlider::Music,
+                settings.audio.music,
+            );
+            spawn_slider(parent, &font, "SFX", VolumeSlider::Sfx, settings.audio.sfx);
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        column_gap: Val::Px(10.0),
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|row| {
+                    for (width, height) in RESOLUTIONS {
+                        spawn_menu_button(
+                            row,
+                            &font,
+                            &format!("{}x{}", width, height),
+                            MenuButton::Resolution(width, height),
+                        );
+                    }
+                });
+            spawn_menu_button(parent, &font, "Controls", MenuButton::Controls);
+            spawn_menu_button(parent, &font, "Back", MenuButton::Back);
+        });
+}
+
+/// Spawns a labelled 0-1 slider as a child of `parent`.
+fn spawn_slider(
+    parent: &mut ChildBuilder,
+    font: &Handle<Font>,
+    label: &str,
+    kind: VolumeSlider,
+    value: f32,
+) {
+    let slider = Slider {
+        value,
+        min: 0.0,
+        max: 1.0,
+    };
+    let thumb_left = slider.fraction() * 100.0;
+    parent
+        .spawn(NodeBundle {
+            style: Style {
+                align_items: AlignItems::Center,
+                column_gap: Val::Px(20.0),
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|row| {
+            row.spawn(TextBundle {
+                text: Text::from_section(
+                    label,
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: 30.0,
+                        color: Color::WHITE,
+                    },
+                ),
+                style: Style {
+                    width: Val::Px(100.0),
+                    ..default()
+                },
+                ..default()
+            });
+            row.spawn((
+                ButtonBundle {
+                    style: Style {
+                        width: Val::Px(SLIDER_WIDTH),
+                        height: Val::Px(12.0),
+                        ..default()
+                    },
+                    background_color: NORMAL_BUTTON.into(),
+                    ..default()
+                },
+                slider,
+                kind,
+            ))
+            .with_children(|track| {
+                track.spawn((
+                    NodeBundle {
+                        style: Style {
+                            position_type: PositionType::Absolute,
+                            left: Val::Percent(thumb_left),
+                            top: Val::Px(6.0 - SLIDER_THUMB_SIZE.y / 2.0),
+                            margin: UiRect::left(Val::Px(-SLIDER_THUMB_SIZE.x / 2.0)),
+                            width: Val::Px(SLIDER_THUMB_SIZE.x),
+                            height: Val::Px(SLIDER_THUMB_SIZE.y),
+                            ..default()
+                        },
+                        background_color: Color::WHITE.into(),
+                        ..default()
+                    },
+                    SliderThumb,
+                ));
+            });
+        });
+}
+
+/// Sets a pressed slider's value from the cursor and stores it in `Settings`.
+fn slider_drag_system(
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    mut slider_query: Query<(
+        &Interaction,
+        &Node,
+        &GlobalTransform,
+        &mut Slider,
+        &VolumeSlider,
+    )>,
+    mut settings: ResMut<Settings>,
+) {
+    let Some(cursor) = window_query
+        .get_single()
+        .ok()
+        .and_then(|window| window.cursor_position())
+    else {
+        return;
+    };
+    for (interaction, node, transform, mut slider, kind) in slider_query.iter_mut() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        let width = node.size().x;
+        let left = transform.translation().x - width / 2.0;
+        let fraction = ((cursor.x - left) / width).clamp(0.0, 1.0);
+        let value = slider.min + fraction * (slider.max - slider.min);
+        if slider.value == value {
+            continue;
+        }
+        slider.value = value;
+        match kind {
+            VolumeSlider::Master => settings.audio.master = value,
+            VolumeSlider::Music => settings.audio.music = value,
+            VolumeSlider::Sfx => settings.audio.sfx = value,
+        }
+    }
+}
+
+/// Moves each slider's thumb to match its value.
+fn slider_thumb_system(
+    slider_query: Query<(&Slider, &Children), Changed<Slider>>,
+    mut thumb_query: Query<&mut Style, With<SliderThumb>>,
+) {
+    for (slider, children) in slider_query.iter() {
+        for &child in children.iter() {
+            if let Ok(mut style) = thumb_query.get_mut(child) {
+                style.left = Val::Percent(slider.fraction() * 100.0);
+            }
+        }
+    }
+}
+
+/// Applies resolution buttons and returns to the main menu.
+fn settings_menu_system(
+    query: Query<(&Interaction, &MenuButton), Changed<Interaction>>,
+    mut settings: ResMut<Settings>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    for (interaction, button) in query.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        match button {
+            MenuButton::Resolution(width, height) => settings.resolution = (*width, *height),
+            MenuButton::Controls => next_state.set(GameState::Controls),
+            MenuButton::Back => next_state.set(GameState::MainMenu),
+            _ => {}
+        }
+    }
+}
+
+/// Despawns the settings menu when leaving it.
+fn cleanup_settings_menu(mut commands: Commands, query: Query<Entity, With<SettingsEntity>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+/// Spawns the controls screen, with a button for each of an action's two
+/// keys.
+fn setup_controls_menu(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    key_bindings: Res<KeyBindings>,
+) {
+    let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    row_gap: Val::Px(10.0),
+                    ..default()
+                },
+                background_color: Color::rgb(0.1, 0.1, 0.2).into(),
+                ..default()
+            },
+            ControlsEntity,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                "Controls",
+                TextStyle {
+                    font: font.clone(),
+                    font_size: 60.0,
+                    color: Color::WHITE,
+                },
+            ));
+            for action in BoundAction::ALL {
+                parent
+                    .spawn(NodeBundle {
+                        style: Style {
+                            align_items: AlignItems::Center,
+                            column_gap: Val::Px(20.0),
+                            ..default()
+                        },
+                        ..default()
+                    })
+                    .with_children(|row| {
+                        row.spawn(TextBundle {
+                            text: Text::from_section(
+                                action.label(),
+                                TextStyle {
+                                    font: font.clone(),
+                                    font_size: 30.0,
+                                    color: Color::WHITE,
+                                },
+                            ),
+                            style: Style {
+                                width: Val::Px(100.0),
+                                ..default()
+                            },
+                            ..default()
+                        });
+                        for slot in [KeySlot::Primary, KeySlot::Secondary] {
+                            spawn_menu_button(
+                                row,
+                                &font,
+                                &key_bindings.keys(action).slot_label(slot),
+                                MenuButton::Rebind(action, slot),
+                            );
+                        }
+                    });
+            }
+            spawn_menu_button(parent, &font, "Back", MenuButton::Back);
+        });
+}
+
+/// Clicking one of an action's buttons waits for the next key press and
+/// binds it to that slot, saving the bindings right away. Escape cancels,
+/// and Backspace clears a secondary key.
+fn controls_menu_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut key_bindings: ResMut<KeyBindings>,
+    mut capturing: Local<Option<(BoundAction, KeySlot)>>,
+    interaction_query: Query<(&Interaction, &MenuButton), Changed<Interaction>>,
+    button_query: Query<(&MenuButton, &Children)>,
+    mut text_query: Query<&mut Text>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let mut relabel = |action: BoundAction, slot: KeySlot, label: String| {
+        for (button, children) in button_query.iter() {
+            if *button != MenuButton::Rebind(action, slot) {
+                continue;
+            }
+            for &child in children.iter() {
+                if let Ok(mut text) = text_query.get_mut(child) {
+                    text.sections[0].value = label.clone();
+                }
+            }
+        }
+    };
+
+    if let Some((action, slot)) = *capturing {
+        if let Some(&key) = keyboard_input.get_just_pressed().next() {
+            let keys = key_bindings.keys_mut(action);
+            match (key, slot) {
+                (KeyCode::Escape, _) => {}
+                (KeyCode::Back, KeySlot::Secondary) => keys.secondary = None,
+                (key, KeySlot::Primary) => keys.primary = key,
+                (key, KeySlot::Secondary) => keys.secondary = Some(key),
+            }
+            relabel(action, slot, key_bindings.keys(action).slot_label(slot));
+            write_save_file(&data_file(KEY_BINDINGS_FILE), &*key_bindings);
+            *capturing = None;
+        }
+        return;
+    }
+
+    for (interaction, button) in interaction_query.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        match button {
+            MenuButton::Rebind(action, slot) => {
+                relabel(*action, *slot, "Press a key".to_string());
+                *capturing = Some((*action, *slot));
+            }
+            MenuButton::Back => next_state.set(GameState::Settings),
+            _ => {}
+        }
+    }
+}
+
+/// Despawns the controls screen when leaving it.
+fn cleanup_controls_menu(mut commands: Commands, query: Query<Entity, With<ControlsEntity>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+/// Spawns the lifetime stats screen.
+fn setup_stats_screen(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    stats: Res<PlayerStats>,
+) {
+    let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+    let minutes = (stats.total_play_seconds / 60.0).floor();
+    let seconds = stats.total_play_seconds - minutes * 60.0;
+    let fastest_clear = stats.fastest_clear.map_or("--".to_string(), |seconds| {
+        format_tenths((seconds * 10.0) as u32)
+    });
+    let lines = [
+        format!("Enemies defeated: {}", stats.enemies_killed),
+        format!("Jumps: {}", stats.jumps_made),
+        format!("Deaths: {}", stats.deaths),
+        format!("Falls: {}", stats.falls),
+        format!("Coins collected: {}", stats.coins_collected),
+        format!("Time played: {}m {:.0}s", minutes, seconds),
+        format!("Fastest clear: {}", fastest_clear),
+    ];
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    row_gap: Val::Px(20.0),
+                    ..default()
+                },
+                background_color: Color::rgb(0.1, 0.1, 0.2).into(),
+                ..default()
+            },
+            StatsEntity,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                "Stats",
+                TextStyle {
+                    font: font.clone(),
+                    font_size: 60.0,
+                    color: Color::WHITE,
+                },
+            ));
+            for line in lines {
+                parent.spawn(TextBundle::from_section(
+                    line,
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: 30.0,
+                        color: Color::WHITE,
+                    },
+                ));
+            }
+            spawn_menu_button(parent, &font, "Back", MenuButton::Back);
+        });
+}
+
+/// Returns to the main menu from the stats screen.
+fn stats_screen_system(
+    query: Query<(&Interaction, &MenuButton), Changed<Interaction>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    for (interaction, button) in query.iter() {
+        if *interaction == Interaction::Pressed && matches!(button, MenuButton::Back) {
+            next_state.set(GameState::MainMenu);
+        }
+    }
+}
+
+/// Despawns the stats screen when leaving it.
+fn cleanup_stats_screen(mut commands: Commands, query: Query<Entity, With<StatsEntity>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+/// Spawns a grid of level cards. Levels past the next unplayed one are
+/// greyed out and can't be clicked.
+fn setup_level_select(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    progress: Res<ProgressResource>,
+    level_count: Res<LevelCount>,
+) {
+    let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    row_gap: Val::Px(20.0),
+                    ..default()
+                },
+                background_color: Color::rgb(0.1, 0.1, 0.2).into(),
+                ..default()
+            },
+            LevelSelectEntity,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                "Select Level",
+                TextStyle {
+                    font: font.clone(),
+                    font_size: 60.0,
+                    color: Color::WHITE,
+                },
+            ));
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        display: Display::Grid,
+                        grid_template_columns: RepeatedGridTrack::px(4, 160.0),
+                        row_gap: Val::Px(15.0),
+                        column_gap: Val::Px(15.0),
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|grid| {
+                    for level in 1..=level_count.0 {
+                        let unlocked = level <= progress.highest_completed + 1;
+                        let stars = progress.best_scores.get(&level).map_or(0, |&best| {
+                            let thresholds = read_level_config(level)
+                                .map_or(DEFAULT_STAR_THRESHOLDS, |config| config.star_thresholds);
+                            star_rating(best, thresholds)
+                        });
+                        spawn_level_card(grid, &font, level, unlocked, stars);
+                    }
+                });
+            spawn_menu_button(parent, &font, "Back", MenuButton::Back);
+        });
+}
+
+/// Spawns one level card: number, preview color and earned stars.
+fn spawn_level_card(
+    grid: &mut ChildBuilder,
+    font: &Handle<Font>,
+    level: u32,
+    unlocked: bool,
+    stars: usize,
+) {
+    let preview = if unlocked {
+        LEVEL_PREVIEW_COLORS[(level as usize - 1) % LEVEL_PREVIEW_COLORS.len()]
+    } else {
+        LOCKED_CARD
+    };
+    let style = Style {
+        height: Val::Px(120.0),
+        flex_direction: FlexDirection::Column,
+        align_items: AlignItems::Center,
+        justify_content: JustifyContent::Center,
+        row_gap: Val::Px(10.0),
+        ..default()
+    };
+    let children = |card: &mut ChildBuilder| {
+        card.spawn(TextBundle::from_section(
+            format!("Level {}", level),
+            TextStyle {
+                font: font.clone(),
+                font_size: 30.0,
+                color: Color::WHITE,
+            },
+        ));
+        card.spawn(NodeBundle {
+            style: Style {
+                column_gap: Val::Px(5.0),
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|row| {
+            for index in 0..3 {
+                row.spawn(NodeBundle {
+                    style: Style {
+                        width: Val::Px(CARD_STAR_SIZE),
+                        height: Val::Px(CARD_STAR_SIZE),
+                        ..default()
+                    },
+                    background_color: if index < stars {
+                        Color::GOLD.into()
+                    } else {
+                        Color::DARK_GRAY.into()
+                    },
+                    ..default()
+                });
+            }
+        });
+    };
+
+    // Locked cards are plain nodes so they never receive interactions.
+    if unlocked {
+        grid.spawn((
+            ButtonBundle {
+                style,
+                background_color: preview.into(),
+                ..default()
+            },
+            MenuButton::SelectLevel(level),
+            LevelCard,
+        ))
+        .with_children(children);
+    } else {
+        grid.spawn(NodeBundle {
+            style,
+            background_color: preview.into(),
+            ..default()
+        })
+        .with_children(children);
+    }
+}
+
+/// Starts the chosen level, or goes back to the main menu.
+fn level_select_system(
+    query: Query<(&Interaction, &MenuButton), Changed<Interaction>>,
+    mut current_level: ResMut<CurrentLevel>,
+    mut total_score: ResMut<TotalScore>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    for (interaction, button) in query.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        match button {
+            MenuButton::SelectLevel(level) => {
+                current_level.0 = *level;
+                total_score.0 = 0;
+                next_state.set(GameState::Playing);
+            }
+            MenuButton::Back => next_state.set(GameState::MainMenu),
+            _ => {}
+        }
+    }
+}
+
+/// Despawns the level select screen when leaving it.
+fn cleanup_level_select(mut commands: Commands, query: Query<Entity, With<LevelSelectEntity>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+/// Spawns the pause overlay with Resume / Restart / Quit to Menu buttons,
+/// with Resume picked for the keyboard.
+fn setup_pause_menu(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(PauseSelection::default());
+    let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    position_type: PositionType::Absolute,
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    row_gap: Val::Px(20.0),
+                    ..default()
+                },
+                background_color: Color::rgba(0.0, 0.0, 0.0, 0.7).into(),
+                ..default()
+            },
+            PauseMenuEntity,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                "Paused",
+                TextStyle {
+                    font: font.clone(),
+                    font_size: 80.0,
+                    color: Color::WHITE,
+                },
+            ));
+            spawn_menu_button(parent, &font, "Resume", MenuButton::Resume);
+            spawn_menu_button(parent, &font, "Restart", MenuButton::Restart);
+            spawn_menu_button(parent, &font, "Quit to Menu", MenuButton::QuitToMenu);
+        });
+}
+
+/// Pauses the game when Escape is pressed.
+fn pause_input_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if keyboard_input.just_pressed(KeyCode::Escape) {
+        next_state.set(GameState::Paused);
+    }
+}
+
+/// Resumes the game when Escape is pressed on the pause menu.
+fn resume_input_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if keyboard_input.just_pressed(KeyCode::Escape) {
+        next_state.set(GameState::Playing);
+    }
+}
+
+/// Moves through the pause menu buttons with Up and Down, and presses the
+/// picked one with Enter. Hovering a button with the mouse picks it too.
+fn pause_menu_navigation_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut selection: ResMut<PauseSelection>,
+    menu_query: Query<&Children, With<PauseMenuEntity>>,
+    mut button_query: Query<(&mut Interaction, &mut BackgroundColor), With<MenuButton>>,
+) {
+    let Ok(children) = menu_query.get_single() else {
+        return;
+    };
+    let buttons: Vec<Entity> = children
+        .iter()
+        .copied()
+        .filter(|&child| button_query.contains(child))
+        .collect();
+    if buttons.is_empty() {
+        return;
+    }
+    for (index, &button) in buttons.iter().enumerate() {
+        if let Ok((interaction, _)) = button_query.get_mut(button) {
+            if interaction.is_changed() && *interaction == Interaction::Hovered {
+                selection.0 = index;
+            }
+        }
+    }
+    if keyboard_input.just_pressed(KeyCode::Up) {
+        selection.0 = (selection.0 + buttons.len() - 1) % buttons.len();
+    }
+    if keyboard_input.just_pressed(KeyCode::Down) {
+        selection.0 = (selection.0 + 1) % buttons.len();
+    }
+    if selection.is_changed() {
+        for (index, &button) in buttons.iter().enumerate() {
+            if let Ok((_, mut background)) = button_query.get_mut(button) {
+                background.0 = if index == selection.0 {
+                    HOVERED_BUTTON
+                } else {
+                    NORMAL_BUTTON
+                };
+            }
+        }
+    }
+    // Pressing the button lets `run_menu_system` act on it as for a click.
+    if keyboard_input.just_pressed(KeyCode::Return) {
+        if let Ok((mut interaction, _)) = button_query.get_mut(buttons[selection.0]) {
+            *interaction = Interaction::Pressed;
+        }
+    }
+}
+
+/// Handles the Resume / Restart / Quit to Menu buttons shared by the pause
+/// and game-over screens.
+fn run_menu_system(
+    mut commands: Commands,
+    query: Query<(&Interaction, &MenuButton), Changed<Interaction>>,
+    game_entities: Query<Entity, With<GameEntity>>,
+    mut current_level: ResMut<CurrentLevel>,
+    level_count: Res<LevelCount>,
+    mut total_score: ResMut<TotalScore>,
+    hardcore: Option<Res<Hardcore>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    for (interaction, button) in query.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        match button {
+            MenuButton::Resume => next_state.set(GameState::Playing),
+            MenuButton::Restart => {
+                reset_run(&mut commands, &game_entities);
+                if hardcore.is_some() {
+                    current_level.0 = 1;
+                    total_score.0 = 0;
+                }
+                next_state.set(GameState::Playing);
+            }
+            MenuButton::QuitToMenu => {
+                reset_run(&mut commands, &game_entities);
+                next_state.set(GameState::MainMenu);
+            }
+            MenuButton::NextLevel => advance_level(
+                &mut commands,
+                &game_entities,
+                &mut current_level,
+                &level_count,
+                &mut next_state,
+            ),
+            _ => {}
+        }
+    }
+}
+
+/// Starts the level after the current one, or shows the victory screen
+/// once the last level is cleared.
+fn advance_level(
+    commands: &mut Commands,
+    game_entities: &Query<Entity, With<GameEntity>>,
+    current_level: &mut CurrentLevel,
+    level_count: &LevelCount,
+    next_state: &mut NextState<GameState>,
+) {
+    reset_run(commands, game_entities);
+    if current_level.0 < level_count.0 {
+        current_level.0 += 1;
+        next_state.set(GameState::Playing);
+    } else {
+        next_state.set(GameState::Victory);
+    }
+}
+
+/// Tears down the current level and resets the run counters. The level is
+/// spawned again the next time `GameState::Playing` is entered.
+fn reset_run(commands: &mut Commands, game_entities: &Query<Entity, With<GameEntity>>) {
+    for entity in game_entities.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<LevelLoaded>();
+    commands.remove_resource::<LevelConfig>();
+    commands.remove_resource::<TileMap>();
+    commands.insert_resource(Score(0));
+    commands.insert_resource(Lives(MAX_LIVES));
+    commands.insert_resource(RunStats::default());
+    commands.insert_resource(ComboState::default());
+    commands.insert_resource(CoinPitchTracker::default());
+    commands.insert_resource(WaveSpawner::default());
+    commands.insert_resource(AllClearTimer::default());
+    commands.insert_resource(ActivityMultiplier::default());
+    commands.insert_resource(DoorObjective::default());
+    commands.insert_resource(LevelTimer::default());
+    commands.insert_resource(TimeAttackTimer::default());
+}
+
+/// Forgets keys pressed while paused, so they don't act on the first frame
+/// of play, such as a jump pressed just before resuming.
+fn clear_paused_input(
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+    mut gamepad_buttons: ResMut<Input<GamepadButton>>,
+    mut intent: ResMut<PlayerIntent>,
+    mut intent2: ResMut<Player2Intent>,
+) {
+    keyboard_input.clear();
+    gamepad_buttons.clear();
+    *intent = PlayerIntent::default();
+    intent2.0 = PlayerIntent::default();
+}
+
+/// Despawns the pause menu when leaving the paused state.
+fn cleanup_pause_menu(mut commands: Commands, query: Query<Entity, With<PauseMenuEntity>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+/// Records the high score and starts the pause before the game-over screen.
+fn enter_game_over(
+    mut commands: Commands,
+    score: Res<Score>,
+    current_level: Res<CurrentLevel>,
+    game_mode: Res<GameMode>,
+    mut high_scores: ResMut<HighScores>,
+) {
+    high_scores.record_in(*game_mode, current_level.0, score.0);
+    commands.insert_resource(GameOverDelay(Timer::from_seconds(
+        GAME_OVER_DELAY,
+        TimerMode::Once,
+    )));
+}
+
+/// Shows the game-over screen once the delay has run out.
+fn game_over_screen_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut delay: ResMut<GameOverDelay>,
+    asset_server: Res<AssetServer>,
+    score: Res<Score>,
+    high_scores: Res<HighScores>,
+    current_level: Res<CurrentLevel>,
+    game_mode: Res<GameMode>,
+    run_stats: Res<RunStats>,
+    level_timer: Res<LevelTimer>,
+) {
+    if !delay.0.tick(time.delta()).just_finished() {
+        return;
+    }
+
+    let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+    let fade_in = || FadeIn::new(1.0);
+    let text_style = |font_size: f32, color: Color| TextStyle {
+        font: font.clone(),
+        font_size,
+        color: color.with_a(0.0),
+    };
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    position_type: PositionType::Absolute,
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    row_gap: Val::Px(20.0),
+                    ..default()
+                },
+                background_color: Color::rgba(0.0, 0.0, 0.0, 0.7).into(),
+                ..default()
+            },
+            GameOverEntity,
+        ))
+        .with_children(|parent| {
+            let title = if level_timer.timed_out() {
+                "TIME'S UP!"
+            } else {
+                "GAME OVER"
+            };
+            parent.spawn((
+                TextBundle::from_section(title, text_style(80.0, Color::RED)),
+                fade_in(),
+            ));
+            parent.spawn((
+                TextBundle::from_section(
+                    format!("Score: {}", score.0),
+                    text_style(40.0, Color::WHITE),
+                ),
+                fade_in(),
+            ));
+            let best = high_scores
+                .best_in(*game_mode, current_level.0)
+                .unwrap_or(0);
+            parent.spawn((
+                TextBundle::from_section(format!("Best: {}", best), text_style(40.0, Color::GOLD)),
+                fade_in(),
+            ));
+            if *game_mode == GameMode::Endless {
+                parent.spawn((
+                    TextBundle::from_section(
+                        format!(
+                            "Survived: {}",
+                            format_clock(level_timer.elapsed.elapsed_secs())
+                        ),
+                        text_style(40.0, Color::WHITE),
+                    ),
+                    fade_in(),
+                ));
+            }
+            parent.spawn((
+                TextBundle::from_section(
+                    format!(
+                        "Enemies stomped: {}   Coins: {}   Blocks broken: {}",
+                        run_stats.enemies_stomped,
+                        run_stats.coins_collected,
+                        run_stats.blocks_broken
+                    ),
+                    text_style(30.0, Color::WHITE),
+                ),
+                fade_in(),
+            ));
+            parent
+                .spawn((
+                    NodeBundle {
+                        visibility: Visibility::Hidden,
+                        ..default()
+                    },
+                    AfterJingle,
+                ))
+                .with_children(|row| {
+                    spawn_menu_button(row, &font, "Retry", MenuButton::Restart);
+                });
+            spawn_menu_button(parent, &font, "Main Menu", MenuButton::QuitToMenu);
+        });
+}
+
+/// Lets R press Retry and Escape press Main Menu on the game-over screen,
+/// once the button is showing.
+fn game_over_keys_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut button_query: Query<(&MenuButton, &mut Interaction, &InheritedVisibility)>,
+) {
+    let wanted = if keyboard_input.just_pressed(KeyCode::R) {
+        MenuButton::Restart
+    } else if keyboard_input.just_pressed(KeyCode::Escape) {
+        MenuButton::QuitToMenu
+    } else {
+        return;
+    };
+    for (button, mut interaction, visibility) in button_query.iter_mut() {
+        if *button == wanted && visibility.get() {
+            *interaction = Interaction::Pressed;
+        }
+    }
+}
+
+/// Fades text and node backgrounds in by raising their alpha as the timer runs.
+fn fade_in_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut query: Query<(
+        Entity,
+        &mut FadeIn,
+        Option<&mut Text>,
+        Option<&mut BackgroundColor>,
+    )>,
+) {
+    for (entity, mut fade_in, text, background) in query.iter_mut() {
+        let alpha = fade_in.timer.tick(time.delta()).percent() * fade_in.target_alpha;
+        if let Some(mut text) = text {
+            for section in text.sections.iter_mut() {
+                section.style.color.set_a(alpha);
+            }
+        }
+        if let Some(mut background) = background {
+            background.0.set_a(alpha);
+        }
+        if fade_in.timer.finished() {
+            commands.entity(entity).remove::<FadeIn>();
+        }
+    }
+}
+
+/// Spawns the win screen: title, final score, time, best score and a star
+/// rating whose stars light up one at a time.
+fn setup_win_screen(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    score: Res<Score>,
+    mut high_scores: ResMut<HighScores>,
+    level_timer: Res<LevelTimer>,
+    level_config: Option<Res<LevelConfig>>,
+    win_condition: Res<WinCondition>,
+    current_level: Res<CurrentLevel>,
+    level_count: Res<LevelCount>,
+    game_mode: Res<GameMode>,
+    time_attack: Res<TimeAttackTimer>,
+    mut progress: ResMut<ProgressResource>,
+    mut total_score: ResMut<TotalScore>,
+    mut stats: ResMut<PlayerStats>,
+) {
+    total_score.0 += score.0;
+    commands.insert_resource(LevelAdvanceTimer(Timer::from_seconds(
+        LEVEL_ADVANCE_DELAY,
+        TimerMode::Once,
+    )));
+    let previous_best = high_scores.record(current_level.0, score.0);
+    let thresholds = level_config.map_or(DEFAULT_STAR_THRESHOLDS, |config| config.star_thresholds);
+    let stars = star_rating(score.0, thresholds);
+
+    progress.highest_completed = progress.highest_completed.max(current_level.0);
+    let best = progress
+        .best_scores
+        .entry(current_level.0)
+        .or_insert(score.0);
+    *best = (*best).max(score.0);
+
+    let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+    let text_style = |font_size: f32, color: Color| TextStyle {
+        font: font.clone(),
+        font_size,
+        color,
+    };
+    // Time attack is judged on time rather than score.
+    let (time_line, best_line) = if *game_mode == GameMode::TimeAttack {
+        let run_time = time_attack.0.elapsed_secs();
+        let best_line = match high_scores.record_time(current_level.0, run_time) {
+            Some(best) if run_time >= best => format!("Best: {}", format_run_time(best)),
+            _ => "New best time!".to_string(),
+        };
+        (format!("Time: {}", format_run_time(run_time)), best_line)
+    } else {
+        let best_line = match previous_best {
+            Some(best) if score.0 <= best => format!("Best: {}", best),
+            _ => "New best!".to_string(),
+        };
+        let clear_time = level_timer.elapsed.elapsed_secs();
+        if *game_mode == GameMode::Story {
+            stats.fastest_clear = Some(stats.fastest_clear.unwrap_or(clear_time).min(clear_time));
+        }
+        (
+            format!("Time: {}", format_tenths((clear_time * 10.0) as u32)),
+            best_line,
+        )
+    };
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    position_type: PositionType::Absolute,
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    row_gap: Val::Px(20.0),
+                    ..default()
+                },
+                background_color: Color::rgba(0.0, 0.1, 0.0, 0.0).into(),
+                ..default()
+            },
+            FadeIn::new(0.8),
+            WinScreenEntity,
+        ))
+        .with_children(|parent| {
+            let title = if *game_mode == GameMode::Survival {
+                "SURVIVED!"
+            } else if *win_condition == WinCondition::ReachGoal {
+                "LEVEL CLEAR!"
+            } else {
+                "YOU WIN!"
+            };
+            parent.spawn(TextBundle::from_section(
+                title,
+                text_style(80.0, Color::GREEN),
+            ));
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        column_gap: Val::Px(15.0),
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|row| {
+                    for index in 0..3 {
+                        let mut star = row.spawn(NodeBundle {
+                            style: Style {
+                                width: Val::Px(STAR_SIZE),
+                                height: Val::Px(STAR_SIZE),
+                                ..default()
+                            },
+                            background_color: Color::DARK_GRAY.into(),
+                            ..default()
+                        });
+                        if index < stars {
+                            star.insert(StarReveal(Timer::from_seconds(
+                                STAR_REVEAL_INTERVAL * (index + 1) as f32,
+                                TimerMode::Once,
+                            )));
+                        }
+                    }
+                });
+            parent.spawn(TextBundle::from_section(
+                format!("Score: {}", score.0),
+                text_style(40.0, Color::WHITE),
+            ));
+            parent.spawn(TextBundle::from_section(
+                time_line,
+                text_style(40.0, Color::WHITE),
+            ));
+            parent.spawn(TextBundle::from_section(
+                best_line,
+                text_style(40.0, Color::GOLD),
+            ));
+            let next_label = if current_level.0 < level_count.0 {
+                "Next Level"
+            } else {
+                "Continue"
+            };
+            parent
+                .spawn((
+                    NodeBundle {
+                        visibility: Visibility::Hidden,
+                        ..default()
+                    },
+                    AfterJingle,
+                ))
+                .with_children(|row| {
+                    spawn_menu_button(row, &font, next_label, MenuButton::NextLevel);
+                });
+            spawn_menu_button(parent, &font, "Main Menu", MenuButton::QuitToMenu);
+        });
+}
+
+/// Moves on from the win screen by itself once the delay runs out.
+fn level_advance_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut timer: ResMut<LevelAdvanceTimer>,
+    game_entities: Query<Entity, With<GameEntity>>,
+    mut current_level: ResMut<CurrentLevel>,
+    level_count: Res<LevelCount>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if timer.0.tick(time.delta()).just_finished() {
+        advance_level(
+            &mut commands,
+            &game_entities,
+            &mut current_level,
+            &level_count,
+            &mut next_state,
+        );
+    }
+}
+
+/// Spawns the final victory screen shown after the last level.
+fn setup_victory_screen(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    total_score: Res<TotalScore>,
+) {
+    let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    row_gap: Val::Px(20.0),
+                    ..default()
+                },
+                background_color: Color::rgb(0.1, 0.1, 0.2).into(),
+                ..default()
+            },
+            VictoryEntity,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                "VICTORY!",
+                TextStyle {
+                    font: font.clone(),
+                    font_size: 80.0,
+                    color: Color::GOLD,
+                },
+            ));
+            parent.spawn(TextBundle::from_section(
+                format!("Total score: {}", total_score.0),
+                TextStyle {
+                    font: font.clone(),
+                    font_size: 40.0,
+                    color: Color::WHITE,
+                },
+            ));
+            spawn_menu_button(parent, &font, "Main Menu", MenuButton::QuitToMenu);
+        });
+}
+
+/// Despawns the victory screen when leaving it.
+fn cleanup_victory_screen(mut commands: Commands, query: Query<Entity, With<VictoryEntity>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+/// Number of stars (1-3) a score earns against the level's two- and
+/// three-star thresholds. Clearing a level always earns the first.
+fn star_rating(score: i32, (two_stars, three_stars): (i32, i32)) -> usize {
+    if score >= three_stars {
+        3
+    } else if score >= two_stars {
+        2
+    } else {
+        1
+    }
+}
+
+/// Lights up the earned stars on the win screen one after another.
+fn star_reveal_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut query: Query<(Entity, &mut StarReveal, &mut BackgroundColor)>,
+) {
+    for (entity, mut reveal, mut color) in query.iter_mut() {
+        if reveal.0.tick(time.delta()).just_finished() {
+            *color = Color::GOLD.into();
+            commands.entity(entity).remove::<StarReveal>();
+        }
+    }
+}
+
+/// Despawns the win screen when leaving the win state.
+fn cleanup_win_screen(mut commands: Commands, query: Query<Entity, With<WinScreenEntity>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+/// Despawns the game-over screen when leaving the game-over state.
+fn cleanup_game_over_screen(mut commands: Commands, query: Query<Entity, With<GameOverEntity>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<GameOverDelay>();
+}
+
+//
+// GAMEPLAY SYSTEMS
+//
+
+/// Samples the keyboard and the first gamepad into `PlayerIntent`. A
+/// controller works alongside the keyboard rather than replacing it. In
+/// co-op the arrow keys belong to player 2, so player 1 climbs with W and S.
+fn player_input_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    coop: Option<Res<Coop>>,
+    gamepads: Res<Gamepads>,
+    axes: Res<Axis<GamepadAxis>>,
+    buttons: Res<Input<GamepadButton>>,
+    mut intent: ResMut<PlayerIntent>,
+    mut tutorial: ResMut<TutorialProgress>,
+) {
+    let gamepad = first_gamepad(&gamepads);
+    let stick = gamepad.map_or(Vec2::ZERO, |gamepad| {
+        let axis = |axis_type| {
+            axes.get(GamepadAxis::new(gamepad, axis_type))
+                .unwrap_or(0.0)
+        };
+        Vec2::new(
+            axis(GamepadAxisType::LeftStickX),
+            axis(GamepadAxisType::LeftStickY),
+        )
+    });
+    let pad_pressed = |button_type| {
+        gamepad.is_some_and(|gamepad| buttons.pressed(GamepadButton::new(gamepad, button_type)))
+    };
+    let pad_just_pressed = |button_type| {
+        gamepad
+            .is_some_and(|gamepad| buttons.just_pressed(GamepadButton::new(gamepad, button_type)))
+    };
+
+    let (up_keys, down_keys) = if coop.is_some() {
+        (&[KeyCode::W][..], &[KeyCode::S][..])
+    } else {
+        (
+            &[KeyCode::Up, KeyCode::W][..],
+            &[KeyCode::Down, KeyCode::S][..],
+        )
+    };
+    let mut direction = Vec2::ZERO;
+    if key_bindings.left.pressed(&keyboard_input) || stick.x < -GAMEPAD_STICK_THRESHOLD {
+        direction.x -= 1.0;
+    }
+    if key_bindings.right.pressed(&keyboard_input) || stick.x > GAMEPAD_STICK_THRESHOLD {
+        direction.x += 1.0;
+    }
+    if keyboard_input.any_pressed(up_keys.iter().copied()) || stick.y > GAMEPAD_STICK_THRESHOLD {
+        direction.y += 1.0;
+    }
+    if keyboard_input.any_pressed(down_keys.iter().copied()) || stick.y < -GAMEPAD_STICK_THRESHOLD {
+        direction.y -= 1.0;
+    }
+    intent.direction = direction;
+    intent.jump |= key_bindings.jump.just_pressed(&keyboard_input)
+        || pad_just_pressed(GamepadButtonType::South);
+    intent.dash |= key_bindings.dash.just_pressed(&keyboard_input)
+        || pad_just_pressed(GamepadButtonType::West);
+    intent.jump_held =
+        key_bindings.jump.pressed(&keyboard_input) || pad_pressed(GamepadButtonType::South);
+    tutorial.moved |= direction.x != 0.0;
+    tutorial.jumped |= intent.jump;
+}
+
+/// Samples player 2's keys into `Player2Intent`: J and L walk, I and K
+/// climb, Up jumps and N dashes.
+fn player2_input_system(keyboard_input: Res<Input<KeyCode>>, mut intent: ResMut<Player2Intent>) {
+    let axis = |negative, positive| {
+        keyboard_input.pressed(positive) as i32 as f32
+            - keyboard_input.pressed(negative) as i32 as f32
+    };
+    let intent = &mut intent.0;
+    intent.direction = Vec2::new(axis(KeyCode::J, KeyCode::L), axis(KeyCode::K, KeyCode::I));
+    intent.jump |= keyboard_input.just_pressed(KeyCode::Up);
+    intent.dash |= keyboard_input.just_pressed(KeyCode::N);
+    intent.jump_held = keyboard_input.pressed(KeyCode::Up);
+}
+
+/// Moves the players as `PlayerIntent` and `Player2Intent` ask: walking,
+/// dashing, climbing and jumping. Jump and dash presses are used up here.
+fn player_control_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    tuning: Res<MovementTuning>,
+    difficulty: Res<DifficultyMultipliers>,
+    mut intent: ResMut<PlayerIntent>,
+    mut intent2: ResMut<Player2Intent>,
+    audio_handles: Res<AudioHandles>,
+    sfx_volume: Res<SfxVolume>,
+    mut stats: ResMut<PlayerStats>,
+    mut query: Query<
+        (
+            Entity,
+            &mut Velocity,
+            &mut Transform,
+            &Grounded,
+            Has<OnLadder>,
+            Has<Climbing>,
+            Has<Dashing>,
+            Has<DashCooldown>,
+            Has<InWater>,
+            Has<Player2>,
+        ),
+        (With<Player>, Without<DyingAnimation>),
+    >,
+) {
+    let intents = [intent.take_presses(), intent2.0.take_presses()];
+    for (
+        entity,
+        mut velocity,
+        mut transform,
+        grounded,
+        on_ladder,
+        climbing,
+        dashing,
+        dash_cooldown,
+        in_water,
+        second,
+    ) in query.iter_mut()
+    {
+        let intent = intents[second as usize];
+        let (jump, dash) = (intent.jump, intent.dash);
+
+        // Water slows walking and weakens jumps.
+        let (speed_scale, jump_scale) = if in_water {
+            (WATER_SPEED_SCALE, WATER_JUMP_SCALE)
+        } else {
+            (1.0, 1.0)
+        };
+
+        // Horizontal movement: speed up toward walking pace, and slow down
+        // when letting go or going faster, e.g. after a dash.
+        let direction = intent.direction.x;
+        let target = direction * PLAYER_SPEED * speed_scale * difficulty.player_speed;
+        let (accel, friction) = if grounded.0 || climbing {
+            (tuning.ground_accel, tuning.ground_friction)
+        } else {
+            (tuning.air_accel, tuning.air_drag)
+        };
+        let slowing =
+            direction == 0.0 || (velocity.x * target > 0.0 && velocity.x.abs() > target.abs());
+        let rate = if slowing { friction } else { accel };
+        let step = rate * time.delta_seconds();
+        velocity.x += (target - velocity.x).clamp(-step, step);
+
+        // Flip sprite based on direction.
+        if direction != 0.0 {
+            transform.scale.x = transform.scale.x.abs() * direction.signum();
+        }
+
+        // Dash the way the player faces, overriding walking until it ends.
+        if dashing {
+            velocity.x = transform.scale.x.signum() * DASH_SPEED;
+        } else if !dash_cooldown && dash {
+            commands
+                .entity(entity)
+                .insert(Dashing(Timer::from_seconds(DASH_DURATION, TimerMode::Once)));
+            velocity.x = transform.scale.x.signum() * DASH_SPEED;
+        }
+
+        let up = intent.direction.y > 0.0;
+
+        // Grab a ladder with Up, and let go when jumping or leaving it.
+        let mut climbing = climbing;
+        if climbing && (!on_ladder || jump) {
+            commands.entity(entity).remove::<Climbing>();
+            climbing = false;
+        } else if !climbing && on_ladder && up && !jump {
+            commands.entity(entity).insert(Climbing);
+            climbing = true;
+        }
+
+        if climbing {
+            velocity.y = intent.direction.y * CLIMB_SPEED;
+        } else if jump && (grounded.0 || on_ladder) {
+            // Jump if standing on something or hanging off a ladder.
+            velocity.y = PLAYER_JUMP_VELOCITY * jump_scale;
+            stats.jumps_made += 1;
+            play_sound(&mut commands, &audio_handles.jump, &sfx_volume, 1.0);
+        }
+    }
+}
+
+/// Ends dashes once they have run their course and then counts down the
+/// cooldown before the next one.
+fn dash_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut query: Query<(Entity, Option<&mut Dashing>, Option<&mut DashCooldown>), With<Player>>,
+) {
+    for (entity, dashing, cooldown) in query.iter_mut() {
+        if let Some(mut dashing) = dashing {
+            if dashing.0.tick(time.delta()).finished() {
+                commands
+                    .entity(entity)
+                    .remove::<Dashing>()
+                    .insert(DashCooldown(Timer::from_seconds(
+                        DASH_COOLDOWN,
+                        TimerMode::Once,
+                    )));
+            }
+        }
+        if let Some(mut cooldown) = cooldown {
+            if cooldown.0.tick(time.delta()).finished() {
+                commands.entity(entity).remove::<DashCooldown>();
+            }
+        }
+    }
+}
+
+/// Tracks which ladder the player is touching and stops climbers at its top.
+fn ladder_overlap_system(
+    mut commands: Commands,
+    ladder_query: Query<(&Transform, &Sprite), (With<Ladder>, Without<Player>)>,
+    mut player_query: Query<
+        (
+            Entity,
+            &mut Transform,
+            &mut Velocity,
+            &Collider,
+            Has<OnLadder>,
+            Has<Climbing>,
+        ),
+        With<Player>,
+    >,
+) {
+    for (entity, mut player_transform, mut velocity, collider, on_ladder, climbing) in
+        player_query.iter_mut()
+    {
+        let player_pos = player_transform.translation;
+        let player_half = collider.half_extents;
+        let ladder_top = ladder_query.iter().find_map(|(transform, sprite)| {
+            let half = sprite.custom_size.unwrap_or_default() / 2.0;
+            let ladder_pos = transform.translation;
+            let top = ladder_pos.y + half.y;
+            // Standing level with the top still counts so the player can climb
+            // down, and climbers that overshoot it get pulled back below.
+            let reach = if climbing { top + player_half.y } else { top };
+            let touching = (player_pos.x - ladder_pos.x).abs() < player_half.x + half.x
+                && player_pos.y - player_half.y <= reach
+                && player_pos.y + player_half.y > ladder_pos.y - half.y;
+            touching.then_some(top)
+        });
+
+        match ladder_top {
+            Some(top) => {
+                if !on_ladder {
+                    commands.entity(entity).insert(OnLadder);
+                }
+                if climbing && player_pos.y - player_half.y > top {
+                    player_transform.translation.y = top + player_half.y;
+                    velocity.y = velocity.y.min(0.0);
+                }
+            }
+            None if on_ladder => {
+                commands.entity(entity).remove::<OnLadder>();
+            }
+            None => {}
+        }
+    }
+}
+
+/// Applies gravity to everything with a `GravityScale`, weakened in water.
+/// Climbers hold on against it.
+fn apply_gravity_system(
+    time: Res<Time>,
+    gravity: Res<Gravity>,
+    mut query: Query<
+        (&mut Velocity, &GravityScale, Has<InWater>),
+        (Without<Climbing>, Without<DyingAnimation>),
+    >,
+) {
+    for (mut velocity, gravity_scale, in_water) in query.iter_mut() {
+        let water_scale = if in_water { WATER_GRAVITY_SCALE } else { 1.0 };
+        velocity.y += gravity.0 * gravity_scale.0 * water_scale * time.delta_seconds();
+    }
+}
+
+/// Caps velocities at `VelocityLimits` once gravity and input have had
+/// their say, just before anything moves.
+fn clamp_velocity_system(
+    limits: Res<VelocityLimits>,
+    mut query: Query<(Entity, &mut Velocity), Without<Unclamped>>,
+) {
+    for (entity, mut velocity) in query.iter_mut() {
+        let clamped = Vec2::new(
+            velocity
+                .x
+                .clamp(-limits.max_horizontal, limits.max_horizontal),
+            velocity.y.clamp(-limits.max_fall, limits.max_rise),
+        );
+        if clamped != velocity.0 {
+            debug!(
+                "Clamped velocity of {:?} from {} to {}",
+                entity, velocity.0, clamped
+            );
+            velocity.0 = clamped;
+        }
+    }
+}
+
+/// Marks the player and enemies whose centre is inside water. Enemies wade
+/// at half speed, and the player swims up in strokes while holding the jump key.
+fn water_physics_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    intent: Res<PlayerIntent>,
+    intent2: Res<Player2Intent>,
+    water_query: Query<(&Transform, &Sprite), With<Water>>,
+    mut player_query: Query<
+        (
+            Entity,
+            &Transform,
+            &mut Velocity,
+            Has<InWater>,
+            Option<&mut SwimCooldown>,
+            Has<Player2>,
+        ),
+        (With<Player>, Without<Enemy>),
+    >,
+    mut enemy_query: Query<(Entity, &Transform, &mut Velocity, Has<InWater>), With<Enemy>>,
+) {
+    let submerged = |position: Vec3| {
+        water_query.iter().any(|(transform, sprite)| {
+            let half = sprite.custom_size.unwrap_or(WATER_SIZE) / 2.0;
+            let offset = (position - transform.translation).truncate().abs();
+            offset.x < half.x && offset.y < half.y
+        })
+    };
+
+    for (entity, transform, mut velocity, in_water, cooldown, second) in player_query.iter_mut() {
+        let jump_held = if second {
+            intent2.0.jump_held
+        } else {
+            intent.jump_held
+        };
+        let mut cooling_down = false;
+        if let Some(mut cooldown) = cooldown {
+            if cooldown.0.tick(time.delta()).finished() {
+                commands.entity(entity).remove::<SwimCooldown>();
+            } else {
+                cooling_down = true;
+            }
+        }
+        let now_in_water = submerged(transform.translation);
+        if now_in_water != in_water {
+            if now_in_water {
+                commands.entity(entity).insert(InWater);
+            } else {
+                commands.entity(entity).remove::<InWater>();
+            }
+        }
+        if now_in_water && jump_held && !cooling_down {
+            // Strokes never cut a jump short.
+            velocity.y = velocity.y.max(SWIM_STROKE_VELOCITY);
+            commands
+                .entity(entity)
+                .insert(SwimCooldown(Timer::from_seconds(
+                    SWIM_STROKE_COOLDOWN,
+                    TimerMode::Once,
+                )));
+        }
+    }
+
+    for (entity, transform, mut velocity, in_water) in enemy_query.iter_mut() {
+        let now_in_water = submerged(transform.translation);
+        if now_in_water && !in_water {
+            velocity.x *= WATER_ENEMY_SPEED_SCALE;
+            commands.entity(entity).insert(InWater);
+        } else if !now_in_water && in_water {
+            velocity.x /= WATER_ENEMY_SPEED_SCALE;
+            commands.entity(entity).remove::<InWater>();
+        }
+    }
+}
+
+/// A box that swept movement stops at. One-way boxes only stop things
+/// falling onto them from above.
+struct SolidBox {
+    center: Vec3,
+    half: Vec2,
+    one_way: bool,
+}
+
+/// Moves all entities based on their velocity. The player is swept through
+/// the level so a fast move can't tunnel through the ground, obstacles or
+/// platforms.
+fn movement_system(
+    time: Res<Time>,
+    ground_data: Res<GroundData>,
+    level_height: Option<Res<LevelHeight>>,
+    obstacle_query: Query<(&Transform, &Collider), (With<Obstacle>, Without<Velocity>)>,
+    platform_query: Query<(&Transform, &Sprite), (With<OneWayPlatform>, Without<Velocity>)>,
+    mut query: Query<
+        (&mut Transform, &Velocity, Option<&Collider>, Has<Player>),
+        Without<DyingAnimation>,
+    >,
+) {
+    // The vertical level's ground is a death zone the player falls into.
+    let mut solids: Vec<SolidBox> = Vec::new();
+    if level_height.is_none() {
+        solids.extend(ground_data.segments.iter().map(|&(left, right)| SolidBox {
+            center: Vec3::new(
+                (left + right) / 2.0,
+                ground_data.top_y - GAP_FALL_DEPTH / 2.0,
+                0.0,
+            ),
+            half: Vec2::new((right - left) / 2.0, GAP_FALL_DEPTH / 2.0),
+            one_way: false,
+        }));
+    }
+    solids.extend(obstacle_query.iter().map(|(transform, collider)| SolidBox {
+        center: transform.translation,
+        half: collider.half_extents,
+        one_way: false,
+    }));
+    solids.extend(platform_query.iter().map(|(transform, sprite)| SolidBox {
+        center: transform.translation,
+        half: sprite.custom_size.unwrap_or(PLATFORM_SIZE) / 2.0,
+        one_way: true,
+    }));
+
+    for (mut transform, velocity, collider, is_player) in query.iter_mut() {
+        let displacement = velocity.0 * time.delta_seconds();
+        match collider {
+            Some(collider) if is_player => {
+                transform.translation = sweep_move(
+                    transform.translation,
+                    displacement,
+                    collider.half_extents,
+                    &solids,
+                );
+            }
+            _ => transform.translation += displacement.extend(0.0),
+        }
+    }
+}
+
+/// Moves a box of `half` size from `start` by `displacement`, first along x
+/// and then along y, in steps of at most `SUBSTEP_LENGTH`.
+fn sweep_move(start: Vec3, displacement: Vec2, half: Vec2, solids: &[SolidBox]) -> Vec3 {
+    let steps = (displacement.abs().max_element() / SUBSTEP_LENGTH)
+        .ceil()
+        .max(1.0) as u32;
+    let step = displacement / steps as f32;
+    let after_x = sweep_axis(start, Vec2::new(step.x, 0.0), steps, half, solids);
+    sweep_axis(after_x, Vec2::new(0.0, step.y), steps, half, solids)
+}
+
+/// Takes up to `steps` steps of `step` from `start`, stopping after the
+/// first one that touches a solid and leaving the small overlap for the
+/// collision systems to resolve. Solids already touched at `start` don't
+/// stop it.
+fn sweep_axis(start: Vec3, step: Vec2, steps: u32, half: Vec2, solids: &[SolidBox]) -> Vec3 {
+    let start_bottom = start.y - half.y;
+    let blocking: Vec<&SolidBox> = solids
+        .iter()
+        .filter(|solid| {
+            let from_above = step.y < 0.0 && start_bottom >= solid.center.y + solid.half.y;
+            (!solid.one_way || from_above) && !is_colliding(start, half, solid.center, solid.half)
+        })
+        .collect();
+    let mut position = start;
+    for _ in 0..steps {
+        position += step.extend(0.0);
+        if blocking
+            .iter()
+            .any(|solid| is_colliding(position, half, solid.center, solid.half))
+        {
+            break;
+        }
+    }
+    position
+}
+
+/// Stops the player at the edges of the level.
+fn player_bounds_system(
+    bounds: Res<LevelBounds>,
+    mut query: Query<(&mut Transform, &mut Velocity, &Collider), With<Player>>,
+) {
+    for (mut transform, mut velocity, collider) in query.iter_mut() {
+        let min_x = bounds.min_x + collider.half_extents.x;
+        let max_x = bounds.max_x - collider.half_extents.x;
+        if transform.translation.x < min_x || transform.translation.x > max_x {
+            transform.translation.x = transform.translation.x.clamp(min_x, max_x);
+            velocity.x = 0.0;
+        }
+    }
+}
+
+/// Turns enemies around at the edges of the level.
+fn enemy_bounds_system(
+    bounds: Res<LevelBounds>,
+    mut query: Query<(&mut Transform, &mut Velocity, &Collider), With<Enemy>>,
+) {
+    for (mut transform, mut velocity, collider) in query.iter_mut() {
+        let min_x = bounds.min_x + collider.half_extents.x;
+        let max_x = bounds.max_x - collider.half_extents.x;
+        let x = transform.translation.x;
+        if (x <= min_x && velocity.x < 0.0) || (x >= max_x && velocity.x > 0.0) {
+            velocity.x = -velocity.x;
+        }
+        transform.translation.x = x.clamp(min_x, max_x);
+    }
+}
+
+/// The point in `points` closest to `from`, if there are any.
+fn nearest(points: &[Vec2], from: Vec2) -> Option<Vec2> {
+    points.iter().copied().min_by(|a, b| {
+        a.distance_squared(from)
+            .total_cmp(&b.distance_squared(from))
+    })
+}
+
+/// Sets enemies chasing once the nearest player comes within their
+/// `AggroRange` and back to patrolling, at their spawn velocity, once the
+/// player is well out of it. A chasing patroller still stops at the edge of
+/// its platform.
+fn enemy_ai_system(
+    player_query: Query<&Transform, With<Player>>,
+    mut query: Query<
+        (
+            &Transform,
+            &mut Velocity,
+            &AggroRange,
+            &mut EnemyState,
+            &SpawnVelocity,
+            Option<&PlatformPatrol>,
+            Option<&mut PatrolPath>,
+            Has<InWater>,
+        ),
+        (With<Enemy>, Without<Player>, Without<Pathfinder>),
+    >,
+) {
+    let players: Vec<Vec2> = player_query
+        .iter()
+        .map(|transform| transform.translation.truncate())
+        .collect();
+    for (transform, mut velocity, aggro, mut state, spawn_velocity, patrol, route, in_water) in
+        query.iter_mut()
+    {
+        let enemy_pos = transform.translation.truncate();
+        let Some(player_pos) = nearest(&players, enemy_pos) else {
+            return;
+        };
+        let distance = (player_pos - enemy_pos).length();
+        let water_scale = if in_water {
+            WATER_ENEMY_SPEED_SCALE
+        } else {
+            1.0
+        };
+        match *state {
+            EnemyState::Patrol if distance < aggro.0 => *state = EnemyState::Chase,
+            EnemyState::Chase if distance > aggro.0 * AGGRO_RELEASE_FACTOR => {
+                *state = EnemyState::Patrol;
+                velocity.x = spawn_velocity.0 * water_scale;
+                if let Some(mut route) = route {
+                    route.current = route.nearest(enemy_pos);
+                }
+            }
+            _ => {}
+        }
+        if *state != EnemyState::Chase {
+            continue;
+        }
+        let direction = (player_pos.x - enemy_pos.x).signum();
+        let at_edge = patrol.is_some_and(|patrol| {
+            (enemy_pos.x <= patrol.min_x && direction < 0.0)
+                || (enemy_pos.x >= patrol.max_x && direction > 0.0)
+        });
+        velocity.x = if at_edge {
+            0.0
+        } else {
+            direction * spawn_velocity.0.abs() * CHASE_SPEED_FACTOR * water_scale
+        };
+    }
+}
+
+/// Walks patrolling enemies with a `PatrolPath` toward their current
+/// waypoint, moving on to the next, round and round, as each is reached.
+fn patrol_path_system(
+    mut query: Query<(
+        &Transform,
+        &mut Velocity,
+        &EnemyState,
+        &SpawnVelocity,
+        &mut PatrolPath,
+        Has<InWater>,
+    )>,
+) {
+    for (transform, mut velocity, state, spawn_velocity, mut route, in_water) in query.iter_mut() {
+        if *state != EnemyState::Patrol {
+            continue;
+        }
+        let x = transform.translation.x;
+        if (route.waypoints[route.current].x - x).abs() < PATROL_WAYPOINT_REACH {
+            route.current = (route.current + 1) % route.waypoints.len();
+        }
+        let speed = if in_water {
+            spawn_velocity.0.abs() * WATER_ENEMY_SPEED_SCALE
+        } else {
+            spawn_velocity.0.abs()
+        };
+        velocity.x = (route.waypoints[route.current].x - x).signum() * speed;
+    }
+}
+
+/// Turns enemies around at the edges of the platform they patrol.
+fn enemy_patrol_system(
+    mut query: Query<(&Transform, &mut Velocity, &PlatformPatrol), With<Enemy>>,
+) {
+    for (transform, mut velocity, patrol) in query.iter_mut() {
+        let x = transform.translation.x;
+        if (x <= patrol.min_x && velocity.x < 0.0) || (x >= patrol.max_x && velocity.x > 0.0) {
+            velocity.x = -velocity.x;
+        }
+    }
+}
+
+/// Helper function for AABB collision detection.
+pub(crate) fn is_colliding(pos_a: Vec3, half_a: Vec2, pos_b: Vec3, half_b: Vec2) -> bool {
+    (pos_a.x - half_a.x < pos_b.x + half_b.x)
+        && (pos_a.x + half_a.x > pos_b.x - half_b.x)
+        && (pos_a.y - half_a.y < pos_b.y + half_b.y)
+        && (pos_a.y + half_a.y > pos_b.y - half_b.y)
+}
+
+/// Keeps the player on top of the ground segments. Each segment is solid
+/// down to `GAP_FALL_DEPTH`, so the walls of a gap stop the player too.
+/// In vertical mode the ground is a death zone, so the player falls through.
+/// A hard landing kicks up dust.
+fn collision_system(
+    mut commands: Commands,
+    mut query: Query<
+        (&mut Transform, &mut Velocity, &mut ChainBounce, &Collider),
+        (With<Player>, Without<DyingAnimation>),
+    >,
+    ground_data: Res<GroundData>,
+    level_height: Option<Res<LevelHeight>>,
+) {
+    for (mut transform, mut velocity, mut chain, collider) in query.iter_mut() {
+        if level_height.is_some() {
+            continue;
+        }
+        for &(left, right) in &ground_data.segments {
+            let fall_speed = -velocity.y;
+            let face = push_out_of_box(
+                &mut transform,
+                &mut velocity,
+                collider.half_extents,
+                Vec3::new(
+                    (left + right) / 2.0,
+                    ground_data.top_y - GAP_FALL_DEPTH / 2.0,
+                    0.0,
+                ),
+                Vec2::new((right - left) / 2.0, GAP_FALL_DEPTH / 2.0),
+            );
+            if face == Some(ContactFace::Top) {
+                chain.count = 0;
+                if fall_speed > DUST_MIN_FALL_SPEED {
+                    let feet = transform.translation - Vec3::new(0.0, collider.half_extents.y, 0.0);
+                    spawn_landing_dust(&mut commands, feet);
+                }
+            }
+        }
+    }
+}
+
+/// Puffs a few specks of dust out to either side of `position`.
+fn spawn_landing_dust(commands: &mut Commands, position: Vec3) {
+    let mut rng = rand::thread_rng();
+    for _ in 0..rng.gen_range(4..=6) {
+        let side = if rng.gen_bool(0.5) { 1.0 } else { -1.0 };
+        let velocity = Vec2::new(side * rng.gen_range(40.0..100.0), rng.gen_range(10.0..40.0));
+        commands.spawn((
+            SpriteBundle {
+                sprite: Sprite {
+                    color: DUST_COLOR,
+                    custom_size: Some(Vec2::splat(DUST_SIZE)),
+                    ..default()
+                },
+                transform: Transform::from_translation(position + Vec3::Z),
+                ..default()
+            },
+            Velocity(velocity),
+            Interpolated::default(),
+            Debris(Timer::from_seconds(DUST_LIFETIME, TimerMode::Once)),
+            GameEntity,
+        ));
+    }
+}
+
+/// Keeps the player and enemies on top of slopes. Anything overlapping a
+/// slope with its feet near the surface under its centre is snapped onto
+/// it, and walking uphill is a little slower. Running into a slope's tall
+/// side stops the player and turns enemies around.
+fn slope_system(
+    time: Res<Time>,
+    slope_query: Query<(&Slope, &Transform, &Collider), Without<Velocity>>,
+    mut body_query: Query<
+        (
+            &mut Transform,
+            &mut Velocity,
+            &Collider,
+            Option<&mut Grounded>,
+            Option<&mut ChainBounce>,
+            Has<Enemy>,
+        ),
+        (Or<(With<Player>, With<Enemy>)>, Without<DyingAnimation>),
+    >,
+) {
+    for (mut transform, mut velocity, collider, mut grounded, mut chain, is_enemy) in
+        body_query.iter_mut()
+    {
+        if velocity.y > 0.0 {
+            continue;
+        }
+        let half = collider.half_extents;
+        for (slope, slope_transform, slope_collider) in slope_query.iter() {
+            let left = slope_transform.translation.x - slope_collider.half_extents.x;
+            let right = slope_transform.translation.x + slope_collider.half_extents.x;
+            let x = transform.translation.x;
+            if x + half.x < left || x - half.x > right {
+                continue;
+            }
+            let bottom = transform.translation.y - half.y;
+            let surface = slope.height_at((x - left) / (right - left));
+            if bottom > surface + SLOPE_SNAP {
+                continue;
+            }
+            if bottom < surface - SLOPE_SNAP {
+                // Ran into the tall side: push back out the nearer edge.
+                let side = if x - left < right - x { -1.0 } else { 1.0 };
+                let edge = if side < 0.0 { left } else { right };
+                transform.translation.x = edge + side * half.x;
+                if velocity.x * side < 0.0 {
+                    velocity.x = if is_enemy { -velocity.x } else { 0.0 };
+                }
+                continue;
+            }
+            // Only the slope under the centre carries it.
+            if x < left || x > right {
+                continue;
+            }
+
+            let rise = slope.right_y - slope.left_y;
+            if velocity.x * rise > 0.0 {
+                transform.translation.x -=
+                    velocity.x * (1.0 - SLOPE_UPHILL_SPEED) * time.delta_seconds();
+            }
+            let x = transform.translation.x;
+            transform.translation.y = slope.height_at((x - left) / (right - left)) + half.y;
+            velocity.y = 0.0;
+            if let Some(grounded) = grounded.as_mut() {
+                grounded.0 = true;
+            }
+            if let Some(chain) = chain.as_mut() {
+                chain.count = 0;
+            }
+        }
+    }
+}
+
+/// Drops enemies that walk off the edge of a gap and removes them, for no
+/// points, once they have fallen out of sight. Enemies with their own
+/// `GravityScale` are already falling, so they are only landed on the ground.
+fn enemy_gap_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    gravity: Res<Gravity>,
+    ground_data: Res<GroundData>,
+    mut query: Query<
+        (
+            Entity,
+            &mut Transform,
+            &mut Velocity,
+            &Collider,
+            Has<GravityScale>,
+        ),
+        With<Enemy>,
+    >,
+) {
+    for (entity, mut transform, mut velocity, collider, falls) in query.iter_mut() {
+        let position = transform.translation;
+        let bottom = position.y - collider.half_extents.y;
+        let supported = ground_data.supports(position.x, 0.0);
+        if bottom < ground_data.top_y - GAP_FALL_DEPTH {
+            commands.entity(entity).despawn_recursive();
+        } else if falls {
+            if supported && bottom < ground_data.top_y && velocity.y <= 0.0 {
+                transform.translation.y = ground_data.top_y + collider.half_extents.y;
+                velocity.y = 0.0;
+            }
+        } else if bottom < ground_data.top_y - 1.0 || !supported {
+            velocity.y += gravity.0 * time.delta_seconds();
+        }
+    }
+}
+
+/// Puts interpolated entities back where the last physics step left them,
+/// unless something else has moved them since.
+fn restore_interpolated_system(mut query: Query<(&mut Transform, &mut Interpolated)>) {
+    for (mut transform, mut interpolated) in query.iter_mut() {
+        if transform.translation != interpolated.rendered {
+            interpolated.current = transform.translation;
+        }
+        transform.translation = interpolated.current;
+        interpolated.previous = interpolated.current;
+    }
+}
+
+/// Saves where this physics step left each interpolated entity.
+fn record_interpolated_system(mut query: Query<(&Transform, &mut Interpolated)>) {
+    for (transform, mut interpolated) in query.iter_mut() {
+        interpolated.current = transform.translation;
+        interpolated.rendered = transform.translation;
+    }
+}
+
+/// Keeps the shadow on the ground under player 1, shrinking and fading it
+/// the higher they get. It hides over gaps and while there's no player.
+fn player_shadow_system(
+    ground_data: Res<GroundData>,
+    player_query: Query<(&Transform, &Collider), (With<Player>, Without<Player2>)>,
+    mut shadow_query: Query<
+        (&mut Transform, &mut Sprite, &mut Visibility),
+        (With<PlayerShadow>, Without<Player>),
+    >,
+) {
+    let Ok((mut transform, mut sprite, mut visibility)) = shadow_query.get_single_mut() else {
+        return;
+    };
+    let Ok((player, collider)) = player_query.get_single() else {
+        *visibility = Visibility::Hidden;
+        return;
+    };
+    let x = player.translation.x;
+    if !ground_data.supports(x, 0.0) {
+        *visibility = Visibility::Hidden;
+        return;
+    }
+    *visibility = Visibility::Inherited;
+    let height = player.translation.y - collider.half_extents.y - ground_data.top_y;
+    let lift = (height / MAX_JUMP_HEIGHT).clamp(0.0, 1.0);
+    transform.translation = Vec3::new(x, ground_data.top_y + 1.0, -0.1);
+    let scale = 1.0 - lift * (1.0 - SHADOW_MIN_SCALE);
+    sprite.custom_size = Some(SHADOW_SIZE * scale);
+    sprite.color.set_a(SHADOW_ALPHA * scale);
+}
+
+/// Draws interpolated entities part way between the last two physics steps,
+/// by how far the clock has run into the next one.
+fn interpolate_transform_system(
+    fixed_time: Res<Time<Fixed>>,
+    mut query: Query<(&mut Transform, &mut Interpolated)>,
+) {
+    let fraction = fixed_time.overstep_percentage();
+    for (mut transform, mut interpolated) in query.iter_mut() {
+        if transform.translation != interpolated.rendered {
+            interpolated.previous = transform.translation;
+            interpolated.current = transform.translation;
+        }
+        transform.translation = interpolated.previous.lerp(interpolated.current, fraction);
+        interpolated.rendered = transform.translation;
+    }
+}
+
+/// Remembers where everything with a `PrevTranslation` starts the frame.
+fn record_prev_translation_system(mut query: Query<(&Transform, &mut PrevTranslation)>) {
+    for (transform, mut prev) in query.iter_mut() {
+        prev.0 = transform.translation;
+    }
+}
+
+/// Lands anything falling onto a one-way platform. Only a bottom edge that
+/// started the frame at or above the platform's top is caught, so jumping
+/// up through a platform or walking off its end works as expected.
+fn one_way_platform_system(
+    mut query: Query<(&mut Transform, &mut Velocity, &PrevTranslation, &Collider)>,
+    platform_query: Query<(&Transform, &Sprite), (With<OneWayPlatform>, Without<PrevTranslation>)>,
+) {
+    for (mut transform, mut velocity, prev, collider) in query.iter_mut() {
+        if velocity.y > 0.0 {
+            continue;
+        }
+        let half = collider.half_extents;
+        for (platform_transform, platform_sprite) in platform_query.iter() {
+            let platform_pos = platform_transform.translation;
+            let platform_half = platform_sprite.custom_size.unwrap_or(PLATFORM_SIZE) / 2.0;
+            let platform_top = platform_pos.y + platform_half.y;
+            let over_platform =
+                (transform.translation.x - platform_pos.x).abs() < platform_half.x + half.x;
+            let was_above = prev.0.y - half.y >= platform_top;
+            if over_platform && was_above && transform.translation.y - half.y <= platform_top {
+                transform.translation.y = platform_top + half.y;
+                velocity.y = 0.0;
+            }
+        }
+    }
+}
+
+/// Marks the player and chasers grounded when there is something to stand
+/// on just below either foot or the middle of their feet, and settles them
+/// onto the highest such surface unless they are moving up.
+fn ground_probe_system(
+    raycaster: Raycaster,
+    mut query: Query<(&mut Transform, &mut Velocity, &Collider, &mut Grounded)>,
+) {
+    for (mut transform, mut velocity, collider, mut grounded) in query.iter_mut() {
+        let half = collider.half_extents;
+        let feet = transform.translation.truncate() - Vec2::new(0.0, half.y - GROUND_PROBE_LIFT);
+        let surface = [-half.x, 0.0, half.x]
+            .into_iter()
+            .filter_map(|offset| {
+                raycaster.cast(
+                    feet + Vec2::new(offset, 0.0),
+                    Vec2::NEG_Y,
+                    GROUND_PROBE_DISTANCE,
+                )
+            })
+            .map(|hit| hit.point.y)
+            .reduce(f32::max);
+        grounded.0 = surface.is_some();
+        if let Some(surface) = surface {
+            if velocity.y <= 0.0 {
+                transform.translation.y = surface + half.y;
+                velocity.y = 0.0;
+            }
+        }
+    }
+}
+
+/// Caps the virtual clock's frame delta at `MaxDelta`. Every `Time` reading,
+/// in `Update` and in the fixed physics steps it feeds, is bounded by it.
+fn apply_max_delta_system(max_delta: Res<MaxDelta>, mut time: ResMut<Time<Virtual>>) {
+    time.set_max_delta(Duration::from_secs_f32(max_delta.0));
+}
+
+/// Stops the virtual clock, and with it physics and everything else timed
+/// in game, while a hit-stop runs, and slows it during slow motion, then
+/// sets it going at full speed again. With hit-stop turned off in the
+/// settings the clock never stops.
+fn hit_stop_system(
+    real_time: Res<Time<Real>>,
+    settings: Res<Settings>,
+    mut hit_stop: ResMut<HitStop>,
+    mut slow_motion: ResMut<SlowMotion>,
+    mut virtual_time: ResMut<Time<Virtual>>,
+) {
+    let frozen = settings.hit_stop && !hit_stop.0.tick(real_time.delta()).finished();
+    let slowed = !slow_motion.timer.tick(real_time.delta()).finished();
+    let speed = if frozen {
+        0.0
+    } else if slowed {
+        slow_motion.scale
+    } else {
+        1.0
+    };
+    if virtual_time.relative_speed() != speed {
+        virtual_time.set_relative_speed(speed);
+    }
+}
+
+/// A nav cell: column from x = 0, row from the ground up.
+type NavCell = (i32, i32);
+
+/// Coarse grid over the level, from the ground up, that chasers plan their
+/// routes on. A cell can be stood in when it is clear of obstacles and has
+/// the ground or an obstacle right below it.
+struct NavGrid {
+    min_column: i32,
+    columns: i32,
+    ground_y: f32,
+    blocked: Vec<bool>,
+    standable: Vec<bool>,
+}
+
+impl NavGrid {
+    fn build(
+        bounds: &LevelBounds,
+        ground_data: &GroundData,
+        grid: &SpatialGrid,
+        obstacles: &Query<(&Transform, &Collider), With<Obstacle>>,
+    ) -> Self {
+        let min_column = (bounds.min_x / NAV_CELL_SIZE).floor() as i32;
+        let columns = (bounds.max_x / NAV_CELL_SIZE).ceil() as i32 - min_column;
+        let mut nav = NavGrid {
+            min_column,
+            columns,
+            ground_y: ground_data.top_y,
+            blocked: Vec::with_capacity((columns * NAV_ROWS) as usize),
+            standable: Vec::with_capacity((columns * NAV_ROWS) as usize),
+        };
+        // Shrunk a little so an obstacle only touching a cell's edge leaves
+        // it clear.
+        let half = Vec2::splat(NAV_CELL_SIZE / 2.0 - 1.0);
+        for column in min_column..min_column + columns {
+            for row in 0..NAV_ROWS {
+                let center = nav.center((column, row));
+                let blocked = grid
+                    .query_region(center, half)
+                    .filter_map(|entity| obstacles.get(entity).ok())
+                    .any(|(transform, collider)| {
+                        is_colliding(
+                            center.extend(0.0),
+                            half,
+                            transform.translation,
+                            collider.half_extents,
+                        )
+                    });
+                let supported = if row == 0 {
+                    ground_data.supports(center.x, 0.0)
+                } else {
+                    nav.blocked.last() == Some(&true)
+                };
+                nav.blocked.push(blocked);
+                nav.standable.push(!blocked && supported);
+            }
+        }
+        nav
+    }
+
+    fn index(&self, (column, row): NavCell) -> Option<usize> {
+        let column = column - self.min_column;
+        ((0..self.columns).contains(&column) && (0..NAV_ROWS).contains(&row))
+            .then(|| (column * NAV_ROWS + row) as usize)
+    }
+
+    /// Cells off the grid count as blocked.
+    fn is_blocked(&self, cell: NavCell) -> bool {
+        self.index(cell).is_none_or(|index| self.blocked[index])
+    }
+
+    fn is_standable(&self, cell: NavCell) -> bool {
+        self.index(cell).is_some_and(|index| self.standable[index])
+    }
+
+    fn center(&self, (column, row): NavCell) -> Vec2 {
+        Vec2::new(
+            (column as f32 + 0.5) * NAV_CELL_SIZE,
+            self.ground_y + (row as f32 + 0.5) * NAV_CELL_SIZE,
+        )
+    }
+
+    /// Where the feet of something standing in `cell` are.
+    fn feet(&self, cell: NavCell) -> Vec2 {
+        self.center(cell) - Vec2::new(0.0, NAV_CELL_SIZE / 2.0)
+    }
+
+    /// The cell something with its feet at `feet` stands in, or would land
+    /// in if it is in the air.
+    fn standing_cell(&self, feet: Vec2) -> Option<NavCell> {
+        let column = (feet.x / NAV_CELL_SIZE).floor() as i32;
+        let row = ((feet.y - self.ground_y) / NAV_CELL_SIZE).round() as i32;
+        (0..=row.min(NAV_ROWS - 1))
+            .rev()
+            .map(|row| (column, row))
+            .find(|&cell| self.is_standable(cell))
+    }
+
+    /// Cells reachable from `cell` in one move, with what each move costs:
+    /// walking sideways, hopping up one row, or walking off an edge and
+    /// dropping to whatever is below.
+    fn neighbours(&self, (column, row): NavCell) -> Vec<(NavCell, u32)> {
+        let mut moves = Vec::new();
+        for next_column in [column - 1, column + 1] {
+            let beside = (next_column, row);
+            if self.is_standable(beside) {
+                moves.push((beside, NAV_WALK_COST));
+            } else if !self.is_blocked(beside) {
+                let landing = (0..row)
+                    .rev()
+                    .map(|row| (next_column, row))
+                    .take_while(|&cell| !self.is_blocked(cell))
+                    .find(|&cell| self.is_standable(cell));
+                if let Some(landing) = landing {
+                    moves.push((landing, NAV_WALK_COST + (row - landing.1) as u32));
+                }
+            }
+            let above = (next_column, row + 1);
+            if self.is_standable(above) && !self.is_blocked((column, row + 1)) {
+                moves.push((above, NAV_HOP_COST));
+            }
+        }
+        moves
+    }
+
+    /// A* from `start` to `goal`, as the feet positions to visit after
+    /// `start`. When `goal` can't be reached the route ends at the reachable
+    /// cell closest to it.
+    fn find_path(&self, start: NavCell, goal: NavCell) -> VecDeque<Vec2> {
+        // Every move crosses one column for at least the walking cost.
+        let heuristic = |cell: NavCell| (cell.0 - goal.0).unsigned_abs() * NAV_WALK_COST;
+        let mut open = BinaryHeap::from([Reverse((heuristic(start), start))]);
+        let mut came_from = HashMap::new();
+        let mut cost_so_far = HashMap::from([(start, 0)]);
+        let mut closest = start;
+        while let Some(Reverse((_, cell))) = open.pop() {
+            if (heuristic(cell), cost_so_far[&cell]) < (heuristic(closest), cost_so_far[&closest]) {
+                closest = cell;
+            }
+            if cell == goal {
+                break;
+            }
+            for (next, step_cost) in self.neighbours(cell) {
+                let cost = cost_so_far[&cell] + step_cost;
+                if cost_so_far.get(&next).is_none_or(|&known| cost < known) {
+                    cost_so_far.insert(next, cost);
+                    came_from.insert(next, cell);
+                    open.push(Reverse((cost + heuristic(next), next)));
+                }
+            }
+        }
+
+        let mut path = VecDeque::new();
+        let mut cell = closest;
+        while let Some(&previous) = came_from.get(&cell) {
+            path.push_front(self.feet(cell));
+            cell = previous;
+        }
+        path
+    }
+}
+
+/// Re-plans each chaser's route to the nearest player every
+/// `PATHFIND_INTERVAL`.
+fn pathfind_system(
+    time: Res<Time>,
+    bounds: Res<LevelBounds>,
+    ground_data: Res<GroundData>,
+    grid: Res<SpatialGrid>,
+    obstacles: Query<(&Transform, &Collider), With<Obstacle>>,
+    player_query: Query<(&Transform, &Collider), With<Player>>,
+    mut query: Query<(&Transform, &Collider, &mut Pathfinder)>,
+) {
+    let feet = |transform: &Transform, collider: &Collider| {
+        transform.translation.truncate() - Vec2::new(0.0, collider.half_extents.y)
+    };
+    let players: Vec<Vec2> = player_query
+        .iter()
+        .map(|(transform, collider)| feet(transform, collider))
+        .collect();
+    let mut nav = None;
+    for (transform, collider, mut pathfinder) in query.iter_mut() {
+        if !pathfinder.timer.tick(time.delta()).just_finished() {
+            continue;
+        }
+        let Some(target) = nearest(&players, feet(transform, collider)) else {
+            return;
+        };
+        let nav =
+            nav.get_or_insert_with(|| NavGrid::build(&bounds, &ground_data, &grid, &obstacles));
+        let start = nav.standing_cell(feet(transform, collider));
+        let goal = nav.standing_cell(target);
+        pathfinder.path = match (start, goal) {
+            (Some(start), Some(goal)) => nav.find_path(start, goal),
+            _ => VecDeque::new(),
+        };
+    }
+}
+
+/// Walks chasers toward their next waypoint, hopping when it is higher
+/// than they stand. A chaser with nowhere to go waits.
+fn pathfinder_steering_system(
+    mut query: Query<(
+        &Transform,
+        &Collider,
+        &mut Velocity,
+        &Grounded,
+        &mut Pathfinder,
+        Has<InWater>,
+    )>,
+) {
+    for (transform, collider, mut velocity, grounded, mut pathfinder, in_water) in query.iter_mut()
+    {
+        let feet = transform.translation.truncate() - Vec2::new(0.0, collider.half_extents.y);
+        while pathfinder
+            .path
+            .front()
+            .is_some_and(|waypoint| (waypoint.x - feet.x).abs() < PATH_WAYPOINT_REACH)
+        {
+            pathfinder.path.pop_front();
+        }
+        let Some(&waypoint) = pathfinder.path.front() else {
+            velocity.x = 0.0;
+            continue;
+        };
+        let speed = if in_water {
+            pathfinder.speed * WATER_ENEMY_SPEED_SCALE
+        } else {
+            pathfinder.speed
+        };
+        velocity.x = (waypoint.x - feet.x).signum() * speed;
+        if grounded.0 && waypoint.y > feet.y + NAV_CELL_SIZE / 2.0 {
+            velocity.y = CHASER_HOP_VELOCITY;
+        }
+    }
+}
+
+/// Files every enemy and obstacle into the `SpatialGrid` at its current
+/// position.
+fn rebuild_spatial_grid_system(
+    mut grid: ResMut<SpatialGrid>,
+    query: Query<(Entity, &Transform, &Collider), Or<(With<Enemy>, With<Obstacle>)>>,
+) {
+    grid.clear();
+    for (entity, transform, collider) in query.iter() {
+        grid.insert(
+            entity,
+            transform.translation.truncate(),
+            collider.half_extents,
+        );
+    }
+}
+
+/// Finds what the player is touching and sends a `CollisionEvent` for each
+/// enemy and obstacle it overlaps. The responses live in the systems chained
+/// after this one, so every event is handled in the frame it was sent.
+fn collision_detection_system(
+    grid: Res<SpatialGrid>,
+    mut collision_events: EventWriter<CollisionEvent>,
+    player_query: Query<(Entity, &Transform, &Collider), (With<Player>, Without<DyingAnimation>)>,
+    enemy_query: Query<(&Transform, &Collider), (With<Enemy>, Without<Player>)>,
+    obstacle_query: Query<(&Transform, &Collider), (With<Obstacle>, Without<Player>)>,
+) {
+    for (player_entity, player_transform, player_collider) in player_query.iter() {
+        let player_half = player_collider.half_extents;
+        for entity in grid.query_region(player_transform.translation.truncate(), player_half) {
+            if let Ok((enemy_transform, enemy_collider)) = enemy_query.get(entity) {
+                let enemy_half = enemy_collider.half_extents;
+                if is_colliding(
+                    player_transform.translation,
+                    player_half,
+                    enemy_transform.translation,
+                    enemy_half,
+                ) {
+                    // Stomp enemy if player is above.
+                    let kind = if player_transform.translation.y - player_half.y
+                        >= enemy_transform.translation.y + enemy_half.y - 5.0
+                    {
+                        CollisionKind::Stomp
+                    } else {
+                        CollisionKind::SideHit
+                    };
+                    collision_events.send(CollisionEvent {
+                        a: player_entity,
+                        b: entity,
+                        kind,
+                    });
+                }
+            } else if let Ok((obstacle_transform, obstacle_collider)) = obstacle_query.get(entity) {
+                if is_colliding(
+                    player_transform.translation,
+                    player_half,
+                    obstacle_transform.translation,
+                    obstacle_collider.half_extents,
+                ) {
+                    collision_events.send(CollisionEvent {
+                        a: player_entity,
+                        b: entity,
+                        kind: CollisionKind::Obstacle,
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// Spawns the background and fill of an enemy's health bar above it.
+fn spawn_enemy_health_bar(parent: &mut ChildBuilder) {
+    let bar_sprite = |color, z| SpriteBundle {
+        sprite: Sprite {
+            color,
+            custom_size: Some(ENEMY_HEALTH_BAR_SIZE),
+            ..default()
+        },
+        transform: Transform::from_xyz(0.0, ENEMY_HEALTH_BAR_OFFSET, z),
+        ..default()
+    };
+    parent.spawn((bar_sprite(ENEMY_HEALTH_BAR_BACKGROUND, 1.0), EnemyHealthBar));
+    parent.spawn((
+        bar_sprite(ENEMY_HEALTH_BAR_COLOR, 1.1),
+        EnemyHealthBar,
+        EnemyHealthBarFill,
+    ));
+}
+
+/// Shrinks an enemy's health bar fill from the right as it takes stomps,
+/// and hides the bar once one stomp is all it takes.
+fn update_enemy_health_bar_system(
+    enemy_query: Query<(&EnemyHealth, &Children), Changed<EnemyHealth>>,
+    mut bar_query: Query<
+        (
+            &mut Visibility,
+            &mut Sprite,
+            &mut Transform,
+            Has<EnemyHealthBarFill>,
+        ),
+        With<EnemyHealthBar>,
+    >,
+) {
+    for (health, children) in enemy_query.iter() {
+        let fraction = health.current as f32 / health.max as f32;
+        for &child in children.iter() {
+            let Ok((mut visibility, mut sprite, mut transform, fill)) = bar_query.get_mut(child)
+            else {
+                continue;
+            };
+            *visibility = if health.current > 1 {
+                Visibility::Inherited
+            } else {
+                Visibility::Hidden
+            };
+            if fill {
+                let width = ENEMY_HEALTH_BAR_SIZE.x * fraction;
+                sprite.custom_size = Some(Vec2::new(width, ENEMY_HEALTH_BAR_SIZE.y));
+                transform.translation.x = (width - ENEMY_HEALTH_BAR_SIZE.x) / 2.0;
+            }
+        }
+    }
+}
+
+/// Defeats stomped enemies: awards combo, chain and streak points and
+/// bounces the player back up. Enemies with `EnemyHealth` left just lose a
+/// point of it, flash and bounce the player off.
+fn stomp_system(
+    mut commands: Commands,
+    mut popups: EventWriter<ScorePopup>,
+    audio_handles: Res<AudioHandles>,
+    sfx_volume: Res<SfxVolume>,
+    mut collision_events: EventReader<CollisionEvent>,
+    mut score: ResMut<Score>,
+    mut activity: ResMut<ActivityMultiplier>,
+    difficulty: Res<DifficultyMultipliers>,
+    mut combo: ResMut<ComboState>,
+    mut kills: KillTally,
+    mut shake: ResMut<ScreenShake>,
+    mut hit_stop: ResMut<HitStop>,
+    mut player_query: Query<(&Transform, &mut Velocity, &mut ChainBounce), With<Player>>,
+    mut enemy_query: Query<
+        (
+            &Transform,
+            &Sprite,
+            Option<&mut EnemyHealth>,
+            Option<&HitFlash>,
+        ),
+        (With<Enemy>, Without<Player>),
+    >,
+) {
+    for event in collision_events.read() {
+        if event.kind != CollisionKind::Stomp {
+            continue;
+        }
+        let Ok((player_transform, mut velocity, mut chain)) = player_query.get_mut(event.a) else {
+            continue;
+        };
+        let Ok((enemy_transform, sprite, health, flash)) = enemy_query.get_mut(event.b) else {
+            continue;
+        };
+        if let Some(mut health) = health {
+            health.current = health.current.saturating_sub(1);
+            if health.current > 0 {
+                // A stomp mid-flash keeps the color from before the first one.
+                let original_color = flash.map_or(sprite.color, |flash| flash.original_color);
+                commands
+                    .entity(event.b)
+                    .insert(HitFlash::new(original_color));
+                play_sound(&mut commands, &audio_handles.stomp, &sfx_volume, 1.0);
+                shake.add_trauma(SHAKE_STOMP_TRAUMA);
+                velocity.y = PLAYER_JUMP_VELOCITY;
+                continue;
+            }
+        }
+        commands.entity(event.b).despawn_recursive();
+        // Each stomp inside the combo window is worth one more multiple.
+        combo.timer.reset();
+        combo.count += 1;
+        activity.value = (activity.value + ACTIVITY_KILL_BOOST).min(ACTIVITY_MAX);
+        let stomp_points = difficulty.points(STOMP_SCORE * combo.count as i32 * activity.factor());
+        score.0 += stomp_points;
+        // Vary the pitch so repeated stomps don't sound identical, and
+        // raise it further on long combos.
+        let mut speed = rand::thread_rng().gen_range(STOMP_PITCH_RANGE.0..STOMP_PITCH_RANGE.1);
+        if combo.count > STOMP_PITCH_COMBO {
+            speed += STOMP_PITCH_STEP * combo.count as f32;
+        }
+        play_sound(&mut commands, &audio_handles.stomp, &sfx_volume, speed);
+        shake.add_trauma(SHAKE_STOMP_TRAUMA);
+        hit_stop.add(HIT_STOP_DURATION);
+        let popup_pos = enemy_transform.translation + Vec3::new(0.0, ENEMY_SIZE.y, 1.0);
+        popups.send(ScorePopup {
+            text: format!("+{}", stomp_points),
+            position: popup_pos,
+            color: Color::WHITE,
+        });
+        if combo.count > 1 {
+            popups.send(ScorePopup {
+                text: format!("x{}!", combo.count),
+                position: popup_pos,
+                color: Color::ORANGE,
+            });
+        }
+        // Bounce off, higher with every stomp before landing.
+        chain.count = (chain.count + 1).min(MAX_CHAIN_BOUNCE);
+        velocity.y = PLAYER_JUMP_VELOCITY * (1.0 + CHAIN_BOUNCE_STEP * chain.count as f32);
+        if chain.count > 1 {
+            let chain_bonus = CHAIN_BONUS_SCORE * (chain.count as i32).pow(2);
+            let chain_points = difficulty.points(chain_bonus * activity.factor());
+            score.0 += chain_points;
+            popups.send(ScorePopup {
+                text: format!("CHAIN! +{}", chain_points),
+                position: player_transform.translation + Vec3::new(0.0, PLAYER_SIZE.y, 1.0),
+                color: Color::CYAN,
+            });
+        }
+        kills.record();
+        info!("Enemy defeated! Score: {}", score.0);
+    }
+}
+
+/// Starts slow motion when a stomp takes out the last enemy. The stomped
+/// enemies' despawns may not have gone through yet, so it checks that every
+/// enemy left was stomped this step and has no health left.
+fn last_enemy_slow_motion_system(
+    mut collision_events: EventReader<CollisionEvent>,
+    enemy_query: Query<(Entity, Option<&EnemyHealth>), With<Enemy>>,
+    mut slow_motion: ResMut<SlowMotion>,
+) {
+    let stomped: Vec<Entity> = collision_events
+        .read()
+        .filter(|event| event.kind == CollisionKind::Stomp)
+        .map(|event| event.b)
+        .collect();
+    let defeated = |(enemy, health): (Entity, Option<&EnemyHealth>)| {
+        stomped.contains(&enemy) && health.map_or(0, |health| health.current) == 0
+    };
+    if !stomped.is_empty() && enemy_query.iter().all(defeated) {
+        slow_motion.start();
+    }
+}
+
+/// Side hits from enemies cost the player health, at most once per frame
+/// and never during the grace period after the last hit.
+fn player_damage_system(
+    mut commands: Commands,
+    mut collision_events: EventReader<CollisionEvent>,
+    mut hurt_events: EventWriter<PlayerHurt>,
+    mut death_events: EventWriter<PlayerDied>,
+    mut player_query: Query<(&mut Health, &Sprite, Has<Invulnerable>), With<Player>>,
+) {
+    let mut hurt = Vec::new();
+    for event in collision_events.read() {
+        if event.kind == CollisionKind::SideHit && !hurt.contains(&event.a) {
+            hurt.push(event.a);
+        }
+    }
+    for player_entity in hurt {
+        let Ok((mut health, sprite, invulnerable)) = player_query.get_mut(player_entity) else {
+            continue;
+        };
+        if !invulnerable {
+            hurt_player(
+                &mut commands,
+                player_entity,
+                &mut health,
+                sprite,
+                ENEMY_CONTACT_DAMAGE,
+                &mut hurt_events,
+                &mut death_events,
+            );
+        }
+    }
+}
+
+/// Takes `damage` off the player's health and starts a short grace period
+/// with a red flash, or costs a life once health runs out.
+fn hurt_player(
+    commands: &mut Commands,
+    player: Entity,
+    health: &mut Health,
+    sprite: &Sprite,
+    damage: f32,
+    hurt_events: &mut EventWriter<PlayerHurt>,
+    death_events: &mut EventWriter<PlayerDied>,
+) {
+    hurt_events.send(PlayerHurt);
+    health.current -= damage;
+    if health.current <= 0.0 {
+        death_events.send(PlayerDied(player));
+    } else {
+        commands.entity(player).insert((
+            Invulnerable(Timer::from_seconds(HIT_INVULNERABILITY, TimerMode::Once)),
+            HitFlash::new(sprite.color),
+        ));
+    }
+}
+
+/// Pushes enemies sideways out of any obstacle they walk into and turns
+/// them around. Only an enemy still heading into the obstacle is turned,
+/// so one that starts inside walks out rather than flipping every step.
+/// Chasers climb onto obstacles, so they are pushed out like the player.
+fn enemy_obstacle_collision_system(
+    grid: Res<SpatialGrid>,
+    mut enemy_query: Query<
+        (&mut Transform, &mut Velocity, &Collider, Has<Pathfinder>),
+        (With<Enemy>, Without<Obstacle>),
+    >,
+    obstacle_query: Query<(&Transform, &Collider), With<Obstacle>>,
+) {
+    for (mut enemy_transform, mut enemy_velocity, enemy_collider, chaser) in enemy_query.iter_mut()
+    {
+        let enemy_half = enemy_collider.half_extents;
+        let nearby = grid.query_region(enemy_transform.translation.truncate(), enemy_half);
+        for (obstacle_transform, obstacle_collider) in
+            nearby.filter_map(|entity| obstacle_query.get(entity).ok())
+        {
+            let obstacle_half = obstacle_collider.half_extents;
+            let obstacle_pos = obstacle_transform.translation;
+            if !is_colliding(
+                enemy_transform.translation,
+                enemy_half,
+                obstacle_pos,
+                obstacle_half,
+            ) {
+                continue;
+            }
+            if chaser {
+                push_out_of_box(
+                    &mut enemy_transform,
+                    &mut enemy_velocity,
+                    enemy_half,
+                    obstacle_pos,
+                    obstacle_half,
+                );
+                continue;
+            }
+            // Out through the nearer side face.
+            let side = (enemy_transform.translation.x - obstacle_pos.x).signum();
+            enemy_transform.translation.x =
+                obstacle_pos.x + side * (obstacle_half.x + enemy_half.x);
+            if enemy_velocity.x * side < 0.0 {
+                enemy_velocity.x = -enemy_velocity.x;
+            }
+        }
+    }
+}
+
+/// Which face of a solid box the player was pushed out through.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum ContactFace {
+    Side,
+    Top,
+    Bottom,
+}
+
+/// Pushes the player out of a solid box along the axis of least overlap and
+/// stops its motion into the box. Returns `None` if they don't overlap.
+fn push_out_of_box(
+    transform: &mut Transform,
+    velocity: &mut Velocity,
+    half: Vec2,
+    box_pos: Vec3,
+    box_half: Vec2,
+) -> Option<ContactFace> {
+    let delta = (transform.translation - box_pos).truncate();
+    let overlap = half + box_half - delta.abs();
+    if overlap.x <= 0.0 || overlap.y <= 0.0 {
+        return None;
+    }
+
+    if overlap.x < overlap.y {
+        // Side contact: prevent horizontal overlap.
+        transform.translation.x += overlap.x * delta.x.signum();
+        velocity.x = 0.0;
+        Some(ContactFace::Side)
+    } else if delta.y > 0.0 {
+        // Landed on top.
+        transform.translation.y += overlap.y;
+        if velocity.y < 0.0 {
+            velocity.y = 0.0;
+        }
+        Some(ContactFace::Top)
+    } else {
+        // Hit the underside.
+        transform.translation.y -= overlap.y;
+        if velocity.y > 0.0 {
+            velocity.y = 0.0;
+        }
+        Some(ContactFace::Bottom)
+    }
+}
+
+/// Keeps the player out of the obstacles it ran into. Hitting the underside
+/// of a breakable block while moving up destroys it, and bouncy obstacles
+/// throw the player back off.
+fn obstacle_block_system(
+    mut commands: Commands,
+    mut collision_events: EventReader<CollisionEvent>,
+    mut score: ResMut<Score>,
+    activity: Res<ActivityMultiplier>,
+    difficulty: Res<DifficultyMultipliers>,
+    mut run_stats: ResMut<RunStats>,
+    coin_assets: Res<CoinAssets>,
+    mut player_query: Query<(&mut Transform, &mut Velocity, &Collider, Has<Dashing>), With<Player>>,
+    obstacle_query: Query<
+        (
+            &Transform,
+            &Sprite,
+            &Collider,
+            Has<Breakable>,
+            Has<Fragile>,
+            Option<&Restitution>,
+        ),
+        (With<Obstacle>, Without<Player>),
+    >,
+) {
+    let mut rng = rand::thread_rng();
+    for event in collision_events.read() {
+        if event.kind != CollisionKind::Obstacle {
+            continue;
+        }
+        let Ok((mut player_transform, mut player_velocity, player_collider, dashing)) =
+            player_query.get_mut(event.a)
+        else {
+            continue;
+        };
+        let Ok((
+            obstacle_transform,
+            obstacle_sprite,
+            obstacle_collider,
+            breakable,
+            fragile,
+            restitution,
+        )) = obstacle_query.get(event.b)
+        else {
+            continue;
+        };
+        let obstacle_pos = obstacle_transform.translation;
+        // Dashing smashes straight through fragile obstacles.
+        if fragile && dashing {
+            commands.entity(event.b).despawn_recursive();
+            score.0 += difficulty.points(FRAGILE_SCORE * activity.factor());
+            spawn_debris(&mut commands, &mut rng, obstacle_pos, obstacle_sprite.color);
+            continue;
+        }
+        let rising = player_velocity.y > 0.0;
+        let incoming = player_velocity.0;
+        // Pushing out of an earlier obstacle this frame may already have
+        // cleared this one, in which case nothing happens.
+        let face = push_out_of_box(
+            &mut player_transform,
+            &mut player_velocity,
+            player_collider.half_extents,
+            obstacle_pos,
+            obstacle_collider.half_extents,
+        );
+        // Bounce back at a share of the incoming speed. Each bounce is
+        // slower than the last, and slow ones are dropped so they settle.
+        if let Some(&Restitution(restitution)) = restitution {
+            match face {
+                Some(ContactFace::Top) if -incoming.y * restitution > BOUNCE_MIN_SPEED => {
+                    player_velocity.y = -incoming.y * restitution;
+                }
+                Some(ContactFace::Side) if incoming.x.abs() * restitution > BOUNCE_MIN_SPEED => {
+                    player_velocity.x = -incoming.x * restitution;
+                }
+                _ => {}
+            }
+        }
+        if breakable && rising && face == Some(ContactFace::Bottom) {
+            commands.entity(event.b).despawn();
+            score.0 += difficulty.points(BREAKABLE_SCORE * activity.factor());
+            run_stats.blocks_broken += 1;
+            if rng.gen_bool(BREAKABLE_COIN_CHANCE) {
+                spawn_coin(&mut commands, &coin_assets, obstacle_pos);
+            }
+        }
+    }
+}
+
+/// Carries the player and enemies standing on a conveyor along with it.
+/// Stepping off stops the push at once.
+fn conveyor_system(
+    time: Res<Time>,
+    conveyor_query: Query<(&Transform, &Collider, &Conveyor)>,
+    mut rider_query: Query<
+        (&mut Transform, &Collider),
+        (
+            Or<(With<Player>, With<Enemy>)>,
+            Without<Conveyor>,
+            Without<DyingAnimation>,
+        ),
+    >,
+) {
+    for (mut rider_transform, rider_collider) in rider_query.iter_mut() {
+        let rider_half = rider_collider.half_extents;
+        let rider_pos = rider_transform.translation;
+        let push = conveyor_query
+            .iter()
+            .find(|(transform, collider, _)| {
+                let half = collider.half_extents;
+                let top = transform.translation.y + half.y;
+                (rider_pos.y - rider_half.y - top).abs() <= CONVEYOR_CONTACT_TOLERANCE
+                    && (rider_pos.x - transform.translation.x).abs() < half.x + rider_half.x
+            })
+            .map(|(_, _, conveyor)| conveyor.push);
+        if let Some(push) = push {
+            rider_transform.translation.x += push * time.delta_seconds();
+        }
+    }
+}
+
+/// Scrolls conveyor stripes in the direction the belt runs, wrapping them
+/// around at its ends.
+fn conveyor_stripe_system(
+    time: Res<Time>,
+    conveyor_query: Query<&Conveyor>,
+    mut stripe_query: Query<(&Parent, &mut Transform, &ConveyorStripe)>,
+) {
+    for (parent, mut transform, stripe) in stripe_query.iter_mut() {
+        let Ok(conveyor) = conveyor_query.get(parent.get()) else {
+            continue;
+        };
+        let width = stripe.half_width * 2.0;
+        let x = transform.translation.x + conveyor.push * time.delta_seconds();
+        transform.translation.x = (x + stripe.half_width).rem_euclid(width) - stripe.half_width;
+    }
+}
+
+/// Sends in the next wave of endless mode when its timer runs out, or
+/// straight away once every enemy is gone. Waves cycle through the spawn
+/// patterns and drop in away from the player, each one bigger and faster
+/// than the last.
+fn wave_spawner_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    asset_server: Res<AssetServer>,
+    audio_handles: Res<AudioHandles>,
+    mut spawner: ResMut<WaveSpawner>,
+    mut announcements: EventWriter<WaveAnnouncement>,
+    ground_data: Res<GroundData>,
+    bounds: Res<LevelBounds>,
+    level_config: Option<Res<LevelConfig>>,
+    difficulty: Res<DifficultyMultipliers>,
+    enemy_query: Query<(), With<Enemy>>,
+    player_query: Query<&Transform, (With<Player>, Without<Player2>)>,
+    obstacle_query: Query<(&Transform, &Collider), With<Obstacle>>,
+    mut game_rng: ResMut<GameRng>,
+) {
+    let Ok(player) = player_query.get_single() else {
+        return;
+    };
+    let due = spawner.timer.tick(time.delta()).just_finished();
+    if !due {
+        if !enemy_query.is_empty() {
+            return;
+        }
+        spawner.timer.reset();
+    }
+    spawner.wave += 1;
+    let wave = spawner.wave;
+    announcements.send(WaveAnnouncement { wave });
+
+    let base_size = WAVE_BASE_SIZE + wave as usize - 1;
+    let size =
+        ((base_size as f32 * difficulty.enemy_count).round() as usize).clamp(1, WAVE_MAX_SIZE);
+    let (min_speed, max_speed) = level_config
+        .and_then(|config| config.enemy_speed_range)
+        .unwrap_or(ENEMY_SPEED_RANGE);
+    let ramp = 1.0 + WAVE_SPEED_RAMP * (wave - 1) as f32;
+    let obstacles: Vec<(Vec3, Vec2)> = obstacle_query
+        .iter()
+        .map(|(transform, collider)| (transform.translation, collider.half_extents))
+        .collect();
+    let enemy_reach = ENEMY_SIZE.x / 2.0 + ENEMY_OBSTACLE_CLEARANCE;
+    let player_x = player.translation.x;
+    // Somewhere on solid ground, clear of obstacles and the player.
+    let clear_spot = |x: f32| {
+        (x - player_x).abs() >= ENEMY_SPAWN_CLEARANCE
+            && x - ENEMY_SIZE.x / 2.0 >= bounds.min_x
+            && x + ENEMY_SIZE.x / 2.0 <= bounds.max_x
+            && ground_data.supports(x, ENEMY_SIZE.x / 2.0)
+            && obstacles
+                .iter()
+                .all(|&(pos, half)| (x - pos.x).abs() >= half.x + enemy_reach)
+    };
+
+    let rng = &mut game_rng.rng;
+    let drop_y = ground_data.top_y + ENEMY_SIZE.y / 2.0 + WAVE_DROP_HEIGHT;
+    let pattern = SpawnPattern::for_level(wave);
+    let formation = (0..SPAWN_ATTEMPTS)
+        .take_while(|_| pattern != SpawnPattern::Random)
+        .map(|_| {
+            let center = Vec2::new(rng.gen_range(bounds.min_x..bounds.max_x), drop_y);
+            pattern_spawn_helper(pattern, size, center, FORMATION_SPACING)
+        })
+        .find(|positions| positions.iter().all(|pos| clear_spot(pos.x)));
+    // A wave that can't fit its pattern scatters instead.
+    let positions = formation.unwrap_or_else(|| {
+        (0..size)
+            .filter_map(|_| {
+                (0..SPAWN_ATTEMPTS)
+                    .map(|_| rng.gen_range(bounds.min_x..bounds.max_x))
+                    .find(|&x| clear_spot(x))
+            })
+            .map(|x| Vec2::new(x, drop_y))
+            .collect()
+    });
+
+    let speed = rng.gen_range(min_speed..max_speed) * difficulty.enemy_speed * ramp;
+    for pos in positions {
+        let velocity_x = (player_x - pos.x).signum() * speed;
+        spawn_random_enemy(
+            &mut commands,
+            &asset_server,
+            &audio_handles,
+            pos.extend(0.0),
+            velocity_x,
+        )
+        .insert(GravityScale(1.0));
+    }
+}
+
+/// Starts the countdown to the level's first rock. It runs after the level
+/// is built, so it doesn't change the layout a seed gives.
+fn reset_hazard_spawner_system(
+    mut commands: Commands,
+    mut game_rng: ResMut<GameRng>,
+    difficulty: Res<DifficultyMultipliers>,
+) {
+    commands.insert_resource(HazardSpawner {
+        timer: HazardSpawner::random_interval(&mut game_rng.rng, &difficulty),
+    });
+}
+
+/// Every few seconds, marks a random spot along the top of the screen
+/// where a rock is about to fall.
+fn hazard_spawner_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut spawner: ResMut<HazardSpawner>,
+    mut game_rng: ResMut<GameRng>,
+    difficulty: Res<DifficultyMultipliers>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    camera_query: Query<(&Transform, &OrthographicProjection), With<Camera2d>>,
+) {
+    if !spawner.timer.tick(time.delta()).just_finished() {
+        return;
+    }
+    spawner.timer = HazardSpawner::random_interval(&mut game_rng.rng, &difficulty);
+
+    let (camera_transform, projection) = camera_query.single();
+    let camera = camera_transform.translation;
+    let view = view_size(window_query.single(), projection);
+    let half_width = view.x / 2.0 - HAZARD_SIZE.x;
+    let x = game_rng
+        .rng
+        .gen_range(camera.x - half_width..camera.x + half_width);
+    let top = camera.y + view.y / 2.0;
+    commands.spawn((
+        SpriteBundle {
+            sprite: Sprite {
+                color: Color::rgba(1.0, 0.2, 0.2, 0.4),
+                custom_size: Some(Vec2::new(HAZARD_SIZE.x, view.y)),
+                ..default()
+            },
+            transform: Transform::from_xyz(x, top - view.y / 2.0, -0.5),
+            ..default()
+        },
+        HazardWarning(Timer::from_seconds(
+            HAZARD_WARNING_DURATION,
+            TimerMode::Once,
+        )),
+        GameEntity,
+    ));
+}
+
+/// Replaces each expired warning with a rock at the top of the screen.
+fn hazard_warning_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    camera_query: Query<
+        (&Transform, &OrthographicProjection),
+        (With<Camera2d>, Without<HazardWarning>),
+    >,
+    mut warning_query: Query<(Entity, &Transform, &mut HazardWarning)>,
+) {
+    let (camera_transform, projection) = camera_query.single();
+    let view = view_size(window_query.single(), projection);
+    let top = camera_transform.translation.y + view.y / 2.0;
+    for (entity, transform, mut warning) in warning_query.iter_mut() {
+        if !warning.0.tick(time.delta()).finished() {
+            continue;
+        }
+        commands.entity(entity).despawn();
+        commands.spawn((
+            SpriteBundle {
+                sprite: Sprite {
+                    color: Color::rgb(0.45, 0.4, 0.35),
+                    custom_size: Some(HAZARD_SIZE),
+                    ..default()
+                },
+                transform: Transform::from_xyz(
+                    transform.translation.x,
+                    top + HAZARD_SIZE.y / 2.0,
+                    0.0,
+                ),
+                ..default()
+            },
+            FallingHazard,
+            Velocity(Vec2::ZERO),
+            GravityScale(1.0),
+            Interpolated::default(),
+            GameEntity,
+        ));
+    }
+}
+
+/// Removes pending warnings when play stops so no rock is left hanging.
+fn clear_hazard_warnings(mut commands: Commands, query: Query<Entity, With<HazardWarning>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn();
+    }
+}
+
+/// Checks what falling rocks hit. A rock hurts the player it hits and crushes
+/// enemies for no points, then breaks apart on the ground or an obstacle.
+fn falling_hazard_system(
+    mut commands: Commands,
+    ground_data: Res<GroundData>,
+    mut hurt_events: EventWriter<PlayerHurt>,
+    mut death_events: EventWriter<PlayerDied>,
+    hazard_query: Query<(Entity, &Transform), With<FallingHazard>>,
+    mut player_query: Query<
+        (
+            Entity,
+            &Transform,
+            &Collider,
+            &mut Health,
+            &Sprite,
+            Has<Invulnerable>,
+        ),
+        (With<Player>, Without<FallingHazard>),
+    >,
+    enemy_query: Query<(Entity, &Transform, &Collider), (With<Enemy>, Without<FallingHazard>)>,
+    obstacle_query: Query<(&Transform, &Collider), (With<Obstacle>, Without<FallingHazard>)>,
+) {
+    let mut rng = rand::thread_rng();
+    let dust = Color::rgb(0.7, 0.65, 0.6);
+    for (hazard_entity, hazard_transform) in hazard_query.iter() {
+        let position = hazard_transform.translation;
+        let half = HAZARD_SIZE / 2.0;
+
+        for (enemy_entity, enemy_transform, enemy_collider) in enemy_query.iter() {
+            if is_colliding(
+                position,
+                half,
+                enemy_transform.translation,
+                enemy_collider.half_extents,
+            ) {
+                commands.entity(enemy_entity).despawn_recursive();
+            }
+        }
+
+        let mut shattered = false;
+        for (player_entity, player_transform, player_collider, mut health, sprite, invulnerable) in
+            player_query.iter_mut()
+        {
+            if is_colliding(
+                position,
+                half,
+                player_transform.translation,
+                player_collider.half_extents,
+            ) {
+                if !invulnerable {
+                    hurt_player(
+                        &mut commands,
+                        player_entity,
+                        &mut health,
+                        sprite,
+                        HAZARD_DAMAGE,
+                        &mut hurt_events,
+                        &mut death_events,
+                    );
+                }
+                shattered = true;
+            }
+        }
+        let bottom = position.y - half.y;
+        if bottom <= ground_data.top_y && ground_data.supports(position.x, 0.0) {
+            shattered = true;
+        }
+        if obstacle_query.iter().any(|(transform, collider)| {
+            is_colliding(position, half, transform.translation, collider.half_extents)
+        }) {
+            shattered = true;
+        }
+
+        if shattered {
+            commands.entity(hazard_entity).despawn();
+            spawn_debris(&mut commands, &mut rng, position, dust);
+        } else if bottom < ground_data.top_y - GAP_FALL_DEPTH {
+            // Fell down a gap out of sight.
+            commands.entity(hazard_entity).despawn();
+        }
+    }
+}
+
+/// Throws a few chunks of a smashed obstacle out from `position`.
+fn spawn_debris(commands: &mut Commands, rng: &mut impl Rng, position: Vec3, color: Color) {
+    for _ in 0..rng.gen_range(2..=4) {
+        let velocity = Vec2::new(rng.gen_range(-150.0..150.0), rng.gen_range(100.0..250.0));
+        commands.spawn((
+            SpriteBundle {
+                sprite: Sprite {
+                    color,
+                    custom_size: Some(Vec2::splat(DEBRIS_SIZE)),
+                    ..default()
+                },
+                transform: Transform::from_translation(position),
+                ..default()
+            },
+            Velocity(velocity),
+            GravityScale(1.0),
+            Interpolated::default(),
+            Debris(Timer::from_seconds(DEBRIS_LIFETIME, TimerMode::Once)),
+            // Purely visual, so it can fly as fast as it likes.
+            Unclamped,
+            GameEntity,
+        ));
+    }
+}
+
+/// Removes debris when its time is up.
+fn debris_system(mut commands: Commands, time: Res<Time>, mut query: Query<(Entity, &mut Debris)>) {
+    for (entity, mut debris) in query.iter_mut() {
+        if debris.0.tick(time.delta()).finished() {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+/// Picks up the key when the player touches it.
+fn key_collection_system(
+    mut commands: Commands,
+    mut objective: ResMut<DoorObjective>,
+    player_query: Query<(&Transform, &Collider), With<Player>>,
+    key_query: Query<(Entity, &Transform), With<Key>>,
+) {
+    for (player_transform, player_collider) in player_query.iter() {
+        for (key_entity, key_transform) in key_query.iter() {
+            if is_colliding(
+                player_transform.translation,
+                player_collider.half_extents,
+                key_transform.translation,
+                KEY_SIZE / 2.0,
+            ) {
+                commands.entity(key_entity).despawn();
+                objective.has_key = true;
+                info!("Key collected!");
+            }
+        }
+    }
+}
+
+/// Blocks the player at the door like an obstacle until they hold the key;
+/// after that, walking into it opens it.
+fn door_collision_system(
+    mut score: ResMut<Score>,
+    activity: Res<ActivityMultiplier>,
+    difficulty: Res<DifficultyMultipliers>,
+    mut objective: ResMut<DoorObjective>,
+    mut player_query: Query<(&mut Transform, &mut Velocity, &Collider), With<Player>>,
+    door_query: Query<&Transform, (With<Door>, Without<Player>)>,
+) {
+    for (mut player_transform, mut player_velocity, player_collider) in player_query.iter_mut() {
+        for door_transform in door_query.iter() {
+            if !objective.has_key {
+                push_out_of_box(
+                    &mut player_transform,
+                    &mut player_velocity,
+                    player_collider.half_extents,
+                    door_transform.translation,
+                    DOOR_SIZE / 2.0,
+                );
+            } else if !objective.opened
+                && is_colliding(
+                    player_transform.translation,
+                    player_collider.half_extents,
+                    door_transform.translation,
+                    DOOR_SIZE / 2.0,
+                )
+            {
+                objective.opened = true;
+                score.0 += difficulty.points(DOOR_BONUS * activity.factor());
+                info!("Door unlocked! Score: {}", score.0);
+            }
+        }
+    }
+}
+
+/// Picks each player's animation from how they're moving: climbing, rising,
+/// falling, running or standing still. A change moves `current` to
+/// `previous` and sends an `AnimTransition`.
+fn process_player_anim_transitions_system(
+    mut transitions: EventWriter<AnimTransition>,
+    mut query: Query<
+        (
+            Entity,
+            &Velocity,
+            &Grounded,
+            Has<Climbing>,
+            &mut PlayerAnimStateMachine,
+        ),
+        Without<DyingAnimation>,
+    >,
+) {
+    for (entity, velocity, grounded, climbing, mut machine) in query.iter_mut() {
+        let next = if climbing {
+            PlayerAnim::Climb
+        } else if !grounded.0 {
+            if velocity.y > 0.0 {
+                PlayerAnim::Jump
+            } else {
+                PlayerAnim::Fall
+            }
+        } else if velocity.x.abs() > RUN_ANIM_THRESHOLD {
+            PlayerAnim::Run
+        } else {
+            PlayerAnim::Idle
+        };
+        if next == machine.current {
+            continue;
+        }
+        machine.previous = machine.current;
+        machine.current = next;
+        transitions.send(AnimTransition {
+            entity,
+            from: machine.previous,
+            to: next,
+        });
+    }
+}
+
+/// Starts each new animation from its first pose.
+fn on_anim_transition_system(
+    mut transitions: EventReader<AnimTransition>,
+    mut query: Query<&mut AnimFrame>,
+) {
+    for transition in transitions.read() {
+        if let Ok(mut frame) = query.get_mut(transition.entity) {
+            frame.index = 0;
+            frame.timer.reset();
+        }
+        debug!(
+            "Player animation: {:?} -> {:?}",
+            transition.from, transition.to
+        );
+    }
+}
+
+/// Steps through the poses of each player's current animation, keeping the
+/// way they face.
+fn animate_player_system(
+    time: Res<Time>,
+    mut query: Query<
+        (&PlayerAnimStateMachine, &mut AnimFrame, &mut Transform),
+        Without<DyingAnimation>,
+    >,
+) {
+    for (machine, mut frame, mut transform) in query.iter_mut() {
+        let frames = machine.current.frames();
+        if frame.timer.tick(time.delta()).just_finished() {
+            frame.index = (frame.index + 1) % frames.len();
+        }
+        let pose = frames[frame.index % frames.len()];
+        transform.scale.x = transform.scale.x.signum() * pose.x;
+        transform.scale.y = pose.y;
+    }
+}
+
+/// Waves the goal flag so it reads as the objective.
+fn goal_flag_wave_system(time: Res<Time>, mut query: Query<&mut Transform, With<GoalFlag>>) {
+    for mut transform in query.iter_mut() {
+        transform.scale.x =
+            1.0 + (time.elapsed_seconds() * GOAL_WAVE_SPEED).sin() * GOAL_WAVE_AMOUNT;
+    }
+}
+
+/// Collects coins the player touches.
+fn coin_collection_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut popups: EventWriter<ScorePopup>,
+    audio_handles: Res<AudioHandles>,
+    sfx_volume: Res<SfxVolume>,
+    mut pitch: ResMut<CoinPitchTracker>,
+    mut score: ResMut<Score>,
+    activity: Res<ActivityMultiplier>,
+    difficulty: Res<DifficultyMultipliers>,
+    mut run_stats: ResMut<RunStats>,
+    mut stats: ResMut<PlayerStats>,
+    player_query: Query<(&Transform, &Collider), With<Player>>,
+    coin_query: Query<(Entity, &Transform), With<Coin>>,
+) {
+    if pitch.reset_timer.tick(time.delta()).just_finished() {
+        pitch.count = 0;
+    }
+    for (player_transform, player_collider) in player_query.iter() {
+        for (coin_entity, coin_transform) in coin_query.iter() {
+            if is_colliding(
+                player_transform.translation,
+                player_collider.half_extents,
+                coin_transform.translation,
+                Vec2::splat(COIN_RADIUS),
+            ) {
+                commands.entity(coin_entity).despawn();
+                let points = difficulty.points(COIN_SCORE * activity.factor());
+                score.0 += points;
+                popups.send(ScorePopup {
+                    text: format!("+{}", points),
+                    position: coin_transform.translation.truncate().extend(1.0),
+                    color: Color::YELLOW,
+                });
+                run_stats.coins_collected += 1;
+                stats.coins_collected += 1;
+                // Each coin in a quick run chimes a little higher.
+                let speed = (1.0 + COIN_PITCH_STEP * pitch.count as f32).min(COIN_PITCH_MAX);
+                play_sound(&mut commands, &audio_handles.coin, &sfx_volume, speed);
+                pitch.count += 1;
+                pitch.reset_timer.reset();
+            }
+        }
+    }
+}
+
+/// Gently bobs coins up and down so they stand out.
+fn coin_bob_system(time: Res<Time>, mut query: Query<(&mut Transform, &Coin)>) {
+    for (mut transform, coin) in query.iter_mut() {
+        // Offset the phase by x so neighbouring coins don't bob in lockstep.
+        let phase = time.elapsed_seconds() * COIN_BOB_SPEED + transform.translation.x * 0.05;
+        transform.translation.y = coin.base_y + phase.sin() * COIN_BOB_AMPLITUDE;
+    }
+}
+
+/// Activates checkpoints the player touches and moves the respawn point to
+/// the most recently touched one.
+fn checkpoint_system(
+    mut score: ResMut<Score>,
+    activity: Res<ActivityMultiplier>,
+    difficulty: Res<DifficultyMultipliers>,
+    mut respawn_point: ResMut<RespawnPoint>,
+    player_query: Query<(&Transform, &Collider), With<Player>>,
+    mut checkpoint_query: Query<(&Transform, &mut Checkpoint, &mut Sprite)>,
+) {
+    for (player_transform, player_collider) in player_query.iter() {
+        for (checkpoint_transform, mut checkpoint, mut sprite) in checkpoint_query.iter_mut() {
+            if !is_colliding(
+                player_transform.translation,
+                player_collider.half_extents,
+                checkpoint_transform.translation,
+                CHECKPOINT_SIZE / 2.0,
+            ) {
+                continue;
+            }
+            if !checkpoint.activated {
+                checkpoint.activated = true;
+                sprite.color = Color::LIME_GREEN;
+                score.0 += difficulty.points(CHECKPOINT_SCORE * activity.factor());
+                info!("Checkpoint reached!");
+            }
+            if respawn_point.0 != checkpoint.respawn {
+                respawn_point.0 = checkpoint.respawn;
+            }
+        }
+    }
+}
+
+/// Costs a life when the player falls into the `FallDeathZone`: below the
+/// bottom of the screen in vertical mode, or down a gap in the ground
+/// otherwise. Each fall also counts towards the lifetime stats.
+fn fall_death_system(
+    zone_query: Query<(&Transform, &Sprite), With<FallDeathZone>>,
+    player_query: Query<(Entity, &Transform, &Collider), (With<Player>, Without<DyingAnimation>)>,
+    mut death_events: EventWriter<PlayerDied>,
+    mut stats: ResMut<PlayerStats>,
+) {
+    let Ok((zone, sprite)) = zone_query.get_single() else {
+        return;
+    };
+    let floor = zone.translation.y + sprite.custom_size.unwrap_or_default().y / 2.0;
+    for (entity, transform, collider) in player_query.iter() {
+        if transform.translation.y - collider.half_extents.y < floor {
+            death_events.send(PlayerDied(entity));
+            stats.falls += 1;
+        }
+    }
+}
+
+/// Starts the death animation for each player killed, stopping them where
+/// they are. A player already dying isn't started again.
+fn start_dying_system(
+    mut commands: Commands,
+    mut death_events: EventReader<PlayerDied>,
+    mut player_query: Query<&mut Velocity, (With<Player>, Without<DyingAnimation>)>,
+) {
+    let mut dead: Vec<Entity> = death_events.read().map(|event| event.0).collect();
+    dead.sort();
+    dead.dedup();
+    for entity in dead {
+        let Ok(mut velocity) = player_query.get_mut(entity) else {
+            continue;
+        };
+        velocity.0 = Vec2::ZERO;
+        commands.entity(entity).insert(DyingAnimation {
+            timer: Timer::from_seconds(DEATH_ANIM_DURATION, TimerMode::Once),
+            spin_speed: DEATH_SPIN_SPEED,
+        });
+    }
+}
+
+/// Spins dying players, shrinking them as they drift up. Once the animation
+/// ends they're set upright at full size again and the death is counted.
+fn dying_animation_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut finished: EventWriter<DeathAnimationFinished>,
+    mut query: Query<(Entity, &mut Transform, &mut DyingAnimation)>,
+) {
+    for (entity, mut transform, mut dying) in query.iter_mut() {
+        let dt = time.delta_seconds();
+        if dying.timer.tick(time.delta()).finished() {
+            transform.rotation = Quat::IDENTITY;
+            transform.scale = Vec3::new(transform.scale.x.signum(), 1.0, 1.0);
+            commands.entity(entity).remove::<DyingAnimation>();
+            finished.send(DeathAnimationFinished(entity));
+            continue;
+        }
+        transform.rotate_z(dying.spin_speed * dt);
+        transform.scale *= DEATH_SHRINK_PER_STEP;
+        transform.translation.y += DEATH_RISE_SPEED * dt;
+    }
+}
+
+/// Takes a life for each finished death and respawns the player, or removes
+/// the player for good once no lives remain. Time attack never runs out of
+/// lives, but each death adds a penalty to the clock. Player 2 has no lives
+/// to lose and is simply removed.
+fn player_death_system(
+    mut commands: Commands,
+    mut death_events: EventReader<DeathAnimationFinished>,
+    mut lives: ResMut<Lives>,
+    game_mode: Res<GameMode>,
+    mut time_attack: ResMut<TimeAttackTimer>,
+    mut stats: ResMut<PlayerStats>,
+    time: Res<Time>,
+    mut tracker: ResMut<PerformanceTracker>,
+    respawn_point: Res<RespawnPoint>,
+    mut camera_cuts: EventWriter<CameraCut>,
+    mut player_query: Query<
+        (
+            Entity,
+            &mut Transform,
+            &mut Velocity,
+            &mut Health,
+            &mut Interpolated,
+            Has<Player2>,
+        ),
+        With<Player>,
+    >,
+    enemy_query: Query<(Entity, &Transform), (With<Enemy>, Without<Player>)>,
+    checkpoint_query: Query<&Checkpoint>,
+) {
+    // Several hits in the same frame only count once.
+    let mut dead: Vec<Entity> = death_events.read().map(|event| event.0).collect();
+    dead.sort();
+    dead.dedup();
+
+    for entity in dead {
+        let Ok((player_entity, mut transform, mut velocity, mut health, mut interpolated, second)) =
+            player_query.get_mut(entity)
+        else {
+            continue;
+        };
+        if second {
+            commands.entity(player_entity).despawn();
+            info!("Player 2 is out!");
+            continue;
+        }
+        if *game_mode == GameMode::TimeAttack {
+            let penalized =
+                time_attack.0.elapsed() + Duration::from_secs_f32(TIME_ATTACK_DEATH_PENALTY);
+            time_attack.0.set_elapsed(penalized);
+        } else {
+            lives.0 -= 1;
+        }
+        stats.deaths += 1;
+        tracker.record_death(time.elapsed_seconds_f64());
+        if lives.0 > 0 {
+            transform.translation = respawn_point.0;
+            // Appear at the respawn point rather than sliding there.
+            interpolated.previous = respawn_point.0;
+            camera_cuts.send(CameraCut);
+            velocity.0 = Vec2::ZERO;
+            health.current = health.max;
+            // Don't drop the player back at a checkpoint next to an enemy.
+            if checkpoint_query.iter().any(|c| c.activated) {
+                for (enemy_entity, enemy_transform) in enemy_query.iter() {
+                    if enemy_transform.translation.distance(respawn_point.0)
+                        < CHECKPOINT_CLEAR_RADIUS
+                    {
+                        commands.entity(enemy_entity).despawn_recursive();
+                    }
+                }
+            }
+            commands
+                .entity(player_entity)
+                .insert(Invulnerable(Timer::from_seconds(
+                    RESPAWN_INVULNERABILITY,
+                    TimerMode::Once,
+                )));
+            info!("Life lost! Lives left: {}", lives.0);
+        } else {
+            commands.entity(player_entity).despawn();
+            info!("Game Over!");
+        }
+    }
+}
+
+/// Tints hit sprites red, then restores their color and drops the flash
+/// once it's over.
+fn hit_flash_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut query: Query<(Entity, &mut HitFlash, &mut Sprite)>,
+) {
+    for (entity, mut flash, mut sprite) in query.iter_mut() {
+        if flash.timer.tick(time.delta()).finished() {
+            sprite.color = flash.original_color;
+            commands.entity(entity).remove::<HitFlash>();
+        } else {
+            sprite.color = HIT_FLASH_COLOR;
+        }
+    }
+}
+
+/// Counts down the grace period after a respawn.
+fn invulnerability_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut query: Query<(Entity, &mut Invulnerable)>,
+) {
+    for (entity, mut invulnerable) in query.iter_mut() {
+        if invulnerable.0.tick(time.delta()).finished() {
+            commands.entity(entity).remove::<Invulnerable>();
+        }
+    }
+}
+
+/// Eases the camera after its `CameraTarget` once the target leaves the
+/// deadzone, without showing past the level's edges, allowing for zoom.
+/// Vertically the level runs from the bottom of the starting view to the
+/// top of the view at `LevelHeight`. A newly spawned target, or one that a
+/// `CameraCut` says has jumped, is centred on at once; with no target the
+/// camera stays put.
+fn camera_follow_system(
+    time: Res<Time>,
+    config: Res<CameraConfig>,
+    mut cuts: EventReader<CameraCut>,
+    level_height: Option<Res<LevelHeight>>,
+    bounds: Res<LevelBounds>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    target_query: Query<(&Transform, Ref<CameraTarget>)>,
+    mut camera_query: Query<
+        (&mut Transform, &OrthographicProjection),
+        (With<Camera2d>, Without<CameraTarget>),
+    >,
+) {
+    let window = window_query.single();
+    let min_y = -window.height() / 2.0;
+    let max_y = level_height.map_or(0.0, |height| height.0) + window.height() / 2.0;
+    let smoothing = 1.0 - (-config.follow_rate * time.delta_seconds()).exp();
+    let cut = cuts.read().count() > 0;
+    let half_deadzone = config.deadzone / 2.0;
+    for (target_transform, target) in target_query.iter() {
+        let snap = cut || target.is_added();
+        let t = if snap { 1.0 } else { smoothing };
+        for (mut camera_transform, projection) in camera_query.iter_mut() {
+            let half_view = view_size(window, projection) / 2.0;
+            let center = camera_transform.translation.truncate();
+            let position = target_transform.translation.truncate();
+            // Just far enough to bring the target back to the deadzone's edge.
+            let followed = if snap {
+                position
+            } else {
+                center + position - position.clamp(center - half_deadzone, center + half_deadzone)
+            };
+            let goal = Vec2::new(
+                clamp_view_center(followed.x, bounds.min_x, bounds.max_x, half_view.x),
+                clamp_view_center(followed.y, min_y, max_y, half_view.y),
+            );
+            let moved = center.lerp(goal, t);
+            camera_transform.translation.x = moved.x;
+            camera_transform.translation.y = moved.y;
+        }
+    }
+}
+
+/// Outlines the camera deadzone while the debug overlay is on.
+fn draw_deadzone_system(
+    config: Res<CameraConfig>,
+    camera_query: Query<&Transform, With<Camera2d>>,
+    mut gizmos: Gizmos,
+) {
+    for transform in camera_query.iter() {
+        gizmos.rect_2d(
+            transform.translation.truncate(),
+            0.0,
+            config.deadzone,
+            Color::YELLOW,
+        );
+    }
+}
+
+/// Outlines the hitboxes of players (green), enemies (red) and obstacles
+/// (yellow), with an arrow for each player's and enemy's velocity.
+#[cfg(debug_assertions)]
+fn draw_hitboxes_system(
+    mut gizmos: Gizmos,
+    query: Query<(
+        &Transform,
+        &Collider,
+        Option<&Velocity>,
+        Has<Player>,
+        Has<Enemy>,
+        Has<Obstacle>,
+    )>,
+) {
+    for (transform, collider, velocity, player, enemy, obstacle) in query.iter() {
+        let color = if player {
+            Color::GREEN
+        } else if enemy {
+            Color::RED
+        } else if obstacle {
+            Color::YELLOW
+        } else {
+            continue;
+        };
+        let center = transform.translation.truncate();
+        gizmos.rect_2d(center, 0.0, collider.half_extents * 2.0, color);
+        let Some(velocity) = velocity.filter(|_| player || enemy) else {
+            continue;
+        };
+        let tip = center + velocity.0 * DEBUG_VELOCITY_SCALE;
+        gizmos.line_2d(center, tip, color);
+        if let Some(back) = (center - tip).try_normalize() {
+            let side = back.perp();
+            gizmos.line_2d(tip, tip + (back + side) * DEBUG_ARROW_HEAD, color);
+            gizmos.line_2d(tip, tip + (back - side) * DEBUG_ARROW_HEAD, color);
+        }
+    }
+}
+
+/// Keeps a velocity readout above every player and enemy while the debug
+/// overlay is on, and clears them all when it's turned off.
+#[cfg(debug_assertions)]
+fn debug_label_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    overlay: Res<DebugOverlay>,
+    target_query: Query<
+        (Entity, &Transform, &Velocity, &Collider),
+        Or<(With<Player>, With<Enemy>)>,
+    >,
+    mut label_query: Query<(Entity, &DebugLabel, &mut Text, &mut Transform), Without<Velocity>>,
+) {
+    let mut labelled = Vec::new();
+    for (entity, label, mut text, mut transform) in label_query.iter_mut() {
+        let target = target_query.get(label.target).ok().filter(|_| overlay.0);
+        let Some((_, target_transform, velocity, collider)) = target else {
+            commands.entity(entity).despawn();
+            continue;
+        };
+        text.sections[0].value = format!("{:.0}, {:.0}", velocity.x, velocity.y);
+        transform.translation = target_transform.translation
+            + Vec3::new(0.0, collider.half_extents.y + DEBUG_LABEL_SIZE, 2.0);
+        labelled.push(label.target);
+    }
+    if !overlay.0 {
+        return;
+    }
+    for (target, ..) in target_query.iter() {
+        if !labelled.contains(&target) {
+            commands.spawn((
+                Text2dBundle {
+                    text: Text::from_section(
+                        "",
+                        TextStyle {
+                            font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+                            font_size: DEBUG_LABEL_SIZE,
+                            color: Color::WHITE,
+                        },
+                    ),
+                    ..default()
+                },
+                DebugLabel { target },
+                GameEntity,
+            ));
+        }
+    }
+}
+
+/// Shows or hides the debug overlay when F3 is pressed.
+fn toggle_debug_overlay_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut overlay: ResMut<DebugOverlay>,
+) {
+    if keyboard_input.just_pressed(KeyCode::F3) {
+        overlay.0 = !overlay.0;
+    }
+}
+
+/// Shows or hides the minimap when M is pressed.
+fn toggle_minimap_system(keyboard_input: Res<Input<KeyCode>>, mut hidden: ResMut<MinimapHidden>) {
+    if keyboard_input.just_pressed(KeyCode::M) {
+        hidden.0 = !hidden.0;
+    }
+}
+
+/// Moves the minimap's dots onto the obstacles, enemies, goal and players,
+/// mapping their x from the level bounds onto the strip. Players come last
+/// so their dots draw on top. The strip stays hidden on single-screen
+/// levels and while M has it off.
+fn minimap_system(
+    hidden: Res<MinimapHidden>,
+    bounds: Res<LevelBounds>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    mut minimap_query: Query<&mut Visibility, (With<Minimap>, Without<MinimapDot>)>,
+    mut dot_query: Query<(&mut Style, &mut BackgroundColor, &mut Visibility), With<MinimapDot>>,
+    obstacle_query: Query<&Transform, With<Obstacle>>,
+    enemy_query: Query<&Transform, With<Enemy>>,
+    goal_query: Query<&Transform, Or<(With<GoalFlag>, With<Door>)>>,
+    player_query: Query<&Transform, With<Player>>,
+) {
+    let Ok(mut minimap_visibility) = minimap_query.get_single_mut() else {
+        return;
+    };
+    let Ok(window) = window_query.get_single() else {
+        return;
+    };
+    if hidden.0 || bounds.screen_count(window) <= 1 {
+        minimap_visibility.set_if_neq(Visibility::Hidden);
+        return;
+    }
+    minimap_visibility.set_if_neq(Visibility::Inherited);
+
+    let obstacles = obstacle_query
+        .iter()
+        .map(|transform| (transform, MINIMAP_OBSTACLE_COLOR));
+    let enemies = enemy_query
+        .iter()
+        .map(|transform| (transform, MINIMAP_ENEMY_COLOR));
+    let goals = goal_query
+        .iter()
+        .map(|transform| (transform, MINIMAP_GOAL_COLOR));
+    let players = player_query
+        .iter()
+        .map(|transform| (transform, MINIMAP_PLAYER_COLOR));
+    let mut markers = obstacles.chain(enemies).chain(goals).chain(players);
+    for (mut style, mut background, mut visibility) in dot_query.iter_mut() {
+        match markers.next() {
+            Some((transform, color)) => {
+                let t = ((transform.translation.x - bounds.min_x) / bounds.width()).clamp(0.0, 1.0);
+                style.left = Val::Percent(t * 100.0);
+                background.0 = color;
+                visibility.set_if_neq(Visibility::Inherited);
+            }
+            None => {
+                visibility.set_if_neq(Visibility::Hidden);
+            }
+        }
+    }
+}
+
+/// Spawns the debug readout in the bottom-left corner, above the seed,
+/// when the overlay is turned on, and despawns it when it's turned off.
+fn spawn_debug_hud_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    overlay: Res<DebugOverlay>,
+    hud_query: Query<Entity, With<DebugHud>>,
+) {
+    for entity in hud_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    if !overlay.0 {
+        return;
+    }
+    let refresh = Timer::from_seconds(DEBUG_HUD_REFRESH, TimerMode::Repeating);
+    commands.spawn((
+        TextBundle {
+            text: Text::from_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+                    font_size: 20.0,
+                    color: Color::LIME_GREEN,
+                },
+            ),
+            style: Style {
+                position_type: PositionType::Absolute,
+                bottom: Val::Px(40.0),
+                left: Val::Px(10.0),
+                ..default()
+            },
+            ..default()
+        },
+        DebugHud { refresh },
+    ));
+}
+
+/// Fills in the debug readout with the frame rate, frame time and how many
+/// enemies, obstacles and falling rocks there are.
+fn update_debug_hud_system(
+    time: Res<Time<Real>>,
+    diagnostics: Res<DiagnosticsStore>,
+    mut hud_query: Query<(&mut Text, &mut DebugHud)>,
+    enemy_query: Query<(), With<Enemy>>,
+    obstacle_query: Query<(), With<Obstacle>>,
+    hazard_query: Query<(), With<FallingHazard>>,
+) {
+    let Ok((mut text, mut hud)) = hud_query.get_single_mut() else {
+        return;
+    };
+    // Fill the text in straight away rather than a beat after spawning.
+    if !hud.refresh.tick(time.delta()).just_finished() && !hud.is_added() {
+        return;
+    }
+    let smoothed = |path| {
+        diagnostics
+            .get(path)
+            .and_then(|diagnostic| diagnostic.smoothed())
+            .unwrap_or(0.0)
+    };
+    text.sections[0].value = format!(
+        "FPS: {:.0}\nFrame: {:.1} ms\nEnemies: {}\nObstacles: {}\nRocks: {}",
+        smoothed(FrameTimeDiagnosticsPlugin::FPS),
+        smoothed(FrameTimeDiagnosticsPlugin::FRAME_TIME),
+        enemy_query.iter().count(),
+        obstacle_query.iter().count(),
+        hazard_query.iter().count(),
+    );
+}
+
+/// Scrolls each parallax layer at its share of the camera's speed, from
+/// where the camera is following rather than where the shake has put it,
+/// so the shake moves the background with everything else.
+fn parallax_system(
+    shake: Res<ScreenShake>,
+    camera_query: Query<&Transform, With<Camera2d>>,
+    mut layer_query: Query<(&mut Transform, &Parallax), Without<Camera2d>>,
+) {
+    let Ok(camera_transform) = camera_query.get_single() else {
+        return;
+    };
+    let camera = camera_transform.translation.truncate() - shake.offset;
+    for (mut transform, parallax) in layer_query.iter_mut() {
+        // Equivalent to `camera.x * (1 - factor)`, less whole repeats.
+        let scrolled = camera.x * parallax.factor;
+        transform.translation.x = camera.x - scrolled.rem_euclid(PARALLAX_TILE_WIDTH);
+        transform.translation.y = parallax.base_y + camera.y * (1.0 - parallax.factor);
+    }
+}
+
+/// Where along one axis to centre a view reaching `half_view` either side,
+/// as close to `target` as it can be without showing past `min..max`. When
+/// the level is no bigger than the view, the view is centred on it.
+fn clamp_view_center(target: f32, min: f32, max: f32, half_view: f32) -> f32 {
+    if max - min <= half_view * 2.0 {
+        (min + max) / 2.0
+    } else {
+        target.clamp(min + half_view, max - half_view)
+    }
+}
+
+/// World-space size of what the camera shows: the window, scaled by zoom.
+fn view_size(window: &Window, projection: &OrthographicProjection) -> Vec2 {
+    Vec2::new(window.width(), window.height()) * projection.scale
+}
+
+/// Steps the chosen zoom with the mouse wheel or the +/- keys.
+fn zoom_input_system(
+    keyboard: Res<Input<KeyCode>>,
+    mut wheel_events: EventReader<MouseWheel>,
+    mut zoom: ResMut<CameraZoom>,
+) {
+    // Scrolling up or pressing + zooms in, to a smaller scale.
+    let mut steps: f32 = wheel_events.read().map(|event| -event.y.signum()).sum();
+    if keyboard.any_just_pressed([KeyCode::Equals, KeyCode::NumpadAdd]) {
+        steps -= 1.0;
+    }
+    if keyboard.any_just_pressed([KeyCode::Minus, KeyCode::NumpadSubtract]) {
+        steps += 1.0;
+    }
+    if steps != 0.0 {
+        zoom.chosen = (zoom.chosen + steps * ZOOM_STEP).clamp(ZOOM_RANGE.0, ZOOM_RANGE.1);
+    }
+}
+
+/// Eases the camera's scale toward the chosen zoom, pulled out a little
+/// further by auto zoom when lots of enemies are about or the player is
+/// high above anything to land on. A new wave briefly pulls it out further
+/// still, unless reduced motion is on.
+fn camera_zoom_system(
+    time: Res<Time>,
+    settings: Res<Settings>,
+    mut waves: EventReader<WaveAnnouncement>,
+    mut zoom: ResMut<CameraZoom>,
+    raycaster: Raycaster,
+    enemy_query: Query<(), With<Enemy>>,
+    player_query: Query<(&Transform, &Collider, &Grounded), With<Player>>,
+    mut camera_query: Query<&mut OrthographicProjection, With<Camera2d>>,
+) {
+    let crowded = enemy_query.iter().count() > AUTO_ZOOM_ENEMY_COUNT;
+    let high_up = player_query.iter().any(|(transform, collider, grounded)| {
+        let feet = transform.translation.truncate() - Vec2::new(0.0, collider.half_extents.y);
+        !grounded.0
+            && raycaster
+                .cast(feet, Vec2::NEG_Y, AUTO_ZOOM_HEIGHT)
+                .is_none()
+    });
+    let mut target = zoom.chosen;
+    if zoom.auto && (crowded || high_up) {
+        target = (target + AUTO_ZOOM_OUT).min(ZOOM_RANGE.1);
+    }
+    let smoothing = 1.0 - (-ZOOM_RATE * time.delta_seconds()).exp();
+    zoom.eased += (target - zoom.eased) * smoothing;
+
+    if waves.read().count() > 0 && !settings.reduced_motion {
+        zoom.pulse = Some(Timer::from_seconds(
+            WAVE_PULSE_OUT + WAVE_PULSE_BACK,
+            TimerMode::Once,
+        ));
+    }
+    let mut pulse = 1.0;
+    if let Some(timer) = &mut zoom.pulse {
+        let elapsed = timer.tick(time.delta()).elapsed_secs();
+        // Ease out, then back in, along a smoothstep each way.
+        let t = if elapsed < WAVE_PULSE_OUT {
+            elapsed / WAVE_PULSE_OUT
+        } else {
+            1.0 - (elapsed - WAVE_PULSE_OUT) / WAVE_PULSE_BACK
+        };
+        pulse += WAVE_PULSE_ZOOM * t * t * (3.0 - 2.0 * t);
+        if timer.finished() {
+            zoom.pulse = None;
+        }
+    }
+    for mut projection in camera_query.iter_mut() {
+        projection.scale = zoom.eased * pulse;
+    }
+}
+
+/// Takes last frame's shake back off the camera, so following starts from
+/// where the camera really is.
+fn remove_screen_shake_system(
+    mut shake: ResMut<ScreenShake>,
+    mut camera_query: Query<&mut Transform, With<Camera2d>>,
+) {
+    let offset = std::mem::take(&mut shake.offset);
+    for mut camera_transform in camera_query.iter_mut() {
+        camera_transform.translation -= offset.extend(0.0);
+    }
+}
+
+/// Drains trauma and knocks the camera a random distance off its followed
+/// position, more the higher the trauma. Nothing shakes with reduced motion
+/// on.
+fn screen_shake_system(
+    time: Res<Time>,
+    settings: Res<Settings>,
+    mut shake: ResMut<ScreenShake>,
+    mut camera_query: Query<&mut Transform, With<Camera2d>>,
+) {
+    if shake.trauma <= 0.0 {
+        return;
+    }
+    let strength = if settings.reduced_motion {
+        0.0
+    } else {
+        shake.trauma * shake.trauma * SHAKE_MAX_OFFSET
+    };
+    let mut rng = rand::thread_rng();
+    shake.offset = Vec2::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0)) * strength;
+    shake.trauma = (shake.trauma - SHAKE_DECAY * time.delta_seconds()).max(0.0);
+    for mut camera_transform in camera_query.iter_mut() {
+        camera_transform.translation += shake.offset.extend(0.0);
+    }
+}
+
+/// Updates the UI score text when the score changes. Once the score passes
+/// the best, the best follows it in gold, and passing a saved best puts up
+/// a "New Record!" banner.
+fn update_score_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    score: Res<Score>,
+    mut high_score: ResMut<HighScore>,
+    high_scores: Res<HighScores>,
+    game_mode: Res<GameMode>,
+    current_level: Res<CurrentLevel>,
+    mut query: Query<&mut Text, With<ScoreText>>,
+) {
+    if !score.is_changed() {
+        return;
+    }
+    let beaten = score.0 > high_score.0;
+    if beaten {
+        // Only the first time past a saved best is a new record.
+        let saved = high_scores.best_in(*game_mode, current_level.0);
+        if saved == Some(high_score.0) {
+            spawn_banner(&mut commands, &asset_server, "New Record!", Color::GOLD);
+        }
+        high_score.0 = score.0;
+    }
+    for mut text in query.iter_mut() {
+        text.sections[0].value = format!("Score: {}", score.0);
+        if beaten {
+            text.sections[1].value = format!("  Best: {}", high_score.0);
+            text.sections[1].style.color = Color::GOLD;
+        }
+    }
+}
+
+/// Updates the UI coin counter when a coin is collected.
+fn update_coin_text_system(run_stats: Res<RunStats>, mut query: Query<&mut Text, With<CoinText>>) {
+    if run_stats.is_changed() {
+        for mut text in query.iter_mut() {
+            text.sections[0].value = format!("Coins: {}", run_stats.coins_collected);
+        }
+    }
+}
+
+/// Shows the combo multiplier while a combo is running, with its bar
+/// draining as the window to keep it going runs out. The multiplier swells
+/// when it goes up and shakes as the window is about to close.
+fn combo_ui_system(
+    time: Res<Time>,
+    combo: Res<ComboState>,
+    mut ui_query: Query<(&mut ComboUI, &mut Visibility)>,
+    mut text_query: Query<(&mut Text, &mut Style), With<ComboHudText>>,
+    mut fill_query: Query<&mut Style, (With<ComboBarFill>, Without<ComboHudText>)>,
+) {
+    let Ok((mut ui, mut visibility)) = ui_query.get_single_mut() else {
+        return;
+    };
+    if combo.count <= 1 {
+        *visibility = Visibility::Hidden;
+        ui.shown = combo.count;
+        return;
+    }
+    *visibility = Visibility::Inherited;
+    if combo.count > ui.shown {
+        ui.pulse.reset();
+    }
+    ui.shown = combo.count;
+    let swell = 1.0 + COMBO_PULSE_SCALE * ui.pulse.tick(time.delta()).percent_left();
+
+    let left = combo.timer.percent_left();
+    let shake = if left < COMBO_SHAKE_FRACTION {
+        (time.elapsed_seconds() * COMBO_SHAKE_SPEED).sin() * COMBO_SHAKE_AMOUNT
+    } else {
+        0.0
+    };
+    for (mut text, mut style) in text_query.iter_mut() {
+        text.sections[0].value = format!("x{}", combo.count);
+        text.sections[0].style.font_size = COMBO_FONT_SIZE * swell;
+        style.left = Val::Px(shake);
+    }
+    for mut style in fill_query.iter_mut() {
+        style.width = Val::Percent(left * 100.0);
+    }
+}
+
+/// Shows the hot-streak multiplier and how full its bar is.
+fn update_activity_hud_system(
+    activity: Res<ActivityMultiplier>,
+    mut text_query: Query<&mut Text, With<ActivityText>>,
+    mut fill_query: Query<&mut Style, With<ActivityBarFill>>,
+) {
+    if !activity.is_changed() {
+        return;
+    }
+    for mut text in text_query.iter_mut() {
+        text.sections[0].value = format!("x{}", activity.factor());
+    }
+    let fraction = (activity.value - 1.0) / (ACTIVITY_MAX - 1.0);
+    for mut style in fill_query.iter_mut() {
+        style.width = Val::Percent(fraction * 100.0);
+    }
+}
+
+/// Spawns a health bar split into one segment per point of health:
+/// player 1's in the top-left corner, or player 2's on the right below the
+/// score counters.
+fn spawn_health_bar(commands: &mut Commands, second_player: bool) {
+    let (left, right, top) = if second_player {
+        (Val::Auto, Val::Px(10.0), Val::Px(165.0))
+    } else {
+        (Val::Px(10.0), Val::Auto, Val::Px(10.0))
+    };
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    top,
+                    left,
+                    right,
+                    width: Val::Px(HEALTH_BAR_SIZE.x),
+                    height: Val::Px(HEALTH_BAR_SIZE.y),
+                    border: UiRect::all(Val::Px(2.0)),
+                    ..default()
+                },
+                background_color: Color::rgb(0.2, 0.2, 0.2).into(),
+                border_color: Color::WHITE.into(),
+                ..default()
+            },
+            HealthBarUI::new(PLAYER_MAX_HEALTH, second_player),
+            GameEntity,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        width: Val::Percent(100.0),
+                        height: Val::Percent(100.0),
+                        ..default()
+                    },
+                    background_color: Color::GREEN.into(),
+                    ..default()
+                },
+                HealthBarFill,
+            ));
+            spawn_health_dividers(parent, PLAYER_MAX_HEALTH);
+        });
+}
+
+/// Splits the health bar into one segment per point of `max` health.
+fn spawn_health_dividers(parent: &mut ChildBuilder, max: f32) {
+    let segments = max as i32;
+    for segment in 1..segments {
+        parent.spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    left: Val::Percent(segment as f32 * 100.0 / segments as f32),
+                    width: Val::Px(2.0),
+                    height: Val::Percent(100.0),
+                    ..default()
+                },
+                background_color: Color::WHITE.into(),
+                ..default()
+            },
+            HealthBarDivider,
+        ));
+    }
+}
+
+/// Sizes each health bar to its player's health. Low health turns it red
+/// and makes it pulse. The border flashes red when health drops and green
+/// when it goes back up, and a new maximum redraws the segments. It reads
+/// whichever player is alive, so it carries on across respawns; player 2's
+/// bar empties once they're out.
+fn update_health_bar_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    player_query: Query<(&Health, Has<Player2>), With<Player>>,
+    mut bar_query: Query<(Entity, &mut HealthBarUI, &mut BorderColor, &Children)>,
+    divider_query: Query<(), With<HealthBarDivider>>,
+    mut fill_query: Query<(&mut Style, &mut BackgroundColor), With<HealthBarFill>>,
+) {
+    for (entity, mut bar, mut border, children) in bar_query.iter_mut() {
+        let health = player_query
+            .iter()
+            .find(|&(_, second)| second == bar.second_player)
+            .map(|(health, _)| health);
+        let (current, max, fraction) = match health {
+            Some(health) => (health.current, health.max, health.fraction()),
+            None if bar.second_player => (0.0, bar.max, 0.0),
+            None => continue,
+        };
+        if max != bar.max {
+            for &child in children {
+                if divider_query.contains(child) {
+                    commands.entity(child).despawn_recursive();
+                }
+            }
+            commands
+                .entity(entity)
+                .with_children(|parent| spawn_health_dividers(parent, max));
+            bar.max = max;
+        }
+        if current != bar.current {
+            bar.flash_color = if current < bar.current {
+                Color::RED
+            } else {
+                Color::GREEN
+            };
+            bar.flash.reset();
+            bar.current = current;
+        }
+        // Fade from the flash color back to white.
+        let t = bar.flash.tick(time.delta()).percent();
+        let [r, g, b, _] = bar.flash_color.as_rgba_f32();
+        border.0 = Color::rgb(r + (1.0 - r) * t, g + (1.0 - g) * t, b + (1.0 - b) * t);
+
+        let color = if fraction <= LOW_HEALTH_FRACTION {
+            let pulse = (time.elapsed_seconds() * HEALTH_PULSE_SPEED).sin() * 0.5 + 0.5;
+            Color::RED.with_a(0.5 + 0.5 * pulse)
+        } else {
+            Color::GREEN
+        };
+        let mut fills = fill_query.iter_many_mut(children);
+        while let Some((mut style, mut background)) = fills.fetch_next() {
+            style.width = Val::Percent(fraction * 100.0);
+            background.0 = color;
+        }
+    }
+}
+
+/// Keeps one player icon per remaining life in the lives row, adding icons
+/// when lives are gained and shrinking away the ones lost. Losing the last
+/// life shatters the final icon. Icons fall back to a plain square if the
+/// player texture didn't load.
+fn update_lives_ui_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    lives: Res<Lives>,
+    row_query: Query<(Entity, Option<&Children>), With<LivesUI>>,
+    mut icon_query: Query<(&mut UiImage, &mut BackgroundColor, Has<LostLifeIcon>), With<LifeIcon>>,
+) {
+    let Ok((row, children)) = row_query.get_single() else {
+        return;
+    };
+    let icons: Vec<Entity> = children
+        .into_iter()
+        .flatten()
+        .copied()
+        .filter(|&child| matches!(icon_query.get(child), Ok((.., false))))
+        .collect();
+
+    let texture = asset_server.load("player.png");
+    if asset_server.get_load_state(&texture) == Some(LoadState::Failed) {
+        for (mut image, mut background, _) in icon_query.iter_mut() {
+            if image.texture != Handle::default() {
+                image.texture = Handle::default();
+                background.0 = LIFE_ICON_FALLBACK_COLOR;
+            }
+        }
+    }
+
+    let wanted = lives.0.max(0) as usize;
+    if wanted > icons.len() {
+        commands.entity(row).with_children(|parent| {
+            for _ in icons.len()..wanted {
+                parent.spawn((
+                    ImageBundle {
+                        style: Style {
+                            width: Val::Px(LIFE_ICON_SIZE),
+                            height: Val::Px(LIFE_ICON_SIZE),
+                            margin: UiRect::right(Val::Px(LIFE_ICON_SPACING - LIFE_ICON_SIZE)),
+                            ..default()
+                        },
+                        image: UiImage::new(texture.clone()),
+                        ..default()
+                    },
+                    LifeIcon,
+                ));
+            }
+        });
+    }
+    for &icon in icons.iter().skip(wanted) {
+        let shrink = Timer::from_seconds(LIFE_ICON_SHRINK_DURATION, TimerMode::Once);
+        commands.entity(icon).insert(LostLifeIcon(shrink));
+    }
+
+    if lives.0 <= 0 && !icons.is_empty() {
+        let center = Vec2::new(10.0, LIVES_TOP) + Vec2::splat(LIFE_ICON_SIZE / 2.0);
+        let mut rng = rand::thread_rng();
+        for _ in 0..LIFE_SHARD_COUNT {
+            let angle = rng.gen_range(0.0..std::f32::consts::TAU);
+            commands.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        left: Val::Px(center.x),
+                        top: Val::Px(center.y),
+                        width: Val::Px(LIFE_SHARD_SIZE),
+                        height: Val::Px(LIFE_SHARD_SIZE),
+                        ..default()
+                    },
+                    background_color: Color::RED.into(),
+                    ..default()
+                },
+                LifeIconShard {
+                    velocity: Vec2::from_angle(angle) * LIFE_SHARD_SPEED,
+                    timer: Timer::from_seconds(LIFE_SHARD_LIFETIME, TimerMode::Once),
+                },
+                GameEntity,
+            ));
+        }
+    }
+}
+
+/// Shrinks lost life icons away, removing each once it's gone.
+fn lost_life_icon_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut query: Query<(Entity, &mut LostLifeIcon, &mut Style)>,
+) {
+    for (entity, mut lost, mut style) in query.iter_mut() {
+        if lost.0.tick(time.delta()).finished() {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        }
+        let size = Val::Px(LIFE_ICON_SIZE * lost.0.percent_left());
+        style.width = size;
+        style.height = size;
+    }
+}
+
+/// Flies life icon shards outward, fading them until they disappear.
+fn life_icon_shard_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut query: Query<(Entity, &mut LifeIconShard, &mut Style, &mut BackgroundColor)>,
+) {
+    for (entity, mut shard, mut style, mut background) in query.iter_mut() {
+        if shard.timer.tick(time.delta()).finished() {
+            commands.entity(entity).despawn();
+            continue;
+        }
+        let step = shard.velocity * time.delta_seconds();
+        if let (Val::Px(left), Val::Px(top)) = (style.left, style.top) {
+            style.left = Val::Px(left + step.x);
+            style.top = Val::Px(top + step.y);
+        }
+        background.0.set_a(1.0 - shard.timer.percent());
+    }
+}
+
+/// The first connected gamepad, which drives the player alongside the keyboard.
+fn first_gamepad(gamepads: &Gamepads) -> Option<Gamepad> {
+    gamepads.iter().next()
+}
+
+/// Shows whether a controller is connected.
+fn update_gamepad_text_system(
+    gamepads: Res<Gamepads>,
+    mut query: Query<&mut Text, With<GamepadConnectedText>>,
+) {
+    let status = if first_gamepad(&gamepads).is_some() {
+        "Controller connected"
+    } else {
+        ""
+    };
+    for mut text in query.iter_mut() {
+        if text.sections[0].value != status {
+            text.sections[0].value = status.to_string();
+        }
+    }
+}
+
+/// Plays the hurt sound, reddens the edges of the screen and shakes the
+/// camera when the player is hit.
+fn hurt_feedback_system(
+    mut commands: Commands,
+    audio_handles: Res<AudioHandles>,
+    sfx_volume: Res<SfxVolume>,
+    mut shake: ResMut<ScreenShake>,
+    mut hurt_events: EventReader<PlayerHurt>,
+) {
+    if hurt_events.read().count() == 0 {
+        return;
+    }
+    play_sound(&mut commands, &audio_handles.hurt, &sfx_volume, 1.0);
+    shake.add_trauma(SHAKE_HURT_TRAUMA);
+    for ring in 1..=DAMAGE_VIGNETTE_RINGS {
+        let width = DAMAGE_VIGNETTE_WIDTH * ring as f32 / DAMAGE_VIGNETTE_RINGS as f32;
+        commands.spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    border: UiRect::all(Val::Px(width)),
+                    ..default()
+                },
+                border_color: Color::rgba(1.0, 0.0, 0.0, damage_vignette_ring_alpha(1.0)).into(),
+                ..default()
+            },
+            DamageVignette(Timer::from_seconds(
+                DAMAGE_VIGNETTE_DURATION,
+                TimerMode::Once,
+            )),
+            GameEntity,
+        ));
+    }
+}
+
+/// The alpha for each vignette ring, so that where all of them overlap at
+/// the screen's edge the red reaches `DAMAGE_VIGNETTE_ALPHA` times `strength`.
+fn damage_vignette_ring_alpha(strength: f32) -> f32 {
+    let edge = DAMAGE_VIGNETTE_ALPHA * strength;
+    1.0 - (1.0 - edge).powf(1.0 / DAMAGE_VIGNETTE_RINGS as f32)
+}
+
+/// Whites out the screen as the level is won, so gameplay doesn't cut
+/// straight to the win screen.
+fn spawn_win_flash(mut commands: Commands) {
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                ..default()
+            },
+            background_color: Color::WHITE.into(),
+            z_index: ZIndex::Global(999),
+            ..default()
+        },
+        ScreenFlash {
+            timer: Timer::from_seconds(WIN_FLASH_DURATION, TimerMode::Once),
+        },
+        WinScreenEntity,
+    ));
+}
+
+/// Fades the win flash out to reveal the screen beneath, then removes it.
+fn screen_flash_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut query: Query<(Entity, &mut ScreenFlash, &mut BackgroundColor)>,
+) {
+    for (entity, mut flash, mut color) in query.iter_mut() {
+        if flash.timer.tick(time.delta()).finished() {
+            commands.entity(entity).despawn();
+        } else {
+            color.0.set_a(flash.timer.percent_left());
+        }
+    }
+}
+
+/// Fades out the damage vignette and removes it once it's clear.
+fn damage_vignette_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut query: Query<(Entity, &mut DamageVignette, &mut BorderColor)>,
+) {
+    for (entity, mut vignette, mut color) in query.iter_mut() {
+        if vignette.0.tick(time.delta()).finished() {
+            commands.entity(entity).despawn();
+        } else {
+            color
+                .0
+                .set_a(damage_vignette_ring_alpha(vignette.0.percent_left()));
+        }
+    }
+}
+
+/// Rumbles the controller whenever the player gets hurt.
+fn gamepad_rumble_system(
+    gamepads: Res<Gamepads>,
+    mut hurt_events: EventReader<PlayerHurt>,
+    mut rumble_requests: EventWriter<GamepadRumbleRequest>,
+) {
+    if hurt_events.read().count() == 0 {
+        return;
+    }
+    if let Some(gamepad) = first_gamepad(&gamepads) {
+        rumble_requests.send(GamepadRumbleRequest::Add {
+            gamepad,
+            duration: Duration::from_secs_f32(GAMEPAD_RUMBLE_DURATION),
+            intensity: GamepadRumbleIntensity::strong_motor(GAMEPAD_RUMBLE_STRENGTH),
+        });
+    }
+}
+
+/// Fades out each tutorial hint after it has shown for a while, then moves
+/// on to the next one. Once the last hint has faded the tutorial is removed.
+fn tutorial_system(
     mut commands: Commands,
-    mut score: ResMut<Score>,
-    player_query: Query<(&Transform, &Sprite), With<Player>>,
-    enemy_query: Query<(Entity, &Transform, &Sprite), With<Enemy>>,
-    asset_server: Res<AssetServer>,
-    player_entity_query: Query<Entity, With<Player>>,
-) {
-    for (player_transform, player_sprite) in player_query.iter() {
-        let player_half = player_sprite
-            .custom_size
-            .unwrap_or(PLAYER_SIZE)
-            / 2.0;
-        for (enemy_entity, enemy_transform, enemy_sprite) in enemy_query.iter() {
-            let enemy_half = enemy_sprite
-                .custom_size
-                .unwrap_or(ENEMY_SIZE)
-                / 2.0;
-            if is_colliding(
-                player_transform.translation,
-                Vec2::splat(player_half.x),
-                enemy_transform.translation,
-                Vec2::splat(enemy_half.x),
-            ) {
-                // Stomp enemy if player is above.
-                if player_transform.translation.y - player_half.y
-                    >= enemy_transform.translation.y + enemy_half.y - 5.0
-                {
-                    commands.entity(enemy_entity).despawn();
-                    score.0 += 100;
-                    info!("Enemy defeated! Score: {}", score.0);
-                } else {
-                    // Game over scenario.
-                    commands.spawn(TextBundle {
-                        text: Text::from_section(
-                            "Game Over",
-                            TextStyle {
-                                font: asset_server.load("fonts/FiraSans-Bold.ttf"),
-                                font_size: 80.0,
-                                color: Color::RED,
-                            },
-                        ),
-                        style: Style {
-                            position_type: PositionType::Absolute,
-                            top: Val::Percent(40.0),
-                            left: Val::Percent(35.0),
-                            ..default()
-                        },
-                        ..default()
-                    });
-                    for player_entity in player_entity_query.iter() {
-                        commands.entity(player_entity).despawn();
-                    }
-                    info!("Game Over!");
-                }
-            }
+    time: Res<Time>,
+    mut text_query: Query<(&mut Text, &mut TutorialText, &Parent)>,
+    mut panel_query: Query<(&mut BackgroundColor, &Parent)>,
+) {
+    for (mut text, mut tutorial, panel) in text_query.iter_mut() {
+        let Ok((mut background, root)) = panel_query.get_mut(panel.get()) else {
+            continue;
+        };
+        if tutorial.display_timer.tick(time.delta()).finished() {
+            tutorial.index += 1;
+            let Some(hint) = TUTORIAL_HINTS.get(tutorial.index) else {
+                commands.entity(root.get()).despawn_recursive();
+                continue;
+            };
+            text.sections[0].value = hint.to_string();
+            tutorial.display_timer.reset();
         }
+        let fading_for = tutorial.display_timer.elapsed_secs() - TUTORIAL_HINT_DURATION;
+        let alpha = 1.0 - (fading_for / TUTORIAL_FADE_DURATION).clamp(0.0, 1.0);
+        text.sections[0].style.color.set_a(alpha);
+        background.0.set_a(0.6 * alpha);
     }
 }
 
-fn enemy_obstacle_collision_system(
-    mut enemy_query: Query<(&Transform, &mut Velocity), With<Enemy>>,
-    obstacle_query: Query<&Transform, With<Obstacle>>,
+/// Fades the controls card out once its time is up or the player has both
+/// moved and jumped, then removes it.
+fn controls_hint_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    progress: Res<TutorialProgress>,
+    mut hint_query: Query<(&mut ControlsHint, &mut BackgroundColor, &Children, &Parent)>,
+    mut text_query: Query<&mut Text>,
 ) {
-    for (enemy_transform, mut enemy_velocity) in enemy_query.iter_mut() {
-        // Define enemy half size (assuming enemy sprite uses ENEMY_SIZE)
-        let enemy_half = ENEMY_SIZE / 2.0;
-        for obstacle_transform in obstacle_query.iter() {
-            // Define obstacle half size (assuming obstacle sprite uses OBSTACLE_SIZE)
-            let obstacle_half = OBSTACLE_SIZE / 2.0;
-            let enemy_pos = enemy_transform.translation;
-            let obstacle_pos = obstacle_transform.translation;
-            // Basic AABB collision detection
-            let collision = (enemy_pos.x - enemy_half.x < obstacle_pos.x + obstacle_half.x)
-                && (enemy_pos.x + enemy_half.x > obstacle_pos.x - obstacle_half.x)
-                && (enemy_pos.y - enemy_half.y < obstacle_pos.y + obstacle_half.y)
-                && (enemy_pos.y + enemy_half.y > obstacle_pos.y - obstacle_half.y);
-            if collision {
-                // Invert the horizontal velocity if a collision is detected.
-                enemy_velocity.x = -enemy_velocity.x;
-            }
+    for (mut hint, mut background, children, root) in hint_query.iter_mut() {
+        let time_up = hint.shown.tick(time.delta()).finished();
+        let learned = progress.moved && progress.jumped;
+        if !(time_up || learned) {
+            continue;
+        }
+        if hint.fade.tick(time.delta()).finished() {
+            commands.entity(root.get()).despawn_recursive();
+            continue;
+        }
+        let alpha = hint.fade.percent_left();
+        background.0.set_a(0.6 * alpha);
+        let mut texts = text_query.iter_many_mut(children);
+        while let Some(mut text) = texts.fetch_next() {
+            text.sections[0].style.color.set_a(alpha);
+        }
+    }
+}
+
+/// Shows how many enemies are left. When the last one falls in a level won
+/// by clearing them, shows "CLEAR!" in green while `AllClearTimer` runs.
+/// Modes with waves can't be cleared, so they show the wave number instead.
+fn update_enemy_count_system(
+    time: Res<Time>,
+    win_condition: Res<WinCondition>,
+    game_mode: Res<GameMode>,
+    wave_spawner: Res<WaveSpawner>,
+    mut all_clear: ResMut<AllClearTimer>,
+    enemy_query: Query<(), With<Enemy>>,
+    mut text_query: Query<&mut Text, With<EnemyCountText>>,
+) {
+    let count = enemy_query.iter().count();
+    let waves = game_mode.sends_waves();
+    let cleared = count == 0 && *win_condition == WinCondition::ClearEnemies && !waves;
+    if cleared {
+        all_clear.0.tick(time.delta());
+    }
+    for mut text in text_query.iter_mut() {
+        let section = &mut text.sections[0];
+        if cleared {
+            section.value = "CLEAR!".to_string();
+            section.style.color = Color::GREEN;
+        } else {
+            section.value = if waves {
+                format!("Wave {}   Enemies: {}", wave_spawner.wave, count)
+            } else {
+                format!("Enemies: {}", count)
+            };
+            section.style.color = Color::WHITE;
         }
     }
 }
 
+/// Formats a number of seconds as minutes and seconds, like "2:05".
+fn format_clock(seconds: f32) -> String {
+    let seconds = seconds as u32;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
 
-/// Handles collisions between the player and obstacles.
-fn obstacle_collision_system(
-    mut param_set: ParamSet<(
-        Query<(&mut Transform, &mut Velocity, &Sprite), With<Player>>,
-        Query<&Transform, With<Obstacle>>,
-    )>,
+/// Formats a race time down to hundredths of a second, like "0:42.37".
+fn format_run_time(seconds: f32) -> String {
+    format_split_time((seconds * 100.0).round() as u32, 2)
+}
+
+/// Formats a number of tenths of a second like "1:05.3".
+fn format_tenths(tenths: u32) -> String {
+    format_split_time(tenths, 1)
+}
+
+/// Formats a time counted in units of `10^-digits` seconds as minutes,
+/// seconds and `digits` decimals, like "1:05.3", adding hours once it
+/// passes an hour, like "1:02:05.3".
+fn format_split_time(units: u32, digits: u32) -> String {
+    let per_second = 10u32.pow(digits);
+    let seconds = units / per_second;
+    let (hours, minutes) = (seconds / 3600, seconds / 60 % 60);
+    let rest = format!(
+        "{:02}.{:0width$}",
+        seconds % 60,
+        units % per_second,
+        width = digits as usize
+    );
+    if hours > 0 {
+        format!("{}:{:02}:{}", hours, minutes, rest)
+    } else {
+        format!("{}:{}", minutes, rest)
+    }
+}
+
+/// Shows how long the level has been played. It only runs while playing,
+/// so the clock holds still while paused and stops on the win or game-over
+/// screen. Endless and time attack already show their own clock above it.
+fn update_run_timer_text_system(
+    level_timer: Res<LevelTimer>,
+    game_mode: Res<GameMode>,
+    mut query: Query<(&mut Text, &mut RunTimerText)>,
 ) {
-    let obstacles: Vec<Vec3> = param_set.p1().iter().map(|t| t.translation).collect();
-
-    for (mut player_transform, mut player_velocity, player_sprite) in param_set.p0().iter_mut() {
-        let player_half = player_sprite.custom_size.unwrap_or(PLAYER_SIZE) / 2.0;
-        for &obstacle_pos in &obstacles {
-            let obstacle_half = OBSTACLE_SIZE / 2.0;
-            if is_colliding(player_transform.translation, player_half, obstacle_pos, obstacle_half) {
-                // Prevent horizontal overlap.
-                if player_transform.translation.x < obstacle_pos.x {
-                    player_transform.translation.x =
-                        obstacle_pos.x - obstacle_half.x - player_half.x;
-                } else {
-                    player_transform.translation.x =
-                        obstacle_pos.x + obstacle_half.x + player_half.x;
-                }
-                player_velocity.x = 0.0;
-                // Adjust vertical position if needed.
-                if player_transform.translation.y > obstacle_pos.y {
-                    player_transform.translation.y =
-                        obstacle_pos.y + obstacle_half.y + player_half.y;
-                }
-            }
+    let shows_clock = matches!(*game_mode, GameMode::Story | GameMode::Survival);
+    let tenths = (level_timer.elapsed.elapsed_secs() * 10.0) as u32;
+    for (mut text, mut run_timer) in query.iter_mut() {
+        if !shows_clock || run_timer.shown == Some(tenths) {
+            continue;
         }
+        run_timer.shown = Some(tenths);
+        text.sections[0].value = format_tenths(tenths);
     }
 }
 
-/// Updates the UI score text when the score changes.
-fn update_score_system(score: Res<Score>, mut query: Query<&mut Text, With<ScoreText>>) {
-    if score.is_changed() {
-        for mut text in query.iter_mut() {
-            text.sections[0].value = format!("Score: {}", score.0);
+/// Shows the seconds left in timed mode, turning yellow then red as they run
+/// out. In endless mode it counts up the time survived instead, and in time
+/// attack the run time beside the level's best.
+fn update_timer_text_system(
+    level_timer: Res<LevelTimer>,
+    time_attack: Res<TimeAttackTimer>,
+    game_mode: Res<GameMode>,
+    high_scores: Res<HighScores>,
+    current_level: Res<CurrentLevel>,
+    mut query: Query<&mut Text, With<TimerText>>,
+) {
+    match *game_mode {
+        GameMode::Endless => {
+            for mut text in query.iter_mut() {
+                text.sections[0].value = format_clock(level_timer.elapsed.elapsed_secs());
+            }
+            return;
+        }
+        GameMode::TimeAttack => {
+            let best = high_scores
+                .best_time
+                .get(&current_level.0)
+                .map_or("--".to_string(), |&best| format_run_time(best));
+            for mut text in query.iter_mut() {
+                text.sections[0].value = format!(
+                    "{}   Best {}",
+                    format_run_time(time_attack.0.elapsed_secs()),
+                    best
+                );
+            }
+            return;
         }
+        GameMode::Story | GameMode::Survival => {}
+    }
+    let Some(remaining) = &level_timer.remaining else {
+        return;
+    };
+    let fraction_left = 1.0 - remaining.percent();
+    let color = if fraction_left > TIMER_WARNING_FRACTION {
+        Color::WHITE
+    } else if fraction_left > TIMER_DANGER_FRACTION {
+        Color::YELLOW
+    } else {
+        Color::RED
+    };
+    for mut text in query.iter_mut() {
+        text.sections[0].value = format!("{:.0}", remaining.remaining_secs().ceil());
+        text.sections[0].style.color = color;
     }
 }
 
-/// Ends the game when either all enemies are defeated or the player is gone.
-fn check_end_game_system(
-    enemy_query: Query<Entity, With<Enemy>>,
-    player_query: Query<Entity, With<Player>>,
-    asset_server: Res<AssetServer>,
+/// Puts up a "Wave N" banner for each new wave, and fades banners out.
+fn banner_system(
     mut commands: Commands,
-    mut exit: EventWriter<AppExit>,
+    time: Res<Time>,
+    asset_server: Res<AssetServer>,
+    mut waves: EventReader<WaveAnnouncement>,
+    mut banner_query: Query<(Entity, &mut Banner, &Children)>,
+    mut text_query: Query<&mut Text>,
 ) {
-    if enemy_query.is_empty() {
-        // Spawn a win title if no enemies remain.
-        commands.spawn(TextBundle {
-            text: Text::from_section(
-                "You Win!",
-                TextStyle {
-                    font: asset_server.load("fonts/FiraSans-Bold.ttf"),
-                    font_size: 80.0,
-                    color: Color::GREEN,
+    for (entity, mut banner, children) in banner_query.iter_mut() {
+        if banner.0.tick(time.delta()).finished() {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        }
+        let mut texts = text_query.iter_many_mut(children);
+        while let Some(mut text) = texts.fetch_next() {
+            text.sections[0].style.color.set_a(1.0 - banner.0.percent());
+        }
+    }
+    let Some(announcement) = waves.read().last() else {
+        return;
+    };
+    // A new banner replaces any still fading.
+    for (entity, ..) in banner_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    let message = format!("Wave {}", announcement.wave);
+    spawn_banner(&mut commands, &asset_server, &message, Color::ORANGE);
+}
+
+/// Spawns a banner showing `message` across the top of the screen.
+fn spawn_banner(commands: &mut Commands, asset_server: &AssetServer, message: &str, color: Color) {
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    width: Val::Percent(100.0),
+                    top: Val::Px(100.0),
+                    justify_content: JustifyContent::Center,
+                    ..default()
                 },
-            ),
-            style: Style {
-                position_type: PositionType::Absolute,
-                top: Val::Percent(40.0),
-                left: Val::Percent(35.0),
                 ..default()
             },
-            ..default()
-        });
-        exit.send(AppExit);
-    } else if player_query.is_empty() {
-        // Spawn a game over title if the player is gone.
-        commands.spawn(TextBundle {
-            text: Text::from_section(
-                "Game Over",
+            Banner(Timer::from_seconds(BANNER_DURATION, TimerMode::Once)),
+            GameEntity,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                message,
                 TextStyle {
                     font: asset_server.load("fonts/FiraSans-Bold.ttf"),
-                    font_size: 80.0,
-                    color: Color::RED,
+                    font_size: 70.0,
+                    color,
                 },
-            ),
-            style: Style {
-                position_type: PositionType::Absolute,
-                top: Val::Percent(40.0),
-                left: Val::Percent(35.0),
+            ));
+        });
+}
+
+/// Shows the key icon in the HUD while the player holds the key.
+fn update_key_icon_system(
+    objective: Res<DoorObjective>,
+    mut query: Query<&mut Visibility, With<KeyIcon>>,
+) {
+    if objective.is_changed() {
+        for mut visibility in query.iter_mut() {
+            *visibility = if objective.has_key {
+                Visibility::Inherited
+            } else {
+                Visibility::Hidden
+            };
+        }
+    }
+}
+
+/// Counts up the time spent playing the level and runs down the countdown
+/// in timed mode, ending the game when it reaches zero. The time-attack
+/// clock runs alongside in time attack. Running out the clock in survival
+/// is a win, which `check_end_game_system` picks up.
+fn level_timer_system(
+    time: Res<Time>,
+    game_mode: Res<GameMode>,
+    mut level_timer: ResMut<LevelTimer>,
+    mut time_attack: ResMut<TimeAttackTimer>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    level_timer.elapsed.tick(time.delta());
+    if *game_mode == GameMode::TimeAttack {
+        time_attack.0.tick(time.delta());
+    }
+    if let Some(remaining) = &mut level_timer.remaining {
+        if remaining.tick(time.delta()).just_finished() && *game_mode != GameMode::Survival {
+            next_state.set(GameState::GameOver);
+        }
+    }
+}
+
+/// Drains the hot-streak multiplier back towards 1 while the player idles.
+fn activity_multiplier_system(time: Res<Time>, mut activity: ResMut<ActivityMultiplier>) {
+    if activity.value > 1.0 {
+        activity.value = (activity.value - activity.decay_rate * time.delta_seconds()).max(1.0);
+    }
+}
+
+/// Ends the combo once the window after the last stomp runs out, showing
+/// how long it ran above the player.
+fn combo_decay_system(
+    time: Res<Time>,
+    mut popups: EventWriter<ScorePopup>,
+    mut combo: ResMut<ComboState>,
+    player_query: Query<&Transform, With<Player>>,
+) {
+    if combo.timer.tick(time.delta()).just_finished() {
+        if combo.count > 1 {
+            for player_transform in player_query.iter() {
+                popups.send(ScorePopup {
+                    text: format!("COMBO x{}", combo.count),
+                    position: player_transform.translation + Vec3::new(0.0, PLAYER_SIZE.y, 1.0),
+                    color: Color::ORANGE,
+                });
+            }
+        }
+        combo.count = 0;
+    }
+}
+
+/// Spawns the popups asked for this frame in the HUD font. Each one moves
+/// up past any popup already on its spot, and the oldest popups go once
+/// there are `MAX_FLOATING_TEXTS`. Nothing is shown if the font failed to
+/// load.
+fn spawn_score_popup_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut popups: EventReader<ScorePopup>,
+    floating_query: Query<(Entity, &Transform, &FloatingText)>,
+) {
+    let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+    if asset_server.get_load_state(&font) == Some(LoadState::Failed) {
+        popups.clear();
+        return;
+    }
+    // Oldest first, so the front of the list is what makes way.
+    let mut existing: Vec<_> = floating_query.iter().collect();
+    existing.sort_by_key(|(_, _, floating_text)| floating_text.timer.remaining());
+    let mut shown: Vec<(Entity, Vec3)> = existing
+        .into_iter()
+        .map(|(entity, transform, _)| (entity, transform.translation))
+        .collect();
+    for popup in popups.read() {
+        let mut position = popup.position;
+        while shown.iter().any(|(_, other)| {
+            (other.x - position.x).abs() < FLOATING_TEXT_SIZE
+                && (other.y - position.y).abs() < FLOATING_TEXT_SIZE
+        }) {
+            position.y += FLOATING_TEXT_SIZE;
+        }
+        if shown.len() >= MAX_FLOATING_TEXTS {
+            let (oldest, _) = shown.remove(0);
+            commands.entity(oldest).despawn();
+        }
+        let entity = spawn_floating_text(
+            &mut commands,
+            &font,
+            popup.text.clone(),
+            position,
+            popup.color,
+        );
+        shown.push((entity, position));
+    }
+}
+
+/// Spawns a score popup at `position` in world space.
+fn spawn_floating_text(
+    commands: &mut Commands,
+    font: &Handle<Font>,
+    text: String,
+    position: Vec3,
+    color: Color,
+) -> Entity {
+    commands
+        .spawn((
+            Text2dBundle {
+                text: Text::from_section(
+                    text,
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: FLOATING_TEXT_SIZE,
+                        color,
+                    },
+                ),
+                transform: Transform::from_translation(position),
                 ..default()
             },
-            ..default()
-        });
-        exit.send(AppExit);
+            FloatingText {
+                timer: Timer::from_seconds(FLOATING_TEXT_DURATION, TimerMode::Once),
+                rise_speed: FLOATING_TEXT_RISE / FLOATING_TEXT_DURATION,
+            },
+            GameEntity,
+        ))
+        .id()
+}
+
+/// Floats score popups upward, fading them out before despawning.
+fn floating_text_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut query: Query<(Entity, &mut Transform, &mut Text, &mut FloatingText)>,
+) {
+    for (entity, mut transform, mut text, mut floating_text) in query.iter_mut() {
+        if floating_text.timer.tick(time.delta()).finished() {
+            commands.entity(entity).despawn();
+            continue;
+        }
+        transform.translation.y += floating_text.rise_speed * time.delta_seconds();
+        let alpha = 1.0 - floating_text.timer.percent();
+        for section in text.sections.iter_mut() {
+            section.style.color.set_a(alpha);
+        }
+    }
+}
+
+/// Adds time spent playing to the lifetime stats.
+fn play_time_system(time: Res<Time>, mut stats: ResMut<PlayerStats>) {
+    stats.total_play_seconds += time.delta_seconds_f64();
+}
+
+/// Ends the game when either the `WinCondition` is met or the player is gone.
+/// Endless mode has no way to win; it only ends with the last life.
+/// Survival is won by lasting until the countdown runs out, and pays a
+/// bonus for every life left.
+fn check_end_game_system(
+    enemy_query: Query<Entity, With<Enemy>>,
+    player_query: Query<(&Transform, &Collider, Has<Player2>), With<Player>>,
+    goal_query: Query<&Transform, With<GoalFlag>>,
+    win_condition: Res<WinCondition>,
+    objective: Res<DoorObjective>,
+    level_timer: Res<LevelTimer>,
+    all_clear: Res<AllClearTimer>,
+    lives: Res<Lives>,
+    mut score: ResMut<Score>,
+    activity: Res<ActivityMultiplier>,
+    difficulty: Res<DifficultyMultipliers>,
+    game_mode: Res<GameMode>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let won = match *win_condition {
+        _ if *game_mode == GameMode::Endless => false,
+        _ if *game_mode == GameMode::Survival => level_timer.timed_out(),
+        WinCondition::ClearEnemies => enemy_query.is_empty() && all_clear.0.finished(),
+        WinCondition::ReachGoal => player_query.iter().any(|(player, collider, _)| {
+            goal_query.iter().any(|goal| {
+                is_colliding(
+                    player.translation,
+                    collider.half_extents,
+                    goal.translation,
+                    GOAL_SIZE / 2.0,
+                )
+            })
+        }),
+        WinCondition::UnlockDoor => objective.opened,
+    };
+    if won {
+        if *game_mode == GameMode::Survival {
+            score.0 += difficulty.points(lives.0 * SURVIVAL_LIFE_BONUS);
+        } else if *win_condition == WinCondition::ReachGoal {
+            // Reward a quick run that kept its lives.
+            let seconds_left = (GOAL_PAR_TIME - level_timer.elapsed.elapsed_secs()).max(0.0);
+            let time_bonus = (seconds_left * GOAL_TIME_BONUS_PER_SECOND) as i32;
+            score.0 += difficulty.points(time_bonus * activity.factor());
+            score.0 += difficulty.points(lives.0 * GOAL_LIFE_BONUS * activity.factor());
+        }
+        if let Some(remaining) = level_timer
+            .remaining
+            .as_ref()
+            .filter(|_| *game_mode == GameMode::Story)
+        {
+            // Time left over multiplies the score, up to double on a full clock.
+            let multiplier = remaining.remaining_secs() / remaining.duration().as_secs_f32() + 1.0;
+            score.0 = (score.0 as f32 * multiplier) as i32;
+        }
+        next_state.set(GameState::Win);
+    } else if !player_query.iter().any(|(.., second)| !second) {
+        // Player 2 can't carry on once player 1 is out of lives.
+        next_state.set(GameState::GameOver);
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::time::TimeUpdateStrategy;
+
+    /// A flat level three windows wide, as the random generator builds it.
+    fn flat_level() -> (LevelBounds, GroundData) {
+        let bounds = LevelBounds {
+            min_x: -1200.0,
+            max_x: 1200.0,
+        };
+        let ground_data = GroundData {
+            center_y: -290.0,
+            top_y: -280.0,
+            height: 20.0,
+            segments: vec![(bounds.min_x, bounds.max_x)],
+        };
+        (bounds, ground_data)
+    }
+
+    /// Places obstacles and then enemies the way `spawn_obstacles` and
+    /// `spawn_enemies` do, drawing from `rng`.
+    fn generate_layout(rng: &mut GameRng) -> (Vec<f32>, Vec<Vec3>) {
+        let (bounds, ground_data) = flat_level();
+        let xs = generate_obstacle_xs(&mut rng.rng, 12, &bounds, &ground_data, 0.0);
+        let obstacle_y = ground_data.top_y + OBSTACLE_SIZE.y / 2.0;
+        let obstacles: Vec<(Vec3, Vec2)> = xs
+            .iter()
+            .map(|&x| (Vec3::new(x, obstacle_y, 0.0), OBSTACLE_SIZE / 2.0))
+            .collect();
+        let enemies = (0..8)
+            .filter_map(|_| {
+                pick_enemy_spot(
+                    &mut rng.rng,
+                    &bounds,
+                    &ground_data,
+                    &[],
+                    &obstacles,
+                    Vec3::ZERO,
+                )
+            })
+            .map(|(pos, _)| pos)
+            .collect();
+        (xs, enemies)
+    }
+
+    #[test]
+    fn same_seed_builds_same_layout() {
+        let mut first = GameRng::new(1234);
+        let mut second = GameRng::new(1234);
+        first.reseed(3);
+        second.reseed(3);
+        let layout = generate_layout(&mut first);
+        assert!(!layout.0.is_empty() && !layout.1.is_empty());
+        assert_eq!(layout, generate_layout(&mut second));
+
+        let mut other = GameRng::new(4321);
+        other.reseed(3);
+        assert_ne!(layout, generate_layout(&mut other));
+    }
+
+    #[test]
+    fn placement_never_overlaps() {
+        for seed in 0..100 {
+            let mut rng = GameRng::new(seed);
+            let (xs, enemies) = generate_layout(&mut rng);
+            for (i, &x) in xs.iter().enumerate() {
+                assert!(
+                    x.abs() >= OBSTACLE_SPAWN_CLEARANCE,
+                    "seed {seed}: obstacle on spawn"
+                );
+                for &other in &xs[i + 1..] {
+                    assert!(
+                        (x - other).abs() >= OBSTACLE_SIZE.x * 1.5,
+                        "seed {seed}: obstacles at {x} and {other} overlap"
+                    );
+                }
+            }
+            let (_, ground_data) = flat_level();
+            let obstacle_y = ground_data.top_y + OBSTACLE_SIZE.y / 2.0;
+            for enemy in enemies {
+                assert!(
+                    enemy.length() >= ENEMY_SPAWN_CLEARANCE,
+                    "seed {seed}: enemy on spawn"
+                );
+                for &x in &xs {
+                    assert!(
+                        !is_colliding(
+                            enemy,
+                            ENEMY_SIZE / 2.0,
+                            Vec3::new(x, obstacle_y, 0.0),
+                            OBSTACLE_SIZE / 2.0
+                        ),
+                        "seed {seed}: enemy at {enemy} inside obstacle at {x}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fast_move_stops_at_obstacle() {
+        let half = PLAYER_SIZE / 2.0;
+        let solids = [SolidBox {
+            center: Vec3::new(200.0, 0.0, 0.0),
+            half: OBSTACLE_SIZE / 2.0,
+            one_way: false,
+        }];
+        let obstacle = &solids[0];
+        let end = sweep_move(Vec3::ZERO, Vec2::new(500.0, 0.0), half, &solids);
+        let face = obstacle.center.x - obstacle.half.x;
+        // The sweep stops in the substep that first touches the obstacle,
+        // and the collision pass pushes the player back out to its face.
+        assert!(end.x + half.x > face && end.x + half.x <= face + SUBSTEP_LENGTH);
+        let mut transform = Transform::from_translation(end);
+        let mut velocity = Velocity(Vec2::new(500.0, 0.0));
+        let contact = push_out_of_box(
+            &mut transform,
+            &mut velocity,
+            half,
+            obstacle.center,
+            obstacle.half,
+        );
+        assert_eq!(contact, Some(ContactFace::Side));
+        assert_eq!(transform.translation.x + half.x, face);
+        assert_eq!(velocity.x, 0.0);
+    }
+
+    /// The kind of every `CollisionEvent` sent when the player touches an
+    /// enemy of `enemy_size` with its center at `player_pos` and the enemy at
+    /// the origin.
+    fn enemy_contact_kinds(player_pos: Vec3, enemy_size: Vec2) -> Vec<CollisionKind> {
+        let mut app = App::new();
+        app.add_event::<CollisionEvent>()
+            .init_resource::<SpatialGrid>()
+            .add_systems(
+                Update,
+                (rebuild_spatial_grid_system, collision_detection_system).chain(),
+            );
+        app.world.spawn((
+            Player,
+            Transform::from_translation(player_pos),
+            Collider::from_size(PLAYER_SIZE),
+        ));
+        app.world
+            .spawn((Enemy, Transform::default(), Collider::from_size(enemy_size)));
+        app.update();
+        let events = app.world.resource::<Events<CollisionEvent>>();
+        events
+            .get_reader()
+            .read(events)
+            .map(|event| event.kind)
+            .collect()
+    }
+
+    #[test]
+    fn stomp_and_side_hit_send_different_kinds() {
+        let touching = (PLAYER_SIZE + ENEMY_SIZE) / 2.0 - Vec2::splat(2.0);
+        assert_eq!(
+            enemy_contact_kinds(Vec3::new(0.0, touching.y, 0.0), ENEMY_SIZE),
+            vec![CollisionKind::Stomp]
+        );
+        assert_eq!(
+            enemy_contact_kinds(Vec3::new(touching.x, 0.0, 0.0), ENEMY_SIZE),
+            vec![CollisionKind::SideHit]
+        );
+        assert_eq!(
+            enemy_contact_kinds(Vec3::new(0.0, -touching.y, 0.0), ENEMY_SIZE),
+            vec![CollisionKind::SideHit]
+        );
+    }
+
+    #[test]
+    fn grid_query_matches_linear_scan() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let boxes: Vec<(Entity, Vec2, Vec2)> = (0..300)
+            .map(|i| {
+                let center =
+                    Vec2::new(rng.gen_range(-1500.0..1500.0), rng.gen_range(-400.0..400.0));
+                let half = Vec2::new(rng.gen_range(5.0..60.0), rng.gen_range(5.0..60.0));
+                (Entity::from_raw(i), center, half)
+            })
+            .collect();
+        let mut grid = SpatialGrid::default();
+        for &(entity, center, half) in &boxes {
+            grid.insert(entity, center, half);
+        }
+        let overlapping = |center: Vec2, half: Vec2, other: &(Entity, Vec2, Vec2)| {
+            is_colliding(center.extend(0.0), half, other.1.extend(0.0), other.2)
+        };
+        for _ in 0..200 {
+            let center = Vec2::new(rng.gen_range(-1500.0..1500.0), rng.gen_range(-400.0..400.0));
+            let half = Vec2::new(rng.gen_range(5.0..100.0), rng.gen_range(5.0..100.0));
+            let mut from_grid: Vec<Entity> = grid
+                .query_region(center, half)
+                .filter(|&entity| overlapping(center, half, &boxes[entity.index() as usize]))
+                .collect();
+            from_grid.sort();
+            let brute_force: Vec<Entity> = boxes
+                .iter()
+                .filter(|other| overlapping(center, half, other))
+                .map(|&(entity, ..)| entity)
+                .collect();
+            assert_eq!(from_grid, brute_force);
+        }
+    }
+
+    /// Runs `one_way_platform_system` once for a player that moved from
+    /// `prev` to `now` this frame, over a platform whose top is at y = 0,
+    /// and returns where it ends up and its vertical velocity.
+    fn settle_on_one_way(prev: Vec3, now: Vec3, velocity_y: f32) -> (Vec3, f32) {
+        let mut app = App::new();
+        app.add_systems(Update, one_way_platform_system);
+        app.world.spawn((
+            OneWayPlatform,
+            Transform::from_xyz(0.0, -PLATFORM_SIZE.y / 2.0, 0.0),
+            Sprite {
+                custom_size: Some(PLATFORM_SIZE),
+                ..default()
+            },
+        ));
+        let player = app
+            .world
+            .spawn((
+                Transform::from_translation(now),
+                Velocity(Vec2::new(0.0, velocity_y)),
+                PrevTranslation(prev),
+                Collider::from_size(PLAYER_SIZE),
+            ))
+            .id();
+        app.update();
+        let player = app.world.entity(player);
+        (
+            player.get::<Transform>().unwrap().translation,
+            player.get::<Velocity>().unwrap().y,
+        )
+    }
+
+    #[test]
+    fn one_way_platform_catches_a_landing() {
+        let half = PLAYER_SIZE.y / 2.0;
+        let (end, velocity_y) = settle_on_one_way(
+            Vec3::new(0.0, half + 4.0, 0.0),
+            Vec3::new(0.0, half - 6.0, 0.0),
+            -300.0,
+        );
+        assert_eq!(end.y, half);
+        assert_eq!(velocity_y, 0.0);
+    }
+
+    #[test]
+    fn one_way_platform_lets_a_jump_through() {
+        let half = PLAYER_SIZE.y / 2.0;
+        // Rising through the platform.
+        let (end, velocity_y) = settle_on_one_way(
+            Vec3::new(0.0, -half - 10.0, 0.0),
+            Vec3::new(0.0, -half, 0.0),
+            300.0,
+        );
+        assert_eq!((end.y, velocity_y), (-half, 300.0));
+        // Past the apex with the feet still below the top: not caught either.
+        let (end, velocity_y) = settle_on_one_way(
+            Vec3::new(0.0, half - 4.0, 0.0),
+            Vec3::new(0.0, half - 5.0, 0.0),
+            -20.0,
+        );
+        assert_eq!((end.y, velocity_y), (half - 5.0, -20.0));
+    }
+
+    #[test]
+    fn one_way_platform_drops_what_walks_off_its_end() {
+        let half = PLAYER_SIZE / 2.0;
+        let beyond_end = PLATFORM_SIZE.x / 2.0 + half.x + 1.0;
+        let (end, velocity_y) = settle_on_one_way(
+            Vec3::new(beyond_end, half.y, 0.0),
+            Vec3::new(beyond_end, half.y - 5.0, 0.0),
+            -100.0,
+        );
+        assert_eq!((end.y, velocity_y), (half.y - 5.0, -100.0));
+    }
+
+    #[test]
+    fn non_square_enemies_collide_with_their_own_box() {
+        // A wide, flat enemy: the player hovers 10px over its top, which a
+        // box as tall as the enemy is wide would count as touching.
+        let flat = Vec2::new(80.0, 10.0);
+        let above = Vec3::new(0.0, flat.y / 2.0 + PLAYER_SIZE.y / 2.0 + 10.0, 0.0);
+        assert!(enemy_contact_kinds(above, flat).is_empty());
+        // A tall, thin enemy: the player runs into its upper half, well above
+        // where a box as tall as the enemy is wide would end.
+        let tall = Vec2::new(10.0, 80.0);
+        let beside = Vec3::new(tall.x / 2.0 + PLAYER_SIZE.x / 2.0 - 2.0, 25.0, 0.0);
+        assert_eq!(
+            enemy_contact_kinds(beside, tall),
+            vec![CollisionKind::SideHit]
+        );
+    }
+
+    /// The highest the player has been after any physics step.
+    #[derive(Resource, Default)]
+    struct Apex(f32);
+
+    fn record_apex_system(mut apex: ResMut<Apex>, query: Query<&Transform, With<Player>>) {
+        for transform in query.iter() {
+            apex.0 = apex.0.max(transform.translation.y);
+        }
+    }
+
+    /// A headless app that runs gravity and movement at the game's physics
+    /// rate over open air, with every frame lasting `frame`, and a player
+    /// at the origin moving at `velocity`.
+    fn physics_app(frame: Duration, velocity: Vec2) -> App {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .insert_resource(TimeUpdateStrategy::ManualDuration(frame))
+            .insert_resource(Time::<Fixed>::from_hz(PHYSICS_HZ))
+            .insert_resource(Gravity(GRAVITY_FORCE))
+            .insert_resource(GroundData {
+                center_y: 0.0,
+                top_y: 0.0,
+                height: 0.0,
+                segments: Vec::new(),
+            })
+            .init_resource::<Apex>()
+            .add_systems(
+                FixedUpdate,
+                (apply_gravity_system, movement_system, record_apex_system).chain(),
+            );
+        app.world.spawn((
+            Player,
+            Transform::default(),
+            Velocity(velocity),
+            GravityScale(1.0),
+            Collider::from_size(PLAYER_SIZE),
+        ));
+        app
+    }
+
+    /// How high a jump goes when the game renders at `fps`.
+    fn jump_apex(fps: u32) -> f32 {
+        let mut app = physics_app(
+            Duration::from_secs_f64(1.0 / fps as f64),
+            Vec2::new(0.0, PLAYER_JUMP_VELOCITY),
+        );
+        // Long enough to rise to the top and come back down.
+        for _ in 0..fps {
+            app.update();
+        }
+        app.world.resource::<Apex>().0
+    }
+
+    #[test]
+    fn jump_apex_does_not_depend_on_frame_rate() {
+        let apex = jump_apex(30);
+        assert!(apex > 0.0);
+        assert_eq!(apex, jump_apex(240));
+    }
+
+    #[test]
+    fn slope_surface_interpolates_between_its_ends() {
+        for slope in [
+            Slope {
+                left_y: 10.0,
+                right_y: 50.0,
+            },
+            Slope {
+                left_y: 50.0,
+                right_y: 10.0,
+            },
+        ] {
+            assert_eq!(slope.height_at(0.0), slope.left_y);
+            assert_eq!(slope.height_at(1.0), slope.right_y);
+            assert_eq!(slope.height_at(0.5), 30.0);
+            assert_eq!(
+                slope.height_at(0.25),
+                slope.left_y + (slope.right_y - slope.left_y) / 4.0
+            );
+            // Past either end the surface stays level with that end.
+            assert_eq!(slope.height_at(-0.5), slope.left_y);
+            assert_eq!(slope.height_at(1.5), slope.right_y);
+        }
+    }
+
+    /// Where `slope_system` leaves a player that starts at `position`, over
+    /// a slope rising from y = 0 at x = -100 to y = 40 at x = 100.
+    fn settle_on_slope(position: Vec3) -> Vec3 {
+        let mut app = App::new();
+        app.init_resource::<Time>()
+            .add_systems(Update, slope_system);
+        app.world.spawn((
+            Slope {
+                left_y: 0.0,
+                right_y: 40.0,
+            },
+            Transform::default(),
+            Collider::from_size(Vec2::new(200.0, 40.0)),
+        ));
+        let player = app
+            .world
+            .spawn((
+                Player,
+                Transform::from_translation(position),
+                Velocity(Vec2::ZERO),
+                Collider::from_size(PLAYER_SIZE),
+            ))
+            .id();
+        app.update();
+        app.world.get::<Transform>(player).unwrap().translation
+    }
+
+    #[test]
+    fn slope_carries_only_what_is_over_it() {
+        let half = PLAYER_SIZE / 2.0;
+        // Feet a little off the midpoint's surface are snapped onto it.
+        assert_eq!(
+            settle_on_slope(Vec3::new(0.0, 20.0 + half.y + 1.0, 0.0)),
+            Vec3::new(0.0, 20.0 + half.y, 0.0)
+        );
+        // Clear of either end the slope leaves the player alone.
+        for x in [-100.0 - half.x - 1.0, 100.0 + half.x + 1.0] {
+            let position = Vec3::new(x, half.y, 0.0);
+            assert_eq!(settle_on_slope(position), position);
+        }
+    }
+
+    #[test]
+    fn velocity_limits_cap_clamped_entities_only() {
+        let mut app = App::new();
+        app.init_resource::<VelocityLimits>()
+            .add_systems(Update, clamp_velocity_system);
+        let limits = VelocityLimits::default();
+        // A dash on a conveyor, a spring launch, a long fall, and a spring
+        // launch on something that opts out.
+        let dashing = app.world.spawn(Velocity(Vec2::new(-2000.0, 0.0))).id();
+        let launched = app
+            .world
+            .spawn(Velocity(Vec2::new(DASH_SPEED, 3000.0)))
+            .id();
+        let falling = app.world.spawn(Velocity(Vec2::new(0.0, -5000.0))).id();
+        let unclamped = app
+            .world
+            .spawn((Velocity(Vec2::new(2000.0, 3000.0)), Unclamped))
+            .id();
+        app.update();
+        let velocity = |entity| app.world.get::<Velocity>(entity).unwrap().0;
+        assert_eq!(velocity(dashing), Vec2::new(-limits.max_horizontal, 0.0));
+        assert_eq!(velocity(launched), Vec2::new(DASH_SPEED, limits.max_rise));
+        assert_eq!(velocity(falling), Vec2::new(0.0, -limits.max_fall));
+        assert_eq!(velocity(unclamped), Vec2::new(2000.0, 3000.0));
+    }
+
+    /// A 40px box resting on a long floor whose top is at y = 0.
+    fn ray_targets() -> [RayTarget; 2] {
+        [
+            RayTarget {
+                entity: None,
+                center: Vec2::new(0.0, -50.0),
+                half: Vec2::new(500.0, 50.0),
+            },
+            RayTarget {
+                entity: Some(Entity::from_raw(1)),
+                center: Vec2::new(100.0, 20.0),
+                half: Vec2::splat(20.0),
+            },
+        ]
+    }
+
+    #[test]
+    fn raycast_hits_the_nearest_target() {
+        let targets = ray_targets();
+        let hit = raycast(Vec2::new(100.0, 100.0), Vec2::NEG_Y, 200.0, &targets).unwrap();
+        assert_eq!(hit.entity, Some(Entity::from_raw(1)));
+        assert_eq!((hit.point, hit.distance), (Vec2::new(100.0, 40.0), 60.0));
+        let hit = raycast(Vec2::new(0.0, 100.0), Vec2::NEG_Y, 200.0, &targets).unwrap();
+        assert_eq!(hit.entity, None);
+        assert_eq!((hit.point, hit.distance), (Vec2::ZERO, 100.0));
+        // Sideways into the box's left face.
+        let hit = raycast(Vec2::new(0.0, 20.0), Vec2::X, 200.0, &targets).unwrap();
+        assert_eq!((hit.point, hit.distance), (Vec2::new(80.0, 20.0), 80.0));
+    }
+
+    #[test]
+    fn raycast_misses_what_is_out_of_reach() {
+        let targets = ray_targets();
+        // Too short to reach the floor.
+        assert!(raycast(Vec2::new(0.0, 100.0), Vec2::NEG_Y, 99.0, &targets).is_none());
+        // Pointing away from everything.
+        assert!(raycast(Vec2::new(0.0, 100.0), Vec2::Y, 500.0, &targets).is_none());
+        // Above the box's top, running past it.
+        assert!(raycast(Vec2::new(0.0, 41.0), Vec2::X, 500.0, &targets).is_none());
+        // Starting inside the box ignores it and finds the floor.
+        let hit = raycast(Vec2::new(100.0, 20.0), Vec2::NEG_Y, 100.0, &targets).unwrap();
+        assert_eq!(hit.entity, None);
+    }
+
+    #[test]
+    fn raycast_counts_grazing_an_edge() {
+        let targets = ray_targets();
+        // Straight down the box's right side.
+        let hit = raycast(Vec2::new(120.0, 100.0), Vec2::NEG_Y, 200.0, &targets).unwrap();
+        assert_eq!(hit.entity, Some(Entity::from_raw(1)));
+        assert_eq!(hit.point, Vec2::new(120.0, 40.0));
+        // Along the top face.
+        let hit = raycast(Vec2::new(0.0, 40.0), Vec2::X, 200.0, &targets).unwrap();
+        assert_eq!(hit.point, Vec2::new(80.0, 40.0));
+    }
+
+    #[test]
+    fn enemy_spawned_in_obstacle_escapes_and_settles() {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f64(
+                1.0 / 60.0,
+            )))
+            .insert_resource(GroundData {
+                center_y: 0.0,
+                top_y: 0.0,
+                height: 0.0,
+                segments: Vec::new(),
+            })
+            .init_resource::<SpatialGrid>()
+            .add_systems(
+                Update,
+                (
+                    movement_system,
+                    rebuild_spatial_grid_system,
+                    enemy_obstacle_collision_system,
+                )
+                    .chain(),
+            );
+        app.world.spawn((
+            Obstacle,
+            Transform::default(),
+            Collider::from_size(OBSTACLE_SIZE),
+        ));
+        let enemy = app
+            .world
+            .spawn((
+                Enemy,
+                Transform::default(),
+                Velocity(Vec2::new(-80.0, 0.0)),
+                Collider::from_size(ENEMY_SIZE),
+            ))
+            .id();
+
+        let mut directions = Vec::new();
+        for _ in 0..10 {
+            app.update();
+            directions.push(app.world.get::<Velocity>(enemy).unwrap().x.signum());
+        }
+        let position = app.world.get::<Transform>(enemy).unwrap().translation;
+        assert!(!is_colliding(
+            position,
+            ENEMY_SIZE / 2.0,
+            Vec3::ZERO,
+            OBSTACLE_SIZE / 2.0
+        ));
+        // Turned around once on the way out, then kept walking away.
+        assert!(directions
+            .iter()
+            .all(|&direction| direction == directions[0]));
+    }
+
+    #[test]
+    fn long_frame_moves_at_most_max_delta() {
+        let mut app = physics_app(
+            Duration::from_secs_f64(1.0 / 60.0),
+            Vec2::new(PLAYER_SPEED, 0.0),
+        );
+        app.init_resource::<MaxDelta>()
+            .add_systems(Update, apply_max_delta_system);
+        // One ordinary frame puts the cap on the clock.
+        app.update();
+        let player = app
+            .world
+            .query_filtered::<Entity, With<Player>>()
+            .single(&app.world);
+        let start = app.world.get::<Transform>(player).unwrap().translation.x;
+
+        app.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs(5)));
+        app.update();
+        let moved = app.world.get::<Transform>(player).unwrap().translation.x - start;
+        let max_delta = app.world.resource::<MaxDelta>().0;
+        assert!(moved > 0.0);
+        assert!(moved <= 2.0 * max_delta * PLAYER_SPEED);
+    }
+
+    #[test]
+    fn view_centers_on_a_level_narrower_than_it() {
+        for target in [-500.0, 0.0, 100.0, 500.0] {
+            assert_eq!(clamp_view_center(target, 0.0, 600.0, 400.0), 300.0);
+        }
+    }
+
+    #[test]
+    fn view_centers_on_a_level_exactly_its_size() {
+        for target in [-500.0, 0.0, 400.0, 500.0] {
+            assert_eq!(clamp_view_center(target, -200.0, 600.0, 400.0), 200.0);
+        }
+    }
+
+    #[test]
+    fn view_follows_inside_a_wider_level() {
+        // The view can centre anywhere from -600 to 600 without showing
+        // past the level's edges.
+        assert_eq!(clamp_view_center(150.0, -1000.0, 1000.0, 400.0), 150.0);
+        assert_eq!(clamp_view_center(-900.0, -1000.0, 1000.0, 400.0), -600.0);
+        assert_eq!(clamp_view_center(2000.0, -1000.0, 1000.0, 400.0), 600.0);
+    }
+
+    #[test]
+    fn times_format_with_their_precision() {
+        assert_eq!(format_tenths(0), "0:00.0");
+        assert_eq!(format_tenths(653), "1:05.3");
+        assert_eq!(format_tenths(37253), "1:02:05.3");
+        assert_eq!(format_run_time(42.375), "0:42.38");
+        assert_eq!(format_run_time(65.05), "1:05.05");
+    }
+
+    #[test]
+    fn example_level_files_parse_with_layouts() {
+        for (name, contents) in [
+            ("level1.ron", include_str!("assets/levels/level1.ron")),
+            ("level2.ron", include_str!("assets/levels/level2.ron")),
+        ] {
+            let config: LevelConfig = ron::from_str(contents)
+                .unwrap_or_else(|err| panic!("{} doesn't parse: {}", name, err));
+            assert!(config.layout.is_some(), "{} has no layout", name);
+        }
+    }
+
+    #[test]
+    fn default_bindings_accept_either_key() {
+        let bindings = KeyBindings::default();
+        for (pair, keys) in [
+            (bindings.left, [KeyCode::Left, KeyCode::A]),
+            (bindings.right, [KeyCode::Right, KeyCode::D]),
+            (bindings.jump, [KeyCode::Space, KeyCode::Key2]),
+            (bindings.dash, [KeyCode::ShiftLeft, KeyCode::ShiftRight]),
+        ] {
+            for key in keys {
+                let mut input = Input::<KeyCode>::default();
+                input.press(key);
+                assert!(pair.pressed(&input), "{:?} doesn't trigger {:?}", key, pair);
+                assert!(
+                    pair.just_pressed(&input),
+                    "{:?} doesn't trigger {:?}",
+                    key,
+                    pair
+                );
+            }
+        }
+        let mut input = Input::<KeyCode>::default();
+        input.press(KeyCode::A);
+        assert!(!bindings.right.pressed(&input));
+    }
+
+    #[test]
+    fn controls_card_names_both_movement_pairs() {
+        let mut bindings = KeyBindings::default();
+        assert_eq!(
+            controls_hint_text(&bindings),
+            "A/D or ←/→ to move, Space to jump"
+        );
+        bindings.right.secondary = None;
+        assert_eq!(controls_hint_text(&bindings), "←/→ to move, Space to jump");
+    }
+}